@@ -4,9 +4,9 @@ use criterion::async_executor::FuturesExecutor;
 #[cfg(not(target_arch = "wasm32"))]
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
-use colorcrunch::{
+use colorcruncher::{
     kmeans::{KMeans, KMeansAlgorithm},
-    types::{Vec3, Vec4, Vec4u},
+    types::{Vec3, Vec4u},
 };
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
@@ -42,7 +42,7 @@ fn generate_random_pixels_vec4u(count: usize, seed: u64) -> Vec<Vec4u> {
 
 #[cfg(not(target_arch = "wasm32"))]
 fn benchmark_kmeans_comparison(c: &mut Criterion) {
-    use colorcrunch::kmeans::KMeansConfig;
+    use colorcruncher::kmeans::KMeansConfig;
     use futures::executor::block_on;
 
     let k_values = [2, 4, 8, 16];
@@ -103,7 +103,7 @@ fn benchmark_kmeans_comparison(c: &mut Criterion) {
 
 #[cfg(not(target_arch = "wasm32"))]
 fn benchmark_euclidean_distance(c: &mut Criterion) {
-    use colorcrunch::kmeans;
+    use colorcruncher::kmeans;
 
     let mut rng = rand::thread_rng();
     let a: Vec3 = [rng.gen(), rng.gen(), rng.gen()];
@@ -119,7 +119,7 @@ fn benchmark_euclidean_distance(c: &mut Criterion) {
 
 #[cfg(not(target_arch = "wasm32"))]
 fn benchmark_find_closest_centroid(c: &mut Criterion) {
-    use colorcrunch::kmeans;
+    use colorcruncher::kmeans;
     let mut rng = rand::thread_rng();
     let pixel = [rng.gen(), rng.gen(), rng.gen()];
     let centroids: Vec<Vec3> = (0..100)
@@ -131,10 +131,75 @@ fn benchmark_find_closest_centroid(c: &mut Criterion) {
     });
 }
 
+// A smooth RGBA gradient, so the quality numbers mean something
+#[cfg(not(target_arch = "wasm32"))]
+fn generate_gradient_image(width: usize, height: usize) -> Vec<u8> {
+    (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            [
+                (x * 255 / width) as u8,
+                (y * 255 / height) as u8,
+                ((x + y) * 255 / (width + height)) as u8,
+                255,
+            ]
+        })
+        .collect()
+}
+
+// Times quantize_image and prints how close each result is to the original
+#[cfg(not(target_arch = "wasm32"))]
+fn benchmark_quantize_quality(c: &mut Criterion) {
+    use colorcruncher::metrics;
+    use colorcruncher::quantize::ColorCruncherBuilder;
+    use futures::executor::block_on;
+
+    let (width, height) = (256, 256);
+    let image = generate_gradient_image(width, height);
+    let algorithms = vec![
+        KMeansAlgorithm::Lloyd,
+        KMeansAlgorithm::Hamerly,
+        #[cfg(feature = "gpu")]
+        KMeansAlgorithm::LloydGpu,
+    ];
+
+    for k in [4, 16] {
+        let mut group = c.benchmark_group(format!("quantize_image_k_{}", k));
+
+        for algorithm in &algorithms {
+            let cruncher = block_on(
+                ColorCruncherBuilder::new()
+                    .with_max_colors(k)
+                    .with_channels(4)
+                    .with_width(width)
+                    .with_algorithm(algorithm.clone())
+                    .with_seed(42)
+                    .build(),
+            )
+            .unwrap();
+
+            let quantized = block_on(cruncher.quantize_image(&image)).unwrap();
+            println!(
+                "quantize_image k={} {}: {}",
+                k,
+                algorithm,
+                metrics::compare(&image, &quantized, 4, width).unwrap()
+            );
+
+            group.bench_function(algorithm.to_string(), |b| {
+                b.iter(|| block_on(cruncher.quantize_image(black_box(&image))))
+            });
+        }
+
+        group.finish();
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 criterion_group!(
     benches,
     benchmark_kmeans_comparison,
+    benchmark_quantize_quality,
     benchmark_euclidean_distance,
     benchmark_find_closest_centroid
 );
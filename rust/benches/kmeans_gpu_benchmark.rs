@@ -1,6 +1,5 @@
-use colorcrunch::kmeans::gpu::{GpuAlgorithm, KMeansGpu};
-use colorcrunch::kmeans::{Initializer, KMeansConfig};
-use colorcrunch::types::Vec4u;
+use colorcruncher::kmeans::{Initializer, KMeans, KMeansAlgorithm, KMeansConfig};
+use colorcruncher::types::Vec4u;
 
 #[cfg(not(target_arch = "wasm32"))]
 use criterion::async_executor::FuturesExecutor;
@@ -11,10 +10,7 @@ use rand::prelude::*;
 
 #[cfg(not(target_arch = "wasm32"))]
 fn benchmark_kmeans_gpu(c: &mut Criterion) {
-    let algorithms = vec![
-        GpuAlgorithm::LloydAssignmentsAndCentroids,
-        GpuAlgorithm::LloydAssignmentsOnly,
-    ];
+    let algorithms = vec![KMeansAlgorithm::LloydGpu];
 
     let mut rng = thread_rng();
     let image_size = 2000;
@@ -36,11 +32,12 @@ fn benchmark_kmeans_gpu(c: &mut Criterion) {
             k: 10,
             max_iterations: 10,
             tolerance: 0.001,
-            algorithm: algorithm.into(),
+            algorithm: algorithm.clone(),
             initializer: Initializer::Random,
             seed: Some(42),
+            fixed_centroids: Vec::new(),
         };
-        let kmeans = block_on(KMeansGpu::new(config));
+        let kmeans = block_on(KMeans::new(config));
 
         group.bench_with_input(
             BenchmarkId::from_parameter(format!("{:?}", algorithm)),
@@ -1,8 +1,8 @@
 // #![cfg(target_arch = "wasm32")]
 
-use colorcrunch::{
+use colorcruncher::{
     kmeans::{KMeans, KMeansAlgorithm, KMeansConfig},
-    types::{Vec3, Vec4, Vec4u},
+    types::{Vec4, Vec4u},
 };
 use futures::executor::block_on;
 use rand::Rng;
@@ -45,8 +45,8 @@ pub extern "C" fn benchmark() -> f64 {
     let warmup_duration = Duration::from_secs(3);
     let mut total_time = 0.0;
     let algorithms = vec![
-        colorcrunch::kmeans::KMeansAlgorithm::Hamerly,
-        colorcrunch::kmeans::KMeansAlgorithm::Lloyd,
+        colorcruncher::kmeans::KMeansAlgorithm::Hamerly,
+        colorcruncher::kmeans::KMeansAlgorithm::Lloyd,
     ];
 
     for &size in &data_sizes {
@@ -61,8 +61,8 @@ pub extern "C" fn benchmark() -> f64 {
                     ..Default::default()
                 }));
 
-                let kmeans_gpu = block_on(KMeans::new_gpu(KMeansConfig {
-                    algorithm: algorithm.clone(),
+                let kmeans_gpu = block_on(KMeans::new(KMeansConfig {
+                    algorithm: KMeansAlgorithm::LloydGpu,
                     k: k as usize,
                     max_iterations: 1000,
                     tolerance: 0.02,
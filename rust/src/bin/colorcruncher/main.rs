@@ -0,0 +1,461 @@
+mod report;
+
+use clap::Parser;
+use colorcruncher::dither::Dithering;
+use colorcruncher::image_io::{self, Image, ImageFormat};
+use colorcruncher::kmeans::{Initializer, KMeansAlgorithm};
+use colorcruncher::metrics;
+use colorcruncher::palette_io::{write_palette, PaletteFormat};
+use colorcruncher::quantize::IndexedImage;
+use colorcruncher::quantize::{ColorCruncherBuilder, Palette};
+use colorcruncher::sampling::SamplingStrategy;
+use futures::executor::block_on;
+use report::{Entry, Failure, Written};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+// Palettes written into a directory get this format
+const DEFAULT_PALETTE_EXTENSION: &str = "gpl";
+
+// Quantizes images in batch and prints a JSON report of what was written. Formats
+// follow the file extensions: png, pgm/ppm/pam/pnm, tga or bmp.
+// Exits with 1 if any input failed and 2 on bad arguments.
+#[derive(Parser, Debug)]
+#[command(
+    name = "colorcruncher",
+    version,
+    about = "Batch color quantization for PNG, Netpbm, TGA and BMP images"
+)]
+struct Args {
+    #[arg(
+        required = true,
+        help = "Image files or glob patterns, e.g. 'sprites/*.png'"
+    )]
+    inputs: Vec<String>,
+
+    #[arg(
+        short,
+        long,
+        help = "Output file, directory, or pattern where * is replaced by the input's file stem. The format follows the extension, and directories keep the input's format [default: <input>-quantized.<ext>]"
+    )]
+    output: Option<String>,
+
+    #[arg(short, long, help = "Maximum number of colors in each palette")]
+    colors: Option<usize>,
+
+    #[arg(long, value_parser = parse_algorithm, help = "lloyd, hamerly or lloyd-gpu")]
+    algorithm: Option<KMeansAlgorithm>,
+
+    #[arg(long, value_parser = parse_initializer, help = "kmeans++ or random")]
+    initializer: Option<Initializer>,
+
+    #[arg(long, help = "Seed for reproducible palettes")]
+    seed: Option<u64>,
+
+    #[arg(long, help = "Stop once no centroid moves more than this")]
+    tolerance: Option<f32>,
+
+    #[arg(long, help = "Only cluster about 1 in n pixels")]
+    sample_rate: Option<usize>,
+
+    #[arg(
+        long,
+        value_parser = parse_sampling,
+        help = "stride, random, jittered, histogram or reservoir:<size>"
+    )]
+    sampling: Option<SamplingStrategy>,
+
+    #[arg(long, value_parser = parse_dithering, help = "none or pattern")]
+    dithering: Option<Dithering>,
+
+    #[arg(
+        long,
+        help = "Also write each palette to a file, directory or * pattern. The format follows the extension: gpl, pal, act, txt, hex or ase"
+    )]
+    palette: Option<String>,
+
+    #[arg(long, help = "Write the JSON report to this file instead of stdout")]
+    report: Option<PathBuf>,
+}
+
+fn parse_algorithm(algorithm: &str) -> Result<KMeansAlgorithm, String> {
+    match algorithm {
+        "lloyd" => Ok(KMeansAlgorithm::Lloyd),
+        "hamerly" => Ok(KMeansAlgorithm::Hamerly),
+        #[cfg(feature = "gpu")]
+        "lloyd-gpu" => Ok(KMeansAlgorithm::LloydGpu),
+        _ => Err(format!("unknown algorithm: {}", algorithm)),
+    }
+}
+
+fn parse_initializer(initializer: &str) -> Result<Initializer, String> {
+    match initializer {
+        "kmeans++" => Ok(Initializer::KMeansPlusPlus),
+        "random" => Ok(Initializer::Random),
+        _ => Err(format!("unknown initializer: {}", initializer)),
+    }
+}
+
+fn parse_sampling(sampling: &str) -> Result<SamplingStrategy, String> {
+    match sampling.split_once(':') {
+        Some(("reservoir", size)) => size
+            .parse()
+            .map(|size| SamplingStrategy::Reservoir { size })
+            .map_err(|_| format!("invalid reservoir size: {}", size)),
+        Some(_) => Err(format!("unknown sampling: {}", sampling)),
+        None => match sampling {
+            "stride" => Ok(SamplingStrategy::Stride),
+            "random" => Ok(SamplingStrategy::Random),
+            "jittered" => Ok(SamplingStrategy::Jittered),
+            "histogram" => Ok(SamplingStrategy::Histogram),
+            "reservoir" => Err("reservoir needs a size, e.g. reservoir:10000".to_string()),
+            _ => Err(format!("unknown sampling: {}", sampling)),
+        },
+    }
+}
+
+fn parse_dithering(dithering: &str) -> Result<Dithering, String> {
+    match dithering {
+        "none" => Ok(Dithering::None),
+        "pattern" => Ok(Dithering::Pattern),
+        _ => Err(format!("unknown dithering: {}", dithering)),
+    }
+}
+
+impl Args {
+    // Only options given on the command line are set, everything else keeps the library default
+    fn builder(&self, image: &Image) -> ColorCruncherBuilder {
+        let mut builder = image.builder();
+        if let Some(colors) = self.colors {
+            builder = builder.with_max_colors(colors);
+        }
+        if let Some(algorithm) = &self.algorithm {
+            builder = builder.with_algorithm(algorithm.clone());
+        }
+        if let Some(initializer) = &self.initializer {
+            builder = builder.with_initializer(initializer.clone());
+        }
+        if let Some(seed) = self.seed {
+            builder = builder.with_seed(seed);
+        }
+        if let Some(tolerance) = self.tolerance {
+            builder = builder.with_tolerance(tolerance);
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            builder = builder.with_sample_rate(sample_rate);
+        }
+        if let Some(sampling) = self.sampling {
+            builder = builder.with_sampling(sampling);
+        }
+        if let Some(dithering) = &self.dithering {
+            builder = builder.with_dithering(dithering.clone());
+        }
+        builder
+    }
+
+    // Catches output patterns that can't work before any file is touched
+    fn check_outputs(&self, num_inputs: usize) -> Result<(), String> {
+        for pattern in self.output.iter().chain(&self.palette) {
+            if num_inputs > 1 && !pattern.contains('*') && !is_directory(pattern) {
+                return Err(format!(
+                    "{} names a single file but there are {} inputs, use a directory or a * pattern",
+                    pattern, num_inputs
+                ));
+            }
+        }
+        if let Some(pattern) = self
+            .output
+            .as_ref()
+            .filter(|pattern| !is_directory(pattern))
+        {
+            image_format(Path::new(pattern))?;
+        }
+        if let Some(pattern) = &self.palette {
+            palette_format(pattern)?;
+        }
+        Ok(())
+    }
+}
+
+// Arguments with glob characters are expanded here, so patterns work the same whether
+// or not the shell expanded them. Anything else is taken as a path.
+fn expand_inputs(patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut inputs = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            inputs.push(PathBuf::from(pattern));
+            continue;
+        }
+
+        let paths =
+            glob::glob(pattern).map_err(|e| format!("invalid pattern {}: {}", pattern, e))?;
+        let matched = inputs.len();
+        for path in paths {
+            let path = path.map_err(|e| e.to_string())?;
+            if path.is_file() {
+                inputs.push(path);
+            }
+        }
+        if inputs.len() == matched {
+            return Err(format!("no files match {}", pattern));
+        }
+    }
+    Ok(inputs)
+}
+
+fn is_directory(pattern: &str) -> bool {
+    pattern.ends_with('/')
+        || pattern.ends_with(std::path::MAIN_SEPARATOR)
+        || Path::new(pattern).is_dir()
+}
+
+// Where the file for one input goes. `*` stands for the input's file stem, and a
+// directory gets a file named after the input with the given extension.
+fn output_path(pattern: &str, input: &Path, extension: &str) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    if pattern.contains('*') {
+        PathBuf::from(pattern.replace('*', &stem))
+    } else if is_directory(pattern) {
+        Path::new(pattern).join(format!("{}.{}", stem, extension))
+    } else {
+        PathBuf::from(pattern)
+    }
+}
+
+fn default_output_path(input: &Path) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = input.extension().unwrap_or_default().to_string_lossy();
+    input.with_file_name(format!("{}-quantized.{}", stem, extension))
+}
+
+fn image_format(path: &Path) -> Result<ImageFormat, String> {
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    ImageFormat::from_extension(&extension)
+        .ok_or_else(|| format!("unknown image format: {}", path.display()))
+}
+
+fn palette_format(pattern: &str) -> Result<PaletteFormat, String> {
+    if is_directory(pattern) {
+        return Ok(PaletteFormat::Gpl);
+    }
+    let extension = Path::new(pattern)
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+    PaletteFormat::from_extension(&extension)
+        .ok_or_else(|| format!("unknown palette format: {}", pattern))
+}
+
+fn create_parent(path: &Path) -> Result<(), Failure> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), Failure> {
+    create_parent(path)?;
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+// Writes an indexed image when the format has one and the palette fits, truecolor
+// otherwise. Images with alpha also stay truecolor when the format's palettes can't
+// hold it.
+fn encode(
+    image: &Image,
+    indexed: &IndexedImage,
+    pixels: Vec<u8>,
+    format: ImageFormat,
+) -> Result<Vec<u8>, Failure> {
+    let fits = format
+        .max_palette()
+        .is_some_and(|max| indexed.palette.len() <= max);
+    if fits && (format.palette_alpha() || !image.layout.has_alpha()) {
+        return Ok(image_io::write_indexed_image(indexed, format)?);
+    }
+    Ok(image_io::write_image(
+        &Image {
+            pixels,
+            ..image.clone()
+        },
+        format,
+    )?)
+}
+
+fn quantize_file(args: &Args, input: &Path) -> Result<Written, Failure> {
+    let start = Instant::now();
+    let format = image_format(input).map_err(|e| Failure::new("Image", e))?;
+    let image = image_io::read_image(&fs::read(input)?, format)?;
+
+    let quantizer = block_on(args.builder(&image).build())?;
+    let indexed = block_on(quantizer.quantize_indexed(&image.pixels))?;
+
+    let mut pixels = Vec::with_capacity(image.pixels.len());
+    let mut counts = vec![0; indexed.palette.len()];
+    for index in (0..indexed.indices.len()).filter_map(|i| indexed.indices.get(i)) {
+        image
+            .layout
+            .extend_from_rgba(&mut pixels, indexed.palette[index]);
+        counts[index] += 1;
+    }
+    let quality = metrics::compare(&image.pixels, &pixels, image.layout.channels(), image.width)?;
+
+    let output = match &args.output {
+        Some(pattern) => {
+            let extension = input.extension().unwrap_or_default().to_string_lossy();
+            output_path(pattern, input, &extension)
+        }
+        None => default_output_path(input),
+    };
+    let output_format = image_format(&output).map_err(|e| Failure::new("Image", e))?;
+    let encoded = encode(&image, &indexed, pixels, output_format)?;
+    write_file(&output, &encoded)?;
+
+    let palette = Palette {
+        colors: indexed.palette,
+        counts,
+    };
+    let palette_file = match &args.palette {
+        Some(pattern) => {
+            let format = palette_format(pattern).map_err(|e| Failure::new("Palette", e))?;
+            let path = output_path(pattern, input, DEFAULT_PALETTE_EXTENSION);
+            write_file(&path, &write_palette(&palette, format)?)?;
+            Some(path)
+        }
+        None => None,
+    };
+
+    Ok(Written {
+        output,
+        output_bytes: encoded.len(),
+        palette_file,
+        width: image.width,
+        height: image.height,
+        palette: palette.colors,
+        quality,
+        elapsed_ms: start.elapsed().as_secs_f64() * 1000.0,
+    })
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let inputs = match expand_inputs(&args.inputs).and_then(|inputs| {
+        args.check_outputs(inputs.len())?;
+        Ok(inputs)
+    }) {
+        Ok(inputs) => inputs,
+        Err(message) => {
+            eprintln!("colorcruncher: {}", message);
+            return ExitCode::from(2);
+        }
+    };
+
+    let entries: Vec<Entry> = inputs
+        .into_iter()
+        .map(|input| {
+            let result = quantize_file(&args, &input);
+            if let Err(failure) = &result {
+                eprintln!("colorcruncher: {}: {}", input.display(), failure);
+            }
+            Entry { input, result }
+        })
+        .collect();
+
+    let report = report::to_json(&entries);
+    let text = serde_json::to_string_pretty(&report).expect("report is valid JSON");
+    match &args.report {
+        Some(path) => {
+            if let Err(failure) = write_file(path, text.as_bytes()) {
+                eprintln!("colorcruncher: {}: {}", path.display(), failure);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => println!("{}", text),
+    }
+
+    if entries.iter().all(|entry| entry.result.is_ok()) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use colorcruncher::pixel_layout::PixelLayout;
+
+    #[test]
+    fn test_output_paths() {
+        let input = Path::new("sprites/hero.png");
+        assert_eq!(
+            output_path("out/*-16.png", input, "png"),
+            PathBuf::from("out/hero-16.png")
+        );
+        assert_eq!(
+            output_path("out/", input, "gpl"),
+            PathBuf::from("out/hero.gpl")
+        );
+        assert_eq!(
+            output_path("single.png", input, "png"),
+            PathBuf::from("single.png")
+        );
+        assert_eq!(
+            default_output_path(input),
+            PathBuf::from("sprites/hero-quantized.png")
+        );
+    }
+
+    #[test]
+    fn test_bmp_output_keeps_alpha() {
+        let pixels = [
+            [0, 0, 0, 0],
+            [255, 0, 0, 255],
+            [0, 0, 0, 0],
+            [255, 0, 0, 255],
+        ]
+        .concat();
+        let image = Image {
+            width: 2,
+            height: 2,
+            layout: PixelLayout::Rgba,
+            pixels: pixels.clone(),
+        };
+        let indexed = block_on(
+            block_on(image.builder().with_max_colors(2).build())
+                .unwrap()
+                .quantize_indexed(&image.pixels),
+        )
+        .unwrap();
+
+        for format in [ImageFormat::Png, ImageFormat::Tga, ImageFormat::Bmp] {
+            let encoded = encode(&image, &indexed, pixels.clone(), format).unwrap();
+            let decoded = image_io::read_image(&encoded, format).unwrap();
+            assert_eq!(decoded.layout, PixelLayout::Rgba, "{}", format);
+            assert_eq!(decoded.pixels, pixels, "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_single_file_output_needs_single_input() {
+        let args = Args::parse_from(["colorcruncher", "a.png", "b.png", "-o", "single.png"]);
+        assert!(args.check_outputs(2).is_err());
+        assert!(args.check_outputs(1).is_ok());
+
+        let args = Args::parse_from(["colorcruncher", "a.png", "--palette", "*.pal"]);
+        assert!(args.check_outputs(2).is_ok());
+        let args = Args::parse_from(["colorcruncher", "a.png", "--palette", "*.xyz"]);
+        assert!(args.check_outputs(1).is_err());
+
+        let args = Args::parse_from(["colorcruncher", "a.png", "-o", "out.tga"]);
+        assert!(args.check_outputs(1).is_ok());
+        let args = Args::parse_from(["colorcruncher", "a.png", "-o", "out.jpg"]);
+        assert!(args.check_outputs(1).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use colorcruncher::error::ColorCruncherError;
+use colorcruncher::metrics::QualityReport;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::PathBuf;
+
+// Why one input couldn't be quantized. `kind` is the library's error kind for
+// quantization errors, or names the step that failed otherwise.
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(kind: &'static str, message: String) -> Self {
+        Self { kind, message }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl From<ColorCruncherError> for Failure {
+    fn from(err: ColorCruncherError) -> Self {
+        Failure::new(err.kind(), err.to_string())
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(err: std::io::Error) -> Self {
+        Failure::new("Io", err.to_string())
+    }
+}
+
+// What got written for one input
+pub struct Written {
+    pub output: PathBuf,
+    pub output_bytes: usize,
+    pub palette_file: Option<PathBuf>,
+    pub width: usize,
+    pub height: usize,
+    pub palette: Vec<[u8; 4]>,
+    pub quality: QualityReport,
+    pub elapsed_ms: f64,
+}
+
+pub struct Entry {
+    pub input: PathBuf,
+    pub result: Result<Written, Failure>,
+}
+
+impl Entry {
+    fn to_json(&self) -> Value {
+        match &self.result {
+            Ok(written) => json!({
+                "input": self.input.display().to_string(),
+                "output": written.output.display().to_string(),
+                "output_bytes": written.output_bytes,
+                "palette_file": written.palette_file.as_ref().map(|path| path.display().to_string()),
+                "width": written.width,
+                "height": written.height,
+                "colors": written.palette.len(),
+                "palette": written.palette.iter().map(hex).collect::<Vec<_>>(),
+                // PSNR of a lossless result is infinite, which comes out as null
+                "quality": {
+                    "mse": written.quality.mse,
+                    "psnr": written.quality.psnr,
+                    "ssim": written.quality.ssim_gray,
+                    "delta_e_mean": written.quality.delta_e_mean,
+                    "delta_e_max": written.quality.delta_e_max,
+                },
+                "elapsed_ms": written.elapsed_ms,
+            }),
+            Err(failure) => json!({
+                "input": self.input.display().to_string(),
+                "error": {
+                    "kind": failure.kind,
+                    "message": failure.message,
+                },
+            }),
+        }
+    }
+}
+
+pub fn to_json(entries: &[Entry]) -> Value {
+    let failed = entries.iter().filter(|entry| entry.result.is_err()).count();
+    json!({
+        "images": entries.iter().map(Entry::to_json).collect::<Vec<_>>(),
+        "succeeded": entries.len() - failed,
+        "failed": failed,
+    })
+}
+
+// #rrggbb for opaque colors, #rrggbbaa otherwise
+fn hex(color: &[u8; 4]) -> String {
+    let [r, g, b, a] = *color;
+    if a == u8::MAX {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
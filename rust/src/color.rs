@@ -0,0 +1,156 @@
+// sRGB <-> CIELAB conversions. Adobe tools store Lab relative to D50, while the
+// perceptual metrics use D65, so both white points are supported.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhitePoint {
+    D50,
+    D65,
+}
+
+// Linear sRGB to XYZ, Bradford-adapted for D50
+const SRGB_TO_XYZ_D50: [[f32; 3]; 3] = [
+    [0.4360747, 0.3850649, 0.1430804],
+    [0.2225045, 0.7168786, 0.0606169],
+    [0.0139322, 0.0971045, 0.7141733],
+];
+const XYZ_TO_SRGB_D50: [[f32; 3]; 3] = [
+    [3.133856, -1.616867, -0.4906146],
+    [-0.9787684, 1.916141, 0.033454],
+    [0.0719453, -0.2289914, 1.405243],
+];
+const SRGB_TO_XYZ_D65: [[f32; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.072175],
+    [0.0193339, 0.119192, 0.9503041],
+];
+const XYZ_TO_SRGB_D65: [[f32; 3]; 3] = [
+    [3.240454, -1.537138, -0.4985314],
+    [-0.969266, 1.876011, 0.041556],
+    [0.0556434, -0.2040259, 1.057225],
+];
+
+const EPSILON: f32 = 216.0 / 24389.0;
+const KAPPA: f32 = 24389.0 / 27.0;
+
+impl WhitePoint {
+    fn reference(self) -> [f32; 3] {
+        match self {
+            WhitePoint::D50 => [0.964_22, 1.0, 0.825_21],
+            WhitePoint::D65 => [0.950_47, 1.0, 1.088_83],
+        }
+    }
+
+    fn rgb_to_xyz(self) -> &'static [[f32; 3]; 3] {
+        match self {
+            WhitePoint::D50 => &SRGB_TO_XYZ_D50,
+            WhitePoint::D65 => &SRGB_TO_XYZ_D65,
+        }
+    }
+
+    fn xyz_to_rgb(self) -> &'static [[f32; 3]; 3] {
+        match self {
+            WhitePoint::D50 => &XYZ_TO_SRGB_D50,
+            WhitePoint::D65 => &XYZ_TO_SRGB_D65,
+        }
+    }
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [
+        matrix[0][0] * v[0] + matrix[0][1] * v[1] + matrix[0][2] * v[2],
+        matrix[1][0] * v[0] + matrix[1][1] * v[1] + matrix[1][2] * v[2],
+        matrix[2][0] * v[0] + matrix[2][1] * v[1] + matrix[2][2] * v[2],
+    ]
+}
+
+fn to_linear(channel: f32) -> f32 {
+    let c = channel / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(channel: f32) -> u8 {
+    let c = if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+// Channels are 0-255, returns L in 0-100 and a/b roughly in -128..127
+pub fn srgb_to_lab(rgb: [f32; 3], white: WhitePoint) -> [f32; 3] {
+    let linear = [to_linear(rgb[0]), to_linear(rgb[1]), to_linear(rgb[2])];
+    let xyz = multiply(white.rgb_to_xyz(), linear);
+    let reference = white.reference();
+
+    let f = |t: f32| {
+        if t > EPSILON {
+            t.cbrt()
+        } else {
+            (KAPPA * t + 16.0) / 116.0
+        }
+    };
+    let fx = f(xyz[0] / reference[0]);
+    let fy = f(xyz[1] / reference[1]);
+    let fz = f(xyz[2] / reference[2]);
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+// Out of gamut colors are clamped
+pub fn lab_to_srgb(lab: [f32; 3], white: WhitePoint) -> [u8; 3] {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+
+    let f_inv = |t: f32| {
+        let cubed = t * t * t;
+        if cubed > EPSILON {
+            cubed
+        } else {
+            (116.0 * t - 16.0) / KAPPA
+        }
+    };
+    let reference = white.reference();
+    let xyz = [
+        f_inv(fx) * reference[0],
+        f_inv(fy) * reference[1],
+        f_inv(fz) * reference[2],
+    ];
+
+    let linear = multiply(white.xyz_to_rgb(), xyz);
+    [
+        from_linear(linear[0]),
+        from_linear(linear[1]),
+        from_linear(linear[2]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_white_and_black() {
+        for white in [WhitePoint::D50, WhitePoint::D65] {
+            let lab = srgb_to_lab([255.0, 255.0, 255.0], white);
+            assert!((lab[0] - 100.0).abs() < 0.01, "{lab:?}");
+            assert!(lab[1].abs() < 0.01 && lab[2].abs() < 0.01, "{lab:?}");
+            assert_eq!(srgb_to_lab([0.0, 0.0, 0.0], white), [0.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for rgb in [[255, 0, 0], [12, 200, 99], [128, 128, 128], [3, 7, 250]] {
+            for white in [WhitePoint::D50, WhitePoint::D65] {
+                let lab = srgb_to_lab([rgb[0] as f32, rgb[1] as f32, rgb[2] as f32], white);
+                assert_eq!(lab_to_srgb(lab, white), rgb);
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::kmeans::find_closest_centroid;
+use crate::types::Vec4;
+use std::collections::HashMap;
+use std::fmt;
+
+// 4x4 Bayer matrix. Each entry picks one of the PATTERN_SIZE candidates for a pixel.
+const THRESHOLD_MAP: [[usize; 4]; 4] =
+    [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+const PATTERN_SIZE: usize = 16;
+
+// How much of the accumulated error gets pushed into the next candidate.
+// 1.0 converges fastest but tends to drag in colors far from the source.
+const ERROR_MULTIPLIER: f32 = 0.5;
+
+// Colors whose candidates are remembered at once. The cache starts over when it fills up,
+// so memory stays bounded on huge images with many colors.
+const MAX_CACHED_COLORS: usize = 1 << 16;
+
+#[derive(Debug, Clone, Default)]
+pub enum Dithering {
+    #[default]
+    None,
+    // Thomas Knoll's pattern dithering: mix palette colors per pixel and order them with a threshold matrix
+    Pattern,
+}
+
+impl fmt::Display for Dithering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[inline]
+pub fn luma(color: &Vec4) -> f32 {
+    0.299 * color[0] + 0.587 * color[1] + 0.114 * color[2]
+}
+
+// Picks PATTERN_SIZE palette entries whose average approximates the color,
+// sorted by luma so that the threshold matrix spreads darks and lights evenly.
+pub fn pattern_candidates(color: &Vec4, palette: &[Vec4]) -> [usize; PATTERN_SIZE] {
+    let mut candidates = [0; PATTERN_SIZE];
+    let mut error = [0.0; 3];
+
+    for candidate in candidates.iter_mut() {
+        let attempt = [
+            (color[0] + error[0] * ERROR_MULTIPLIER).clamp(0.0, 255.0),
+            (color[1] + error[1] * ERROR_MULTIPLIER).clamp(0.0, 255.0),
+            (color[2] + error[2] * ERROR_MULTIPLIER).clamp(0.0, 255.0),
+            color[3],
+        ];
+        let chosen = find_closest_centroid(&attempt, palette);
+        *candidate = chosen;
+
+        for channel in 0..3 {
+            error[channel] += color[channel] - palette[chosen][channel];
+        }
+    }
+
+    candidates.sort_by(|a, b| luma(&palette[*a]).total_cmp(&luma(&palette[*b])));
+    candidates
+}
+
+// Remaps pixels onto a palette with pattern dithering. Candidate lists are cached per
+// color since quantized inputs tend to repeat the same colors over and over.
+pub struct PatternDitherer {
+    palette: Vec<Vec4>,
+    cache: HashMap<[u32; 4], [usize; PATTERN_SIZE]>,
+}
+
+impl PatternDitherer {
+    pub fn new(palette: &[Vec4]) -> Self {
+        Self {
+            palette: palette.to_vec(),
+            cache: HashMap::new(),
+        }
+    }
+
+    // x and y are the pixel's position in the whole image, so pieces of an image dithered
+    // separately line up with each other
+    pub fn index_at(&mut self, pixel: &Vec4, x: usize, y: usize) -> usize {
+        let key = pixel.map(|channel| channel as u32);
+        if self.cache.len() >= MAX_CACHED_COLORS && !self.cache.contains_key(&key) {
+            self.cache.clear();
+        }
+        let palette = &self.palette;
+        let candidates = self
+            .cache
+            .entry(key)
+            .or_insert_with(|| pattern_candidates(pixel, palette));
+        candidates[THRESHOLD_MAP[y % 4][x % 4]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_color_is_never_dithered() {
+        let palette = vec![[0.0, 0.0, 0.0, 255.0], [255.0, 0.0, 0.0, 255.0]];
+        let mut ditherer = PatternDitherer::new(&palette);
+
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(ditherer.index_at(&[255.0, 0.0, 0.0, 255.0], x, y), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mid_gray_mixes_black_and_white() {
+        let palette = vec![[0.0, 0.0, 0.0, 255.0], [255.0, 255.0, 255.0, 255.0]];
+        let mut ditherer = PatternDitherer::new(&palette);
+
+        let whites = (0..16)
+            .filter(|i| ditherer.index_at(&[128.0, 128.0, 128.0, 255.0], i % 4, i / 4) == 1)
+            .count();
+        assert_eq!(whites, 8);
+    }
+
+    #[test]
+    fn test_candidates_are_sorted_by_luma() {
+        let palette = vec![
+            [255.0, 255.0, 255.0, 255.0],
+            [0.0, 0.0, 0.0, 255.0],
+            [128.0, 128.0, 128.0, 255.0],
+        ];
+        let candidates = pattern_candidates(&[100.0, 100.0, 100.0, 255.0], &palette);
+
+        for pair in candidates.windows(2) {
+            assert!(luma(&palette[pair[0]]) <= luma(&palette[pair[1]]));
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorCruncherError {
+    // A builder option or k-means setting that can't work
+    InvalidConfig(String),
+    // k-means was asked for more clusters than the data has distinct colors
+    TooFewColors { requested: usize, available: usize },
+    // Pixel buffer length doesn't match the channel layout or image size
+    InvalidBuffer(String),
+    // No adapter or device could be created
+    GpuUnavailable(String),
+    // The GPU was there but something failed while running on it
+    GpuFailure(String),
+    // Palette file bytes that can't be parsed, or a palette the format can't hold
+    InvalidPalette(String),
+    // Image file bytes that can't be decoded, or an image the format can't hold
+    InvalidImage(String),
+}
+
+impl ColorCruncherError {
+    // Stable name for the kind of error, used for JS error names and Python exception types
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ColorCruncherError::InvalidConfig(_) => "InvalidConfig",
+            ColorCruncherError::TooFewColors { .. } => "TooFewColors",
+            ColorCruncherError::InvalidBuffer(_) => "InvalidBuffer",
+            ColorCruncherError::GpuUnavailable(_) => "GpuUnavailable",
+            ColorCruncherError::GpuFailure(_) => "GpuFailure",
+            ColorCruncherError::InvalidPalette(_) => "InvalidPalette",
+            ColorCruncherError::InvalidImage(_) => "InvalidImage",
+        }
+    }
+}
+
+impl fmt::Display for ColorCruncherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorCruncherError::InvalidConfig(message) => {
+                write!(f, "Invalid configuration: {}", message)
+            }
+            ColorCruncherError::TooFewColors {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Number of unique colors is less than k: {} < {}",
+                available, requested
+            ),
+            ColorCruncherError::InvalidBuffer(message) => write!(f, "Invalid buffer: {}", message),
+            ColorCruncherError::GpuUnavailable(message) => {
+                write!(f, "GPU unavailable: {}", message)
+            }
+            ColorCruncherError::GpuFailure(message) => write!(f, "GPU failure: {}", message),
+            ColorCruncherError::InvalidPalette(message) => {
+                write!(f, "Invalid palette: {}", message)
+            }
+            ColorCruncherError::InvalidImage(message) => write!(f, "Invalid image: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ColorCruncherError {}
@@ -0,0 +1,407 @@
+mod lzw;
+
+use crate::error::ColorCruncherError;
+use crate::quantize::IndexedImage;
+
+// GIF transparency is all or nothing, so palette entries below this alpha are drawn as
+// the frame's transparent color
+const TRANSPARENT_ALPHA: u8 = 128;
+const MAX_GIF_PALETTE: usize = 256;
+
+// What happens to a frame's area before the next frame is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Disposal {
+    // Up to the viewer, which in practice means Keep
+    #[default]
+    Unspecified,
+    // Leave the frame in place and draw the next one over it
+    Keep,
+    // Clear the frame's area to the background
+    Background,
+    // Put back whatever was there before the frame was drawn
+    Previous,
+}
+
+impl Disposal {
+    fn code(&self) -> u8 {
+        match self {
+            Disposal::Unspecified => 0,
+            Disposal::Keep => 1,
+            Disposal::Background => 2,
+            Disposal::Previous => 3,
+        }
+    }
+}
+
+// How often an animation plays after the first time, written as a NETSCAPE2.0 extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Infinite,
+    Times(u16),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FrameOptions {
+    // In hundredths of a second
+    pub delay: u16,
+    pub disposal: Disposal,
+    // Where the frame sits on the canvas
+    pub left: usize,
+    pub top: usize,
+}
+
+// Writes GIF89a files from indexed images. Frames whose palette matches the global one
+// use it, any other frame gets its own local color table. Without an explicit global
+// palette the first frame's palette becomes the global one.
+#[derive(Debug, Clone)]
+pub struct GifEncoder {
+    width: usize,
+    height: usize,
+    global_palette: Option<Vec<[u8; 4]>>,
+    repeat: Option<Repeat>,
+    bytes: Vec<u8>,
+}
+
+impl GifEncoder {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            global_palette: None,
+            repeat: None,
+            bytes: Vec::new(),
+        }
+    }
+
+    pub fn with_global_palette(mut self, palette: Vec<[u8; 4]>) -> Self {
+        self.global_palette = Some(palette);
+        self
+    }
+
+    // Without a repeat the animation plays once
+    pub fn with_repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = Some(repeat);
+        self
+    }
+
+    pub fn add_frame(
+        &mut self,
+        image: &IndexedImage,
+        options: &FrameOptions,
+    ) -> Result<(), ColorCruncherError> {
+        check_frame(image, options, self.width, self.height)?;
+        if self.bytes.is_empty() {
+            if self.global_palette.is_none() {
+                self.global_palette = Some(image.palette.clone());
+            }
+            self.write_header()?;
+        }
+
+        let uses_global = self.global_palette.as_ref() == Some(&image.palette);
+        let palette = &image.palette;
+        let transparent = palette
+            .iter()
+            .position(|color| color[3] < TRANSPARENT_ALPHA);
+
+        // Every entry under the alpha cutoff is drawn as the one transparent index
+        let indices: Vec<u8> = (0..image.indices.len())
+            .filter_map(|i| image.indices.get(i))
+            .map(|index| match transparent {
+                Some(transparent) if palette[index][3] < TRANSPARENT_ALPHA => transparent as u8,
+                _ => index as u8,
+            })
+            .collect();
+
+        let bytes = &mut self.bytes;
+        bytes.extend_from_slice(&[0x21, 0xF9, 0x04]);
+        bytes.push(options.disposal.code() << 2 | transparent.is_some() as u8);
+        bytes.extend_from_slice(&options.delay.to_le_bytes());
+        bytes.push(transparent.unwrap_or(0) as u8);
+        bytes.push(0);
+
+        let size_bits = table_size_bits(palette.len());
+        bytes.push(0x2C);
+        for value in [options.left, options.top, image.width, image.height] {
+            bytes.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        if uses_global {
+            bytes.push(0);
+        } else {
+            bytes.push(0x80 | (size_bits - 1));
+            write_color_table(bytes, palette, size_bits);
+        }
+
+        let min_code_size = size_bits.max(2);
+        bytes.push(min_code_size);
+        for block in lzw::compress(&indices, min_code_size).chunks(u8::MAX as usize) {
+            bytes.push(block.len() as u8);
+            bytes.extend_from_slice(block);
+        }
+        bytes.push(0);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Vec<u8>, ColorCruncherError> {
+        if self.bytes.is_empty() {
+            self.write_header()?;
+        }
+        self.bytes.push(0x3B);
+        Ok(self.bytes)
+    }
+
+    fn write_header(&mut self) -> Result<(), ColorCruncherError> {
+        let (width, height) = (screen_size(self.width)?, screen_size(self.height)?);
+        if let Some(palette) = &self.global_palette {
+            check_palette(palette)?;
+        }
+        let bytes = &mut self.bytes;
+        bytes.extend_from_slice(b"GIF89a");
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+
+        match &self.global_palette {
+            Some(palette) => {
+                let size_bits = table_size_bits(palette.len());
+                // Global table, 8 bits per primary
+                bytes.extend_from_slice(&[0x80 | 0x70 | (size_bits - 1), 0, 0]);
+                write_color_table(bytes, palette, size_bits);
+            }
+            None => bytes.extend_from_slice(&[0x70, 0, 0]),
+        }
+
+        if let Some(repeat) = self.repeat {
+            let times = match repeat {
+                Repeat::Infinite => 0,
+                Repeat::Times(times) => times,
+            };
+            bytes.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+            bytes.extend_from_slice(b"NETSCAPE2.0");
+            bytes.extend_from_slice(&[0x03, 0x01]);
+            bytes.extend_from_slice(&times.to_le_bytes());
+            bytes.push(0);
+        }
+        Ok(())
+    }
+}
+
+// A still image with its own palette as the global one
+pub fn encode_gif(image: &IndexedImage) -> Result<Vec<u8>, ColorCruncherError> {
+    let mut encoder = GifEncoder::new(image.width, image.height);
+    encoder.add_frame(image, &FrameOptions::default())?;
+    encoder.finish()
+}
+
+fn screen_size(size: usize) -> Result<u16, ColorCruncherError> {
+    u16::try_from(size).map_err(|_| {
+        ColorCruncherError::InvalidConfig(format!(
+            "GIF sizes are at most {}, got {}",
+            u16::MAX,
+            size
+        ))
+    })
+}
+
+fn check_palette(palette: &[[u8; 4]]) -> Result<(), ColorCruncherError> {
+    if palette.is_empty() || palette.len() > MAX_GIF_PALETTE {
+        return Err(ColorCruncherError::InvalidConfig(format!(
+            "GIF palettes hold 1 to {} colors, got {}",
+            MAX_GIF_PALETTE,
+            palette.len()
+        )));
+    }
+    Ok(())
+}
+
+fn check_frame(
+    image: &IndexedImage,
+    options: &FrameOptions,
+    width: usize,
+    height: usize,
+) -> Result<(), ColorCruncherError> {
+    check_palette(&image.palette)?;
+    if options.left + image.width > width || options.top + image.height > height {
+        return Err(ColorCruncherError::InvalidConfig(format!(
+            "{}x{} frame at ({}, {}) doesn't fit a {}x{} canvas",
+            image.width, image.height, options.left, options.top, width, height
+        )));
+    }
+    if image.indices.len() != image.width * image.height {
+        return Err(ColorCruncherError::InvalidBuffer(format!(
+            "{} indices don't fill a {}x{} frame",
+            image.indices.len(),
+            image.width,
+            image.height
+        )));
+    }
+    let palette_size = image.palette.len();
+    if (0..image.indices.len()).any(|i| {
+        image
+            .indices
+            .get(i)
+            .is_some_and(|index| index >= palette_size)
+    }) {
+        return Err(ColorCruncherError::InvalidBuffer(
+            "frame has indices outside its palette".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Color tables hold a power of two entries, at least two
+fn table_size_bits(palette_size: usize) -> u8 {
+    let mut bits = 1;
+    while 1 << bits < palette_size {
+        bits += 1;
+    }
+    bits
+}
+
+fn write_color_table(bytes: &mut Vec<u8>, palette: &[[u8; 4]], size_bits: u8) {
+    for color in palette {
+        bytes.extend_from_slice(&color[..3]);
+    }
+    let padding = (1 << size_bits) - palette.len();
+    bytes.extend(std::iter::repeat_n(0, padding * 3));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::IndexBuffer;
+    use rand::{Rng, SeedableRng};
+
+    fn indexed(palette: Vec<[u8; 4]>, indices: Vec<u8>, width: usize) -> IndexedImage {
+        let height = indices.len() / width;
+        IndexedImage {
+            palette,
+            indices: IndexBuffer::U8(indices),
+            width,
+            height,
+        }
+    }
+
+    fn gray_palette(size: usize) -> Vec<[u8; 4]> {
+        (0..size)
+            .map(|i| [i as u8, i as u8, i as u8, 255])
+            .collect()
+    }
+
+    fn decode(bytes: &[u8]) -> (gif::Decoder<&[u8]>, Vec<gif::Frame<'static>>) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(bytes).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push(frame.clone());
+        }
+        (decoder, frames)
+    }
+
+    #[test]
+    fn test_lzw_round_trips_every_palette_size() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for size in [2, 3, 4, 16, 17, 256] {
+            // Big enough to fill the code table several times over
+            let pixels: Vec<u8> = (0..200 * 150)
+                .map(|i| {
+                    if i % 7 < 3 {
+                        (i / 50 % size) as u8
+                    } else {
+                        rng.gen_range(0..size) as u8
+                    }
+                })
+                .collect();
+            let bytes = encode_gif(&indexed(gray_palette(size), pixels.clone(), 200)).unwrap();
+
+            let (_, frames) = decode(&bytes);
+            assert_eq!(frames.len(), 1);
+            assert_eq!(
+                frames[0].buffer.as_ref(),
+                pixels.as_slice(),
+                "{} colors",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_animation_frames_and_extensions() {
+        let global = vec![[0, 0, 0, 255], [255, 255, 255, 255], [255, 0, 0, 0]];
+        let local = vec![[0, 0, 255, 255], [0, 255, 0, 255]];
+        let mut encoder = GifEncoder::new(4, 2)
+            .with_global_palette(global.clone())
+            .with_repeat(Repeat::Times(3));
+
+        encoder
+            .add_frame(
+                &indexed(global.clone(), vec![0, 1, 2, 0, 1, 2, 0, 1], 4),
+                &FrameOptions {
+                    delay: 10,
+                    disposal: Disposal::Background,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        encoder
+            .add_frame(
+                &indexed(local.clone(), vec![1, 0], 2),
+                &FrameOptions {
+                    delay: 25,
+                    disposal: Disposal::Previous,
+                    left: 2,
+                    top: 1,
+                },
+            )
+            .unwrap();
+        let bytes = encoder.finish().unwrap();
+
+        let (decoder, frames) = decode(&bytes);
+        assert_eq!(decoder.repeat(), gif::Repeat::Finite(3));
+        assert_eq!(
+            decoder.global_palette().unwrap(),
+            &[0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(frames.len(), 2);
+
+        assert_eq!(frames[0].delay, 10);
+        assert_eq!(frames[0].dispose, gif::DisposalMethod::Background);
+        assert_eq!(frames[0].transparent, Some(2));
+        assert!(frames[0].palette.is_none());
+        assert_eq!(frames[0].buffer.as_ref(), &[0, 1, 2, 0, 1, 2, 0, 1]);
+
+        assert_eq!(frames[1].delay, 25);
+        assert_eq!(frames[1].dispose, gif::DisposalMethod::Previous);
+        assert_eq!((frames[1].left, frames[1].top), (2, 1));
+        assert_eq!(frames[1].transparent, None);
+        assert_eq!(
+            frames[1].palette.as_deref(),
+            Some(&[0, 0, 255, 0, 255, 0][..])
+        );
+        assert_eq!(frames[1].buffer.as_ref(), &[1, 0]);
+    }
+
+    #[test]
+    fn test_translucent_entries_share_one_transparent_index() {
+        let palette = vec![[9, 9, 9, 255], [1, 2, 3, 0], [4, 5, 6, 100]];
+        let bytes = encode_gif(&indexed(palette, vec![0, 1, 2, 2], 2)).unwrap();
+
+        let (decoder, frames) = decode(&bytes);
+        assert_eq!(decoder.repeat(), gif::Repeat::Finite(0));
+        assert_eq!(frames[0].transparent, Some(1));
+        assert_eq!(frames[0].buffer.as_ref(), &[0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_rejects_frames_outside_the_canvas() {
+        let mut encoder = GifEncoder::new(2, 2);
+        let frame = indexed(gray_palette(2), vec![0, 1, 1, 0], 2);
+        let options = FrameOptions {
+            left: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            encoder.add_frame(&frame, &options),
+            Err(ColorCruncherError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+// GIF codes are at most 12 bits wide, so the highest code is 4095
+const MAX_CODE: u16 = (1 << 12) - 1;
+
+// Variable-width LZW as used by GIF: codes are packed least significant bit first, start
+// one bit wider than the minimum code size and grow as the table fills. When the table
+// is full a clear code resets it.
+pub fn compress(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let mut encoder = Encoder::new(min_code_size);
+    encoder.write(encoder.clear);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        encoder.write(encoder.clear + 1);
+        return encoder.bits.finish();
+    };
+
+    let mut code = first as u16;
+    for &index in rest {
+        let key = (code, index);
+        if let Some(&next) = encoder.table.get(&key) {
+            code = next;
+            continue;
+        }
+        encoder.write(code);
+        code = index as u16;
+        if encoder.next_code() {
+            let highest = encoder.highest;
+            encoder.table.insert(key, highest);
+        }
+    }
+
+    encoder.write(code);
+    // The decoder adds an entry after the last code too, so the end code has to follow
+    // the same width change
+    encoder.next_code();
+    encoder.write(encoder.clear + 1);
+    encoder.bits.finish()
+}
+
+struct Encoder {
+    min_code_size: u8,
+    clear: u16,
+    table: HashMap<(u16, u8), u16>,
+    highest: u16,
+    width: u8,
+    bits: BitWriter,
+}
+
+impl Encoder {
+    fn new(min_code_size: u8) -> Self {
+        let clear = 1 << min_code_size;
+        Self {
+            min_code_size,
+            clear,
+            table: HashMap::new(),
+            highest: clear + 1,
+            width: min_code_size + 1,
+            bits: BitWriter::default(),
+        }
+    }
+
+    fn write(&mut self, code: u16) {
+        self.bits.write(code, self.width);
+    }
+
+    // Claims the next code for a table entry, widening codes once it needs another bit.
+    // Returns false when the table was full and has been cleared instead.
+    fn next_code(&mut self) -> bool {
+        self.highest += 1;
+        if self.highest == 1 << self.width {
+            self.width += 1;
+        }
+        if self.highest == MAX_CODE {
+            self.write(self.clear);
+            self.table.clear();
+            self.highest = self.clear + 1;
+            self.width = self.min_code_size + 1;
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u8) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += width;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
@@ -0,0 +1,212 @@
+mod bmp;
+mod netpbm;
+mod png;
+mod tga;
+
+pub use self::bmp::{read_bmp, write_bmp, write_indexed_bmp};
+pub use self::netpbm::{read_netpbm, write_netpbm};
+pub use self::png::{read_png, write_indexed_png, write_png, MAX_PNG_PALETTE};
+pub use self::tga::{read_tga, write_indexed_tga, write_tga};
+use crate::error::ColorCruncherError;
+use crate::pixel_layout::PixelLayout;
+use crate::quantize::{ColorCruncherBuilder, IndexedImage};
+use std::fmt;
+
+fn invalid_image(message: impl Into<String>) -> ColorCruncherError {
+    ColorCruncherError::InvalidImage(message.into())
+}
+
+// A decoded image with 8 bits per channel, ready to be passed to `quantize_image`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub layout: PixelLayout,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    // A builder already set up for this image's layout and width
+    pub fn builder(&self) -> ColorCruncherBuilder {
+        ColorCruncherBuilder::new()
+            .with_pixel_layout(self.layout)
+            .with_width(self.width)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    // Binary PGM, PPM and PAM
+    Netpbm,
+    // Truecolor TGAs are written uncompressed; use `write_tga` directly for RLE
+    Tga,
+    Bmp,
+}
+
+impl ImageFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "pgm" | "ppm" | "pam" | "pnm" => Some(ImageFormat::Netpbm),
+            "tga" => Some(ImageFormat::Tga),
+            "bmp" => Some(ImageFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    // Largest palette `write_indexed_image` can store, None if the format has no
+    // indexed form
+    pub fn max_palette(&self) -> Option<usize> {
+        match self {
+            ImageFormat::Png => Some(MAX_PNG_PALETTE),
+            ImageFormat::Tga | ImageFormat::Bmp => Some(256),
+            ImageFormat::Netpbm => None,
+        }
+    }
+
+    // Whether the indexed form keeps each palette entry's alpha
+    pub fn palette_alpha(&self) -> bool {
+        match self {
+            ImageFormat::Png | ImageFormat::Tga => true,
+            ImageFormat::Bmp | ImageFormat::Netpbm => false,
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+pub fn read_image(bytes: &[u8], format: ImageFormat) -> Result<Image, ColorCruncherError> {
+    match format {
+        ImageFormat::Png => read_png(bytes),
+        ImageFormat::Netpbm => read_netpbm(bytes),
+        ImageFormat::Tga => read_tga(bytes),
+        ImageFormat::Bmp => read_bmp(bytes),
+    }
+}
+
+pub fn write_image(image: &Image, format: ImageFormat) -> Result<Vec<u8>, ColorCruncherError> {
+    match format {
+        ImageFormat::Png => write_png(image),
+        ImageFormat::Netpbm => write_netpbm(image),
+        ImageFormat::Tga => write_tga(image, false),
+        ImageFormat::Bmp => write_bmp(image),
+    }
+}
+
+pub fn write_indexed_image(
+    image: &IndexedImage,
+    format: ImageFormat,
+) -> Result<Vec<u8>, ColorCruncherError> {
+    match format {
+        ImageFormat::Png => write_indexed_png(image),
+        ImageFormat::Tga => write_indexed_tga(image, false),
+        ImageFormat::Bmp => write_indexed_bmp(image),
+        ImageFormat::Netpbm => Err(invalid_image("Netpbm has no indexed format")),
+    }
+}
+
+fn check_image(image: &Image) -> Result<(), ColorCruncherError> {
+    let expected = image.width * image.height * image.layout.channels();
+    if image.pixels.len() != expected {
+        return Err(invalid_image(format!(
+            "{} bytes don't fill a {}x{} {:?} image",
+            image.pixels.len(),
+            image.width,
+            image.height,
+            image.layout
+        )));
+    }
+    Ok(())
+}
+
+fn check_indexed(
+    image: &IndexedImage,
+    max_palette: usize,
+    format: &str,
+) -> Result<(), ColorCruncherError> {
+    let palette = &image.palette;
+    if palette.is_empty() {
+        return Err(invalid_image("Palette is empty"));
+    }
+    if palette.len() > max_palette {
+        return Err(invalid_image(format!(
+            "Indexed {}s hold at most {} colors, got {}",
+            format,
+            max_palette,
+            palette.len()
+        )));
+    }
+    if image.indices.len() != image.width * image.height {
+        return Err(invalid_image(format!(
+            "{} indices don't fill a {}x{} image",
+            image.indices.len(),
+            image.width,
+            image.height
+        )));
+    }
+    Ok(())
+}
+
+// Pixels as RGBA, for layouts a format can't store directly
+fn rgba_pixels(image: &Image) -> Vec<u8> {
+    image
+        .pixels
+        .chunks_exact(image.layout.channels())
+        .flat_map(|pixel| image.layout.to_rgba(pixel))
+        .collect()
+}
+
+// Packs remapped indices into rows, most significant bits first, each row padded to a
+// multiple of `align` bytes
+fn pack_rows(
+    image: &IndexedImage,
+    remap: &[u8],
+    bits: usize,
+    align: usize,
+) -> Result<Vec<u8>, ColorCruncherError> {
+    let per_byte = 8 / bits;
+    let row_bytes = image.width.div_ceil(per_byte).next_multiple_of(align);
+    let mut data = vec![0u8; row_bytes * image.height];
+
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let i = y * image.width + x;
+            let index = image
+                .indices
+                .get(i)
+                .and_then(|index| remap.get(index))
+                .ok_or_else(|| invalid_image(format!("Pixel {} is not in the palette", i)))?;
+            let shift = 8 - bits * (x % per_byte + 1);
+            data[y * row_bytes + x / per_byte] |= index << shift;
+        }
+    }
+    Ok(data)
+}
+
+// Width times height times bytes per pixel, refusing sizes that can't be allocated
+fn image_size(width: usize, height: usize, channels: usize) -> Result<usize, ColorCruncherError> {
+    width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .filter(|&size| size <= isize::MAX as usize)
+        .ok_or_else(|| invalid_image(format!("{}x{} image is too large", width, height)))
+}
+
+fn read_u16_le(bytes: &[u8], offset: usize) -> Result<u16, ColorCruncherError> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid_image("Unexpected end of file"))
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> Result<u32, ColorCruncherError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid_image("Unexpected end of file"))
+}
@@ -0,0 +1,380 @@
+use super::{
+    check_image, check_indexed, image_size, invalid_image, pack_rows, read_u16_le, read_u32_le,
+    rgba_pixels, Image,
+};
+use crate::error::ColorCruncherError;
+use crate::pixel_layout::PixelLayout;
+use crate::quantize::IndexedImage;
+
+const FILE_HEADER_SIZE: usize = 14;
+const CORE_HEADER_SIZE: usize = 12;
+const INFO_HEADER_SIZE: usize = 40;
+const V4_HEADER_SIZE: usize = 108;
+// Compression values
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+// 72 DPI in pixels per meter
+const PIXELS_PER_METER: u32 = 2835;
+
+// Reads uncompressed 1, 4, 8, 16, 24 and 32-bit BMPs, including bit field masks.
+// Indexed images are expanded to RGB, since BMP palettes have no alpha. 32-bit images
+// only have alpha when a mask says where it is; otherwise the fourth byte is unused.
+pub fn read_bmp(bytes: &[u8]) -> Result<Image, ColorCruncherError> {
+    if !bytes.starts_with(b"BM") {
+        return Err(invalid_image("Missing BM signature"));
+    }
+    let data_offset = read_u32_le(bytes, 10)? as usize;
+    let header_size = read_u32_le(bytes, FILE_HEADER_SIZE)? as usize;
+
+    let (width, height, bits, compression, colors_used, palette_entry) =
+        if header_size == CORE_HEADER_SIZE {
+            (
+                read_u16_le(bytes, 18)? as i64,
+                read_u16_le(bytes, 20)? as i16 as i64,
+                read_u16_le(bytes, 24)?,
+                BI_RGB,
+                0,
+                3,
+            )
+        } else if header_size >= INFO_HEADER_SIZE {
+            (
+                read_u32_le(bytes, 18)? as i32 as i64,
+                read_u32_le(bytes, 22)? as i32 as i64,
+                read_u16_le(bytes, 28)?,
+                read_u32_le(bytes, 30)?,
+                read_u32_le(bytes, 46)? as usize,
+                4,
+            )
+        } else {
+            return Err(invalid_image(format!(
+                "Unsupported BMP header size {}",
+                header_size
+            )));
+        };
+
+    if width <= 0 || height == 0 {
+        return Err(invalid_image(format!(
+            "Invalid BMP size {}x{}",
+            width, height
+        )));
+    }
+    // Positive heights are stored bottom to top
+    let bottom_up = height > 0;
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+
+    let masks = match (compression, bits) {
+        (BI_RGB, 1 | 4 | 8 | 24) => None,
+        (BI_RGB, 16) => Some([0x7C00, 0x03E0, 0x001F, 0]),
+        (BI_RGB, 32) => Some([0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0]),
+        (BI_BITFIELDS, 16 | 32) => {
+            // Masks follow a plain info header, or are part of the larger ones. Only
+            // headers from V3 on have room for an alpha mask.
+            let alpha = if header_size >= 56 {
+                read_u32_le(bytes, 66)?
+            } else {
+                0
+            };
+            Some([
+                read_u32_le(bytes, 54)?,
+                read_u32_le(bytes, 58)?,
+                read_u32_le(bytes, 62)?,
+                alpha,
+            ])
+        }
+        _ => {
+            return Err(invalid_image(format!(
+                "Unsupported BMP with {} bits per pixel and compression {}",
+                bits, compression
+            )))
+        }
+    };
+
+    let palette: Vec<[u8; 4]> = if bits <= 8 {
+        let count = match colors_used {
+            0 => 1 << bits,
+            count => count.min(1 << bits),
+        };
+        let start = FILE_HEADER_SIZE + header_size;
+        bytes
+            .get(start..start + count * palette_entry)
+            .ok_or_else(|| invalid_image("BMP palette is cut short"))?
+            .chunks_exact(palette_entry)
+            .map(|entry| [entry[2], entry[1], entry[0], u8::MAX])
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let bits = bits as usize;
+    let row_bytes = image_size(width, bits, 1)?.div_ceil(32) * 4;
+    let size = image_size(row_bytes, height, 1)?;
+    let rows = bytes
+        .get(data_offset..)
+        .and_then(|data| data.get(..size))
+        .ok_or_else(|| invalid_image("BMP pixel data is cut short"))?;
+
+    let layout = match masks {
+        Some([.., alpha]) if alpha != 0 => PixelLayout::Rgba,
+        _ => PixelLayout::Rgb,
+    };
+    let mut pixels = Vec::with_capacity(image_size(width, height, layout.channels())?);
+    for y in 0..height {
+        let row_index = if bottom_up { height - 1 - y } else { y };
+        let row = &rows[row_index * row_bytes..(row_index + 1) * row_bytes];
+        for x in 0..width {
+            let color = match (bits, masks) {
+                (1 | 4 | 8, _) => {
+                    let per_byte = 8 / bits;
+                    let shift = 8 - bits * (x % per_byte + 1);
+                    let index = (row[x / per_byte] >> shift) as usize & ((1 << bits) - 1);
+                    *palette.get(index).ok_or_else(|| {
+                        invalid_image(format!("Index {} is not in the palette", index))
+                    })?
+                }
+                (24, _) => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3], u8::MAX],
+                (16, Some(masks)) => masked_color(
+                    u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]) as u32,
+                    masks,
+                ),
+                (_, Some(masks)) => {
+                    let value = &row[x * 4..x * 4 + 4];
+                    masked_color(
+                        u32::from_le_bytes([value[0], value[1], value[2], value[3]]),
+                        masks,
+                    )
+                }
+                _ => unreachable!("every bit depth was matched above"),
+            };
+            layout.extend_from_rgba(&mut pixels, color);
+        }
+    }
+
+    Ok(Image {
+        width,
+        height,
+        layout,
+        pixels,
+    })
+}
+
+// Gray images become 8-bit with a gray ramp palette, RGB 24-bit, and anything with
+// alpha 32-bit with a V4 header whose masks mark the alpha byte
+pub fn write_bmp(image: &Image) -> Result<Vec<u8>, ColorCruncherError> {
+    check_image(image)?;
+    let (width, height) = (image.width, image.height);
+    match image.layout {
+        PixelLayout::Gray => {
+            let palette: Vec<[u8; 4]> = (0..=255).map(|v| [v, v, v, u8::MAX]).collect();
+            let mut bytes = headers(width, height, 8, INFO_HEADER_SIZE, &palette)?;
+            write_rows(&mut bytes, &image.pixels, width);
+            Ok(bytes)
+        }
+        PixelLayout::Rgb => {
+            let mut bytes = headers(width, height, 24, INFO_HEADER_SIZE, &[])?;
+            let pixels: Vec<u8> = image
+                .pixels
+                .chunks_exact(3)
+                .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+                .collect();
+            write_rows(&mut bytes, &pixels, width * 3);
+            Ok(bytes)
+        }
+        _ => {
+            let mut bytes = headers(width, height, 32, V4_HEADER_SIZE, &[])?;
+            let pixels: Vec<u8> = rgba_pixels(image)
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+                .collect();
+            write_rows(&mut bytes, &pixels, width * 4);
+            Ok(bytes)
+        }
+    }
+}
+
+// Indexed BMP at 1, 4 or 8 bits per pixel, whichever is the smallest that fits the
+// palette. Palette alpha is dropped, BMP palettes can't store it.
+pub fn write_indexed_bmp(image: &IndexedImage) -> Result<Vec<u8>, ColorCruncherError> {
+    check_indexed(image, 256, "BMP")?;
+    let bits = match image.palette.len() {
+        0..=2 => 1,
+        3..=16 => 4,
+        _ => 8,
+    };
+    let identity: Vec<u8> = (0..image.palette.len()).map(|i| i as u8).collect();
+    let rows = pack_rows(image, &identity, bits, 4)?;
+
+    let mut bytes = headers(
+        image.width,
+        image.height,
+        bits as u16,
+        INFO_HEADER_SIZE,
+        &image.palette,
+    )?;
+    let row_bytes = rows.len() / image.height.max(1);
+    write_rows(&mut bytes, &rows, row_bytes);
+    Ok(bytes)
+}
+
+// File and info headers plus the palette, for a bottom-up image. The V4 header is only
+// used for 32-bit images, with bit field masks for BGRA.
+fn headers(
+    width: usize,
+    height: usize,
+    bits: u16,
+    header_size: usize,
+    palette: &[[u8; 4]],
+) -> Result<Vec<u8>, ColorCruncherError> {
+    let (Ok(width), Ok(height)) = (i32::try_from(width), i32::try_from(height)) else {
+        return Err(invalid_image(format!(
+            "{}x{} image is too large for a BMP",
+            width, height
+        )));
+    };
+    let row_bytes = (width as usize * bits as usize).div_ceil(32) * 4;
+    let image_bytes = row_bytes * height as usize;
+    let data_offset = FILE_HEADER_SIZE + header_size + palette.len() * 4;
+    let file_size = u32::try_from(data_offset + image_bytes)
+        .map_err(|_| invalid_image(format!("{}x{} image is too large for a BMP", width, height)))?;
+
+    let mut bytes = Vec::with_capacity(file_size as usize);
+    bytes.extend_from_slice(b"BM");
+    bytes.extend_from_slice(&file_size.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&(data_offset as u32).to_le_bytes());
+
+    let compression = if header_size == V4_HEADER_SIZE {
+        BI_BITFIELDS
+    } else {
+        BI_RGB
+    };
+    bytes.extend_from_slice(&(header_size as u32).to_le_bytes());
+    bytes.extend_from_slice(&width.to_le_bytes());
+    bytes.extend_from_slice(&height.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&bits.to_le_bytes());
+    bytes.extend_from_slice(&compression.to_le_bytes());
+    bytes.extend_from_slice(&(image_bytes as u32).to_le_bytes());
+    bytes.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    bytes.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    bytes.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+
+    if header_size == V4_HEADER_SIZE {
+        for mask in [0x00FF_0000u32, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000] {
+            bytes.extend_from_slice(&mask.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"BGRs");
+        // Endpoints and gamma are unused for sRGB
+        bytes.resize(FILE_HEADER_SIZE + V4_HEADER_SIZE, 0);
+    }
+
+    for color in palette {
+        bytes.extend_from_slice(&[color[2], color[1], color[0], 0]);
+    }
+    Ok(bytes)
+}
+
+// Appends rows bottom to top, each padded to a multiple of 4 bytes
+fn write_rows(bytes: &mut Vec<u8>, pixels: &[u8], row_bytes: usize) {
+    if row_bytes == 0 {
+        return;
+    }
+    let padding = row_bytes.next_multiple_of(4) - row_bytes;
+    for row in pixels.chunks_exact(row_bytes).rev() {
+        bytes.extend_from_slice(row);
+        bytes.extend(std::iter::repeat_n(0, padding));
+    }
+}
+
+fn masked_color(value: u32, masks: [u32; 4]) -> [u8; 4] {
+    let channel = |mask: u32| {
+        if mask == 0 {
+            return u8::MAX;
+        }
+        let shift = mask.trailing_zeros();
+        let max = (mask >> shift) as u64;
+        (((value & mask) >> shift) as u64 * 255 / max) as u8
+    };
+    [
+        channel(masks[0]),
+        channel(masks[1]),
+        channel(masks[2]),
+        channel(masks[3]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::IndexBuffer;
+
+    #[test]
+    fn test_indexed_bmp_bit_depths() {
+        for (colors, bits) in [(2, 1), (5, 4), (17, 8)] {
+            let palette: Vec<[u8; 4]> = (0..colors)
+                .map(|i| [i as u8 * 10, 0, 255 - i as u8, 255])
+                .collect();
+            let indices: Vec<u8> = (0..15).map(|i| (i % colors) as u8).collect();
+            let indexed = IndexedImage {
+                palette: palette.clone(),
+                indices: IndexBuffer::U8(indices.clone()),
+                width: 5,
+                height: 3,
+            };
+            let bytes = write_indexed_bmp(&indexed).unwrap();
+            assert_eq!(read_u16_le(&bytes, 28).unwrap(), bits);
+
+            let decoded = read_bmp(&bytes).unwrap();
+            assert_eq!(decoded.layout, PixelLayout::Rgb);
+            let expected: Vec<u8> = indices
+                .iter()
+                .flat_map(|&i| &palette[i as usize][..3])
+                .copied()
+                .collect();
+            assert_eq!(decoded.pixels, expected);
+        }
+    }
+
+    #[test]
+    fn test_truecolor_round_trips() {
+        for layout in [PixelLayout::Rgb, PixelLayout::Rgba] {
+            let image = Image {
+                width: 3,
+                height: 2,
+                layout,
+                pixels: (0..6 * layout.channels()).map(|i| i as u8 * 7).collect(),
+            };
+            assert_eq!(read_bmp(&write_bmp(&image).unwrap()).unwrap(), image);
+        }
+
+        // Gray comes back through the gray ramp palette
+        let gray = Image {
+            width: 1,
+            height: 2,
+            layout: PixelLayout::Gray,
+            pixels: vec![10, 200],
+        };
+        let decoded = read_bmp(&write_bmp(&gray).unwrap()).unwrap();
+        assert_eq!(decoded.pixels, vec![10, 10, 10, 200, 200, 200]);
+    }
+
+    #[test]
+    fn test_reads_top_down_565() {
+        let mut bytes = headers(2, 1, 16, INFO_HEADER_SIZE, &[]).unwrap();
+        bytes[30..34].copy_from_slice(&BI_BITFIELDS.to_le_bytes());
+        // Top-down, with the masks right after the info header
+        bytes[22..26].copy_from_slice(&(-1i32).to_le_bytes());
+        bytes.splice(
+            54..54,
+            [0xF800u32, 0x07E0, 0x001F]
+                .iter()
+                .flat_map(|m| m.to_le_bytes()),
+        );
+        bytes[10] += 12;
+        bytes.extend_from_slice(&0xF800u16.to_le_bytes());
+        bytes.extend_from_slice(&0x07E0u16.to_le_bytes());
+
+        let image = read_bmp(&bytes).unwrap();
+        assert_eq!(image.pixels, vec![255, 0, 0, 0, 255, 0]);
+    }
+}
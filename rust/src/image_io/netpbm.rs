@@ -0,0 +1,228 @@
+use super::{check_image, image_size, invalid_image, rgba_pixels, Image};
+use crate::error::ColorCruncherError;
+use crate::pixel_layout::PixelLayout;
+
+// Reads binary PGM (P5), PPM (P6) and PAM (P7). Samples with a maxval other than 255,
+// including 16-bit ones, are rescaled to 8 bits. Only the first image of a multi-image
+// file is read.
+pub fn read_netpbm(bytes: &[u8]) -> Result<Image, ColorCruncherError> {
+    let mut header = Header { bytes, position: 2 };
+    let (width, height, depth, maxval) = match bytes.get(..2) {
+        Some(b"P5") | Some(b"P6") => {
+            let width = header.number()?;
+            let height = header.number()?;
+            let maxval = header.number()?;
+            // A single whitespace character separates the header from the raster
+            header.position += 1;
+            let depth = if bytes[1] == b'5' { 1 } else { 3 };
+            (width, height, depth, maxval)
+        }
+        Some(b"P7") => header.pam()?,
+        _ => {
+            return Err(invalid_image(
+                "Only binary PGM (P5), PPM (P6) and PAM (P7) files are supported",
+            ))
+        }
+    };
+
+    let layout = match depth {
+        1 => PixelLayout::Gray,
+        2 => PixelLayout::GrayAlpha,
+        3 => PixelLayout::Rgb,
+        4 => PixelLayout::Rgba,
+        _ => return Err(invalid_image(format!("Unsupported PAM depth {}", depth))),
+    };
+    if !(1..=u16::MAX as usize).contains(&maxval) {
+        return Err(invalid_image(format!("Invalid maxval {}", maxval)));
+    }
+
+    let sample_bytes = if maxval > 255 { 2 } else { 1 };
+    let size = image_size(width, height, depth * sample_bytes)?;
+    let raster = bytes
+        .get(header.position..)
+        .and_then(|raster| raster.get(..size))
+        .ok_or_else(|| invalid_image("Raster is shorter than the header says"))?;
+
+    let pixels = if sample_bytes == 2 {
+        raster
+            .chunks_exact(2)
+            .map(|sample| scale(u16::from_be_bytes([sample[0], sample[1]]), maxval))
+            .collect()
+    } else if maxval == 255 {
+        raster.to_vec()
+    } else {
+        raster
+            .iter()
+            .map(|&sample| scale(sample as u16, maxval))
+            .collect()
+    };
+
+    Ok(Image {
+        width,
+        height,
+        layout,
+        pixels,
+    })
+}
+
+// PGM for gray images, PPM for RGB and PAM for anything with alpha. BGRA and ARGB are
+// written as RGBA.
+pub fn write_netpbm(image: &Image) -> Result<Vec<u8>, ColorCruncherError> {
+    check_image(image)?;
+    let (width, height) = (image.width, image.height);
+    let header = match image.layout {
+        PixelLayout::Gray => format!("P5\n{} {}\n255\n", width, height),
+        PixelLayout::Rgb => format!("P6\n{} {}\n255\n", width, height),
+        PixelLayout::GrayAlpha => pam_header(width, height, 2, "GRAYSCALE_ALPHA"),
+        PixelLayout::Rgba | PixelLayout::Bgra | PixelLayout::Argb => {
+            pam_header(width, height, 4, "RGB_ALPHA")
+        }
+    };
+
+    let mut bytes = header.into_bytes();
+    match image.layout {
+        PixelLayout::Bgra | PixelLayout::Argb => bytes.extend(rgba_pixels(image)),
+        _ => bytes.extend_from_slice(&image.pixels),
+    }
+    Ok(bytes)
+}
+
+fn pam_header(width: usize, height: usize, depth: usize, tuple_type: &str) -> String {
+    format!(
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {}\nENDHDR\n",
+        width, height, depth, tuple_type
+    )
+}
+
+// Rounds a sample in 0..=maxval to 0..=255
+fn scale(sample: u16, maxval: usize) -> u8 {
+    let sample = (sample as usize).min(maxval);
+    ((sample * 255 + maxval / 2) / maxval) as u8
+}
+
+struct Header<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Header<'_> {
+    // The next whitespace separated token, skipping # comments
+    fn token(&mut self) -> Result<&[u8], ColorCruncherError> {
+        loop {
+            match self.bytes.get(self.position) {
+                Some(b'#') => {
+                    while !matches!(self.bytes.get(self.position), Some(b'\n') | None) {
+                        self.position += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                Some(_) => break,
+                None => return Err(invalid_image("Header ends early")),
+            }
+        }
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|byte| !byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+        Ok(&self.bytes[start..self.position])
+    }
+
+    fn number(&mut self) -> Result<usize, ColorCruncherError> {
+        let token = self.token()?;
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| {
+                invalid_image(format!(
+                    "Expected a number, got {}",
+                    String::from_utf8_lossy(token)
+                ))
+            })
+    }
+
+    // PAM headers are one `NAME value` per line up to ENDHDR. TUPLTYPE is ignored, the
+    // depth alone decides the layout.
+    fn pam(&mut self) -> Result<(usize, usize, usize, usize), ColorCruncherError> {
+        let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+        loop {
+            match self.token()? {
+                b"WIDTH" => width = Some(self.number()?),
+                b"HEIGHT" => height = Some(self.number()?),
+                b"DEPTH" => depth = Some(self.number()?),
+                b"MAXVAL" => maxval = Some(self.number()?),
+                b"TUPLTYPE" => {
+                    self.token()?;
+                }
+                b"ENDHDR" => break,
+                other => {
+                    return Err(invalid_image(format!(
+                        "Unknown PAM header field {}",
+                        String::from_utf8_lossy(other)
+                    )))
+                }
+            }
+        }
+        // ENDHDR is followed by a newline
+        self.position += 1;
+        match (width, height, depth, maxval) {
+            (Some(width), Some(height), Some(depth), Some(maxval)) => {
+                Ok((width, height, depth, maxval))
+            }
+            _ => Err(invalid_image(
+                "PAM header needs WIDTH, HEIGHT, DEPTH and MAXVAL",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ppm_round_trip() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            layout: PixelLayout::Rgb,
+            pixels: vec![1, 2, 3, 250, 251, 252],
+        };
+        let bytes = write_netpbm(&image).unwrap();
+        assert!(bytes.starts_with(b"P6\n2 1\n255\n"));
+        assert_eq!(read_netpbm(&bytes).unwrap(), image);
+    }
+
+    #[test]
+    fn test_bgra_is_written_as_rgb_alpha_pam() {
+        let image = Image {
+            width: 1,
+            height: 1,
+            layout: PixelLayout::Bgra,
+            pixels: vec![3, 2, 1, 128],
+        };
+        let decoded = read_netpbm(&write_netpbm(&image).unwrap()).unwrap();
+        assert_eq!(decoded.layout, PixelLayout::Rgba);
+        assert_eq!(decoded.pixels, vec![1, 2, 3, 128]);
+    }
+
+    #[test]
+    fn test_reads_comments_and_16_bit_samples() {
+        let mut bytes = b"P5 # gray\n# size\n2 1\n65535\n".to_vec();
+        bytes.extend_from_slice(&[0xFF, 0xFF, 0x80, 0x00]);
+        let image = read_netpbm(&bytes).unwrap();
+        assert_eq!(image.layout, PixelLayout::Gray);
+        assert_eq!(image.pixels, vec![255, 128]);
+    }
+
+    #[test]
+    fn test_rejects_short_raster() {
+        assert!(matches!(
+            read_netpbm(b"P6\n2 2\n255\n\x00\x00\x00"),
+            Err(ColorCruncherError::InvalidImage(_))
+        ));
+    }
+}
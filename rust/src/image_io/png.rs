@@ -0,0 +1,224 @@
+use super::{check_indexed, invalid_image, pack_rows, rgba_pixels, Image};
+use crate::error::ColorCruncherError;
+use crate::pixel_layout::PixelLayout;
+use crate::quantize::IndexedImage;
+
+// Indexed PNGs address at most 256 palette entries
+pub const MAX_PNG_PALETTE: usize = 256;
+
+impl From<::png::DecodingError> for ColorCruncherError {
+    fn from(err: ::png::DecodingError) -> Self {
+        invalid_image(format!("Invalid PNG: {}", err))
+    }
+}
+
+impl From<::png::EncodingError> for ColorCruncherError {
+    fn from(err: ::png::EncodingError) -> Self {
+        invalid_image(format!("Could not encode PNG: {}", err))
+    }
+}
+
+// Palette, low bit depth and 16-bit PNGs are all expanded to 8 bits per channel, with
+// tRNS chunks turned into an alpha channel. Only the first frame of an APNG is read.
+pub fn read_png(bytes: &[u8]) -> Result<Image, ColorCruncherError> {
+    let mut decoder = ::png::Decoder::new(bytes);
+    decoder.set_transformations(::png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+
+    let layout = match info.color_type {
+        ::png::ColorType::Grayscale => PixelLayout::Gray,
+        ::png::ColorType::GrayscaleAlpha => PixelLayout::GrayAlpha,
+        ::png::ColorType::Rgb => PixelLayout::Rgb,
+        ::png::ColorType::Rgba => PixelLayout::Rgba,
+        ::png::ColorType::Indexed => return Err(invalid_image("Palette was not expanded")),
+    };
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        layout,
+        pixels,
+    })
+}
+
+// Truecolor PNG in the image's layout. BGRA and ARGB have no PNG equivalent and are
+// written as RGBA.
+pub fn write_png(image: &Image) -> Result<Vec<u8>, ColorCruncherError> {
+    let (color, pixels) = match image.layout {
+        PixelLayout::Gray => (::png::ColorType::Grayscale, image.pixels.clone()),
+        PixelLayout::GrayAlpha => (::png::ColorType::GrayscaleAlpha, image.pixels.clone()),
+        PixelLayout::Rgb => (::png::ColorType::Rgb, image.pixels.clone()),
+        PixelLayout::Rgba => (::png::ColorType::Rgba, image.pixels.clone()),
+        PixelLayout::Bgra | PixelLayout::Argb => (::png::ColorType::Rgba, rgba_pixels(image)),
+    };
+
+    let mut bytes = Vec::new();
+    let mut encoder = ::png::Encoder::new(&mut bytes, image.width as u32, image.height as u32);
+    encoder.set_color(color);
+    encoder.set_depth(::png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(bytes)
+}
+
+// Palette-based PNG at the smallest bit depth that fits the palette. Translucent
+// entries are moved to the front so the tRNS chunk can stop after the last of them;
+// fully opaque palettes get no tRNS chunk at all.
+pub fn write_indexed_png(image: &IndexedImage) -> Result<Vec<u8>, ColorCruncherError> {
+    check_indexed(image, MAX_PNG_PALETTE, "PNG")?;
+    let palette = &image.palette;
+
+    let mut order: Vec<usize> = (0..palette.len()).collect();
+    order.sort_by_key(|&i| palette[i][3] == u8::MAX);
+    let mut remap = vec![0u8; palette.len()];
+    for (new, &old) in order.iter().enumerate() {
+        remap[old] = new as u8;
+    }
+
+    let rgb: Vec<u8> = order
+        .iter()
+        .flat_map(|&i| &palette[i][..3])
+        .copied()
+        .collect();
+    let trns: Vec<u8> = order
+        .iter()
+        .map(|&i| palette[i][3])
+        .take_while(|&alpha| alpha != u8::MAX)
+        .collect();
+
+    let depth = bit_depth(palette.len());
+    let data = pack_rows(image, &remap, depth as usize, 1)?;
+
+    let mut bytes = Vec::new();
+    let mut encoder = ::png::Encoder::new(&mut bytes, image.width as u32, image.height as u32);
+    encoder.set_color(::png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(rgb);
+    if !trns.is_empty() {
+        encoder.set_trns(trns);
+    }
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(bytes)
+}
+
+fn bit_depth(palette_size: usize) -> ::png::BitDepth {
+    match palette_size {
+        0..=2 => ::png::BitDepth::One,
+        3..=4 => ::png::BitDepth::Two,
+        5..=16 => ::png::BitDepth::Four,
+        _ => ::png::BitDepth::Eight,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::{ColorCruncherBuilder, IndexBuffer};
+    use futures::executor::block_on;
+
+    fn indexed(palette: Vec<[u8; 4]>, indices: Vec<u8>, width: usize) -> IndexedImage {
+        let height = indices.len() / width;
+        IndexedImage {
+            palette,
+            indices: IndexBuffer::U8(indices),
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_indexed_png_round_trip() {
+        let palette = vec![
+            [255, 0, 0, 255],
+            [0, 255, 0, 0],
+            [0, 0, 255, 255],
+            [10, 20, 30, 128],
+            [40, 50, 60, 255],
+        ];
+        let image = indexed(palette.clone(), vec![0, 1, 2, 3, 4, 0, 1, 2, 3, 4], 5);
+        let bytes = write_indexed_png(&image).unwrap();
+
+        let decoded = read_png(&bytes).unwrap();
+        assert_eq!((decoded.width, decoded.height), (5, 2));
+        assert_eq!(decoded.layout, PixelLayout::Rgba);
+        let expected: Vec<u8> = [0, 1, 2, 3, 4, 0, 1, 2, 3, 4]
+            .iter()
+            .flat_map(|&i| palette[i])
+            .collect();
+        assert_eq!(decoded.pixels, expected);
+
+        // Two translucent entries moved to the front, so tRNS is two bytes long
+        let reader = ::png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.bit_depth, ::png::BitDepth::Four);
+        assert_eq!(info.trns.as_deref(), Some(&[0, 128][..]));
+    }
+
+    #[test]
+    fn test_transparent_background_survives_quantizing() {
+        // A transparent background, an opaque black outline and a red fill
+        let mut pixels = Vec::new();
+        pixels.extend([0, 0, 0, 0].repeat(40));
+        pixels.extend([0, 0, 0, 255].repeat(20));
+        pixels.extend((0..40).flat_map(|i| [200 + i, 0, 0, 255]));
+
+        for seed in 0..3 {
+            let cruncher = block_on(
+                ColorCruncherBuilder::new()
+                    .with_channels(4)
+                    .with_width(10)
+                    .with_max_colors(3)
+                    .with_seed(seed)
+                    .build(),
+            )
+            .unwrap();
+            let image = block_on(cruncher.quantize_indexed(&pixels)).unwrap();
+            let decoded = read_png(&write_indexed_png(&image).unwrap()).unwrap();
+
+            let alpha: Vec<u8> = decoded.pixels.chunks_exact(4).map(|p| p[3]).collect();
+            assert_eq!(alpha[..40], [0; 40], "seed {}", seed);
+            assert_eq!(alpha[40..], [255; 60], "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_opaque_two_color_png_is_one_bit_without_trns() {
+        let image = indexed(
+            vec![[0, 0, 0, 255], [255, 255, 255, 255]],
+            vec![0, 1, 1, 0, 1, 0, 0, 1, 1],
+            9,
+        );
+        let bytes = write_indexed_png(&image).unwrap();
+        let reader = ::png::Decoder::new(&bytes[..]).read_info().unwrap();
+        assert_eq!(reader.info().bit_depth, ::png::BitDepth::One);
+        assert!(reader.info().trns.is_none());
+
+        let decoded = read_png(&bytes).unwrap();
+        assert_eq!(decoded.layout, PixelLayout::Rgb);
+        assert_eq!(&decoded.pixels[..6], &[0, 0, 0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_reads_16_bit_gray_as_8_bit() {
+        let mut bytes = Vec::new();
+        let mut encoder = ::png::Encoder::new(&mut bytes, 2, 1);
+        encoder.set_color(::png::ColorType::Grayscale);
+        encoder.set_depth(::png::BitDepth::Sixteen);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[0x12, 0x34, 0xAB, 0xCD])
+            .unwrap();
+
+        let decoded = read_png(&bytes).unwrap();
+        assert_eq!(decoded.layout, PixelLayout::Gray);
+        assert_eq!(decoded.pixels, vec![0x12, 0xAB]);
+    }
+
+    #[test]
+    fn test_rejects_out_of_range_indices() {
+        let image = indexed(vec![[0, 0, 0, 255]], vec![0, 1], 2);
+        assert!(write_indexed_png(&image).is_err());
+    }
+}
@@ -0,0 +1,415 @@
+use super::{
+    check_image, check_indexed, image_size, invalid_image, pack_rows, read_u16_le, rgba_pixels,
+    Image,
+};
+use crate::error::ColorCruncherError;
+use crate::pixel_layout::PixelLayout;
+use crate::quantize::IndexedImage;
+
+const HEADER_SIZE: usize = 18;
+// Image descriptor bit for rows stored top to bottom
+const TOP_TO_BOTTOM: u8 = 0x20;
+const RIGHT_TO_LEFT: u8 = 0x10;
+// Image types; RLE compressed variants add 8
+const COLOR_MAPPED: u8 = 1;
+const TRUECOLOR: u8 = 2;
+const GRAYSCALE: u8 = 3;
+const RLE: u8 = 8;
+
+// Reads color-mapped, truecolor and grayscale TGAs, uncompressed or RLE. 32-bit colors
+// keep their alpha, 15 and 16-bit colors are expanded to 8 bits per channel and their
+// attribute bit is ignored. 16-bit grayscale is read as gray plus alpha.
+pub fn read_tga(bytes: &[u8]) -> Result<Image, ColorCruncherError> {
+    let header = bytes
+        .get(..HEADER_SIZE)
+        .ok_or_else(|| invalid_image("TGA header is cut short"))?;
+    let id_length = header[0] as usize;
+    let has_color_map = header[1] == 1;
+    let image_type = header[2];
+    let map_first = read_u16_le(header, 3)? as usize;
+    let map_length = read_u16_le(header, 5)? as usize;
+    let map_depth = header[7];
+    let width = read_u16_le(header, 12)? as usize;
+    let height = read_u16_le(header, 14)? as usize;
+    let depth = header[16];
+    let descriptor = header[17];
+
+    let kind = image_type & !RLE;
+    let pixel_bytes = (depth as usize).div_ceil(8);
+    match (kind, depth) {
+        (COLOR_MAPPED, 8) | (TRUECOLOR, 15 | 16 | 24 | 32) | (GRAYSCALE, 8 | 16) => {}
+        _ => {
+            return Err(invalid_image(format!(
+                "Unsupported TGA image type {} with {} bits per pixel",
+                image_type, depth
+            )))
+        }
+    }
+
+    let map_start = HEADER_SIZE + id_length;
+    let entry_bytes = (map_depth as usize).div_ceil(8);
+    let map_bytes = if has_color_map {
+        map_length * entry_bytes
+    } else {
+        0
+    };
+    let color_map = match kind {
+        COLOR_MAPPED => {
+            if !has_color_map || !matches!(map_depth, 15 | 16 | 24 | 32) {
+                return Err(invalid_image("Color-mapped TGA without a usable color map"));
+            }
+            bytes
+                .get(map_start..map_start + map_bytes)
+                .ok_or_else(|| invalid_image("TGA color map is cut short"))?
+                .chunks_exact(entry_bytes)
+                .map(color)
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+
+    let data = bytes.get(map_start + map_bytes..).unwrap_or_default();
+    let size = image_size(width, height, pixel_bytes)?;
+    let raw = if image_type & RLE != 0 {
+        decode_rle(data, size, pixel_bytes)?
+    } else {
+        data.get(..size)
+            .ok_or_else(|| invalid_image("TGA pixel data is cut short"))?
+            .to_vec()
+    };
+
+    let (layout, pixels): (_, Vec<u8>) = match kind {
+        GRAYSCALE if depth == 8 => (PixelLayout::Gray, raw),
+        GRAYSCALE => (PixelLayout::GrayAlpha, raw),
+        COLOR_MAPPED => {
+            let layout = rgb_layout(map_depth);
+            let mut pixels = Vec::with_capacity(width * height * layout.channels());
+            for &index in &raw {
+                let entry = (index as usize)
+                    .checked_sub(map_first)
+                    .and_then(|i| color_map.get(i))
+                    .ok_or_else(|| {
+                        invalid_image(format!("Index {} is not in the color map", index))
+                    })?;
+                layout.extend_from_rgba(&mut pixels, *entry);
+            }
+            (layout, pixels)
+        }
+        _ => {
+            let layout = rgb_layout(depth);
+            let mut pixels = Vec::with_capacity(width * height * layout.channels());
+            for pixel in raw.chunks_exact(pixel_bytes) {
+                layout.extend_from_rgba(&mut pixels, color(pixel));
+            }
+            (layout, pixels)
+        }
+    };
+
+    let mut image = Image {
+        width,
+        height,
+        layout,
+        pixels,
+    };
+    reorient(&mut image, descriptor);
+    Ok(image)
+}
+
+// Uncompressed or RLE truecolor TGA. Gray images are written as grayscale, gray with
+// alpha as 16-bit grayscale, RGB as 24-bit and everything else as 32-bit with alpha.
+pub fn write_tga(image: &Image, rle: bool) -> Result<Vec<u8>, ColorCruncherError> {
+    check_image(image)?;
+    let (kind, depth, pixels) = match image.layout {
+        PixelLayout::Gray => (GRAYSCALE, 8, image.pixels.clone()),
+        PixelLayout::GrayAlpha => (GRAYSCALE, 16, image.pixels.clone()),
+        PixelLayout::Rgb => (
+            TRUECOLOR,
+            24,
+            image
+                .pixels
+                .chunks_exact(3)
+                .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+                .collect(),
+        ),
+        PixelLayout::Rgba | PixelLayout::Bgra | PixelLayout::Argb => (
+            TRUECOLOR,
+            32,
+            rgba_pixels(image)
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+                .collect(),
+        ),
+    };
+
+    let alpha_bits = if image.layout.has_alpha() { 8 } else { 0 };
+    let mut bytes = header(image.width, image.height, kind, rle, depth, alpha_bits)?;
+    write_pixels(
+        &mut bytes,
+        &pixels,
+        image.width * depth as usize / 8,
+        depth,
+        rle,
+    );
+    Ok(bytes)
+}
+
+// Color-mapped TGA with 8-bit indices. The color map is 24-bit, or 32-bit when any
+// palette entry is translucent.
+pub fn write_indexed_tga(image: &IndexedImage, rle: bool) -> Result<Vec<u8>, ColorCruncherError> {
+    check_indexed(image, 256, "TGA")?;
+    let palette = &image.palette;
+    let translucent = palette.iter().any(|color| color[3] != u8::MAX);
+    let map_depth = if translucent { 32 } else { 24 };
+
+    let mut bytes = header(
+        image.width,
+        image.height,
+        COLOR_MAPPED,
+        rle,
+        8,
+        if translucent { 8 } else { 0 },
+    )?;
+    bytes[1] = 1;
+    bytes[5..7].copy_from_slice(&(palette.len() as u16).to_le_bytes());
+    bytes[7] = map_depth;
+    for color in palette {
+        bytes.extend_from_slice(&[color[2], color[1], color[0]]);
+        if translucent {
+            bytes.push(color[3]);
+        }
+    }
+
+    let identity: Vec<u8> = (0..palette.len()).map(|i| i as u8).collect();
+    let indices = pack_rows(image, &identity, 8, 1)?;
+    write_pixels(&mut bytes, &indices, image.width, 8, rle);
+    Ok(bytes)
+}
+
+// Header for an image stored top to bottom, without an ID or color map
+fn header(
+    width: usize,
+    height: usize,
+    kind: u8,
+    rle: bool,
+    depth: u8,
+    alpha_bits: u8,
+) -> Result<Vec<u8>, ColorCruncherError> {
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(invalid_image(format!(
+            "TGAs are at most 65535 pixels on a side, got {}x{}",
+            width, height
+        )));
+    };
+    let mut bytes = vec![0; HEADER_SIZE];
+    bytes[2] = if rle { kind | RLE } else { kind };
+    bytes[12..14].copy_from_slice(&width.to_le_bytes());
+    bytes[14..16].copy_from_slice(&height.to_le_bytes());
+    bytes[16] = depth;
+    bytes[17] = TOP_TO_BOTTOM | alpha_bits;
+    Ok(bytes)
+}
+
+fn write_pixels(bytes: &mut Vec<u8>, pixels: &[u8], row_bytes: usize, depth: u8, rle: bool) {
+    if !rle {
+        bytes.extend_from_slice(pixels);
+        return;
+    }
+    // Packets stay within a row, as the spec recommends
+    for row in pixels.chunks(row_bytes.max(1)) {
+        encode_rle_row(bytes, row, depth as usize / 8);
+    }
+}
+
+// A packet header's low 7 bits hold one less than the pixel count. With the high bit set
+// one pixel follows and is repeated, otherwise that many literal pixels follow.
+fn encode_rle_row(bytes: &mut Vec<u8>, row: &[u8], pixel_bytes: usize) {
+    let pixels: Vec<&[u8]> = row.chunks_exact(pixel_bytes).collect();
+    let mut i = 0;
+    while i < pixels.len() {
+        let run = pixels[i..]
+            .iter()
+            .take(128)
+            .take_while(|&&pixel| pixel == pixels[i])
+            .count();
+        if run > 1 {
+            bytes.push(0x80 | (run - 1) as u8);
+            bytes.extend_from_slice(pixels[i]);
+            i += run;
+            continue;
+        }
+
+        // Literal pixels up to where the next run starts
+        let mut end = i + 1;
+        while end < pixels.len() && end - i < 128 && pixels.get(end + 1) != Some(&pixels[end]) {
+            end += 1;
+        }
+        bytes.push((end - i - 1) as u8);
+        for pixel in &pixels[i..end] {
+            bytes.extend_from_slice(pixel);
+        }
+        i = end;
+    }
+}
+
+fn decode_rle(data: &[u8], size: usize, pixel_bytes: usize) -> Result<Vec<u8>, ColorCruncherError> {
+    // Every packet is at least one byte plus a pixel and covers at most 128 pixels, which
+    // bounds what a file can claim before anything is allocated
+    if size / pixel_bytes > data.len().saturating_mul(128) {
+        return Err(invalid_image("TGA pixel data is cut short"));
+    }
+    let mut pixels = Vec::with_capacity(size);
+    let mut position = 0;
+    while pixels.len() < size {
+        let packet = *data
+            .get(position)
+            .ok_or_else(|| invalid_image("TGA pixel data is cut short"))?;
+        position += 1;
+        let count = (packet & 0x7F) as usize + 1;
+        let literal_bytes = if packet & 0x80 != 0 {
+            pixel_bytes
+        } else {
+            count * pixel_bytes
+        };
+        let literal = data
+            .get(position..position + literal_bytes)
+            .ok_or_else(|| invalid_image("TGA pixel data is cut short"))?;
+        position += literal_bytes;
+        if packet & 0x80 != 0 {
+            for _ in 0..count {
+                pixels.extend_from_slice(literal);
+            }
+        } else {
+            pixels.extend_from_slice(literal);
+        }
+    }
+    // The last packet may run past the end of the image
+    pixels.truncate(size);
+    Ok(pixels)
+}
+
+// One stored color as RGBA: 15/16-bit ARRRRRGGGGGBBBBB, 24-bit BGR or 32-bit BGRA
+fn color(bytes: &[u8]) -> [u8; 4] {
+    match *bytes {
+        [low, high] => {
+            let value = u16::from_le_bytes([low, high]);
+            let expand = |shift: u16| {
+                let channel = ((value >> shift) & 0x1F) as u8;
+                (channel << 3) | (channel >> 2)
+            };
+            [expand(10), expand(5), expand(0), u8::MAX]
+        }
+        [b, g, r] => [r, g, b, u8::MAX],
+        [b, g, r, a] => [r, g, b, a],
+        _ => unreachable!("colors are 2 to 4 bytes"),
+    }
+}
+
+fn rgb_layout(depth: u8) -> PixelLayout {
+    if depth == 32 {
+        PixelLayout::Rgba
+    } else {
+        PixelLayout::Rgb
+    }
+}
+
+// Flips the image into top to bottom, left to right order
+fn reorient(image: &mut Image, descriptor: u8) {
+    let row_bytes = image.width * image.layout.channels();
+    if row_bytes == 0 {
+        return;
+    }
+    if descriptor & TOP_TO_BOTTOM == 0 {
+        image.pixels = image
+            .pixels
+            .chunks_exact(row_bytes)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+    }
+    if descriptor & RIGHT_TO_LEFT != 0 {
+        let channels = image.layout.channels();
+        for row in image.pixels.chunks_exact_mut(row_bytes) {
+            let mut pixels: Vec<&[u8]> = row.chunks_exact(channels).collect();
+            pixels.reverse();
+            let flipped: Vec<u8> = pixels.concat();
+            row.copy_from_slice(&flipped);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::IndexBuffer;
+
+    fn rgba_image() -> Image {
+        Image {
+            width: 3,
+            height: 2,
+            layout: PixelLayout::Rgba,
+            pixels: [
+                [1, 2, 3, 255],
+                [1, 2, 3, 255],
+                [9, 8, 7, 0],
+                [4, 5, 6, 128],
+                [4, 5, 6, 128],
+                [4, 5, 6, 128],
+            ]
+            .concat(),
+        }
+    }
+
+    #[test]
+    fn test_truecolor_round_trip_with_and_without_rle() {
+        let image = rgba_image();
+        let raw = write_tga(&image, false).unwrap();
+        let rle = write_tga(&image, true).unwrap();
+        assert_eq!(raw[2], TRUECOLOR);
+        assert_eq!(rle[2], TRUECOLOR | RLE);
+        assert!(rle.len() < raw.len());
+        assert_eq!(read_tga(&raw).unwrap(), image);
+        assert_eq!(read_tga(&rle).unwrap(), image);
+    }
+
+    #[test]
+    fn test_indexed_round_trip() {
+        let palette = vec![[255, 0, 0, 255], [0, 0, 255, 255]];
+        let indexed = IndexedImage {
+            palette: palette.clone(),
+            indices: IndexBuffer::U8(vec![0, 0, 0, 1, 0, 1]),
+            width: 3,
+            height: 2,
+        };
+        for rle in [false, true] {
+            let bytes = write_indexed_tga(&indexed, rle).unwrap();
+            assert_eq!(bytes[7], 24);
+            let decoded = read_tga(&bytes).unwrap();
+            assert_eq!(decoded.layout, PixelLayout::Rgb);
+            assert_eq!(&decoded.pixels[9..15], &[0, 0, 255, 255, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_reads_bottom_up_16_bit_rows() {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[2] = TRUECOLOR;
+        bytes[12] = 1;
+        bytes[14] = 2;
+        bytes[16] = 16;
+        // Bottom row pure red, top row pure blue
+        bytes.extend_from_slice(&0x7C00u16.to_le_bytes());
+        bytes.extend_from_slice(&0x001Fu16.to_le_bytes());
+
+        let image = read_tga(&bytes).unwrap();
+        assert_eq!(image.layout, PixelLayout::Rgb);
+        assert_eq!(image.pixels, vec![0, 0, 255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn test_rejects_truncated_rle() {
+        let mut bytes = write_tga(&rgba_image(), true).unwrap();
+        bytes.truncate(bytes.len() - 2);
+        assert!(read_tga(&bytes).is_err());
+    }
+}
@@ -3,6 +3,7 @@ pub mod distance;
 pub mod hamerly;
 pub mod initializer;
 pub mod lloyd;
+mod search;
 mod types;
 mod utils;
 
@@ -13,12 +14,14 @@ use self::gpu::run_lloyd_gpu;
 
 pub use crate::kmeans::config::{KMeansAlgorithm, KMeansConfig};
 pub use crate::kmeans::initializer::Initializer;
+pub use crate::kmeans::search::{KCriterion, KRun};
 pub use crate::kmeans::utils::find_closest_centroid;
 use crate::utils::num_distinct_colors;
 
 use crate::types::{Vec3, Vec4, Vec4u, VectorExt};
 
-use self::types::{KMeansError, KMeansResult};
+pub use self::types::{KMeansFit, KMeansResult};
+use crate::error::ColorCruncherError;
 
 const DEFAULT_INITIALIZER: Initializer = Initializer::KMeansPlusPlus;
 
@@ -56,6 +59,20 @@ impl KMeans {
         self.0.seed = Some(seed);
         self
     }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.0.seed
+    }
+
+    pub fn with_initializer(mut self, initializer: Initializer) -> Self {
+        self.0.initializer = initializer;
+        self
+    }
+
+    pub fn with_fixed_centroids(mut self, fixed_centroids: Vec<Vec4>) -> Self {
+        self.0.fixed_centroids = fixed_centroids;
+        self
+    }
 }
 
 impl Default for KMeans {
@@ -67,43 +84,89 @@ impl Default for KMeans {
             algorithm: KMeansAlgorithm::Lloyd,
             initializer: DEFAULT_INITIALIZER,
             seed: None,
+            fixed_centroids: Vec::new(),
         })
     }
 }
 
 impl KMeans {
-    pub fn run<T: VectorExt>(&self, data: &[T]) -> KMeansResult<T> {
-        let unique_colors = num_distinct_colors(data);
-        if unique_colors < self.0.k {
-            return Err(KMeansError(format!(
-                "Number of unique colors is less than k: {}",
-                unique_colors
+    fn check_fixed_centroids(&self) -> Result<(), ColorCruncherError> {
+        if self.0.fixed_centroids.len() > self.0.k {
+            return Err(ColorCruncherError::InvalidConfig(format!(
+                "number of fixed centroids is greater than k: {} > {}",
+                self.0.fixed_centroids.len(),
+                self.0.k
             )));
         }
+        Ok(())
+    }
 
-        match self.0.algorithm {
-            KMeansAlgorithm::Lloyd => Ok(lloyd::kmeans_lloyd(data, &self.0)),
-            KMeansAlgorithm::Hamerly => Ok(hamerly::kmeans_hamerly(data, &self.0)),
-            #[cfg(feature = "gpu")]
-            _ => Err(KMeansError(format!(
-                "Algorithm not supported on cpu: {}",
-                self.0.algorithm
-            ))),
+    pub fn run<T: VectorExt>(&self, data: &[T]) -> KMeansResult<T> {
+        let fit = self.fit(data)?;
+        Ok((fit.assignments, fit.centroids))
+    }
+
+    // Like run, but also reports the inertia and number of iterations
+    pub fn fit<T: VectorExt>(&self, data: &[T]) -> Result<KMeansFit<T>, ColorCruncherError> {
+        self.check_fixed_centroids()?;
+
+        // Fixed centroids don't need any data to seed them
+        let requested = self.0.k - self.0.fixed_centroids.len();
+        let unique_colors = num_distinct_colors(data, requested);
+        if unique_colors < requested {
+            return Err(ColorCruncherError::TooFewColors {
+                requested,
+                available: unique_colors,
+            });
         }
+
+        let (_, centroids, iterations) = match self.0.algorithm {
+            KMeansAlgorithm::Lloyd => lloyd::kmeans_lloyd(data, &self.0),
+            KMeansAlgorithm::Hamerly => hamerly::kmeans_hamerly(data, &self.0),
+            #[cfg(feature = "gpu")]
+            _ => {
+                return Err(ColorCruncherError::InvalidConfig(format!(
+                    "algorithm not supported on cpu: {}",
+                    self.0.algorithm
+                )))
+            }
+        };
+        // The algorithms' own assignments are from before the last centroid update
+        let assignments = utils::assign(data, &centroids);
+        Ok(KMeansFit {
+            inertia: utils::inertia(data, &assignments, &centroids),
+            assignments,
+            centroids,
+            iterations,
+        })
     }
+
     pub async fn run_async(&self, data: &[Vec4u]) -> KMeansResult<Vec4> {
+        let fit = self.fit_async(data).await?;
+        Ok((fit.assignments, fit.centroids))
+    }
+
+    // Like run_async, but also reports the inertia and number of iterations
+    pub async fn fit_async(&self, data: &[Vec4u]) -> Result<KMeansFit<Vec4>, ColorCruncherError> {
+        self.check_fixed_centroids()?;
+
+        let points = data
+            .iter()
+            .map(|v| [v[0] as f32, v[1] as f32, v[2] as f32, v[3] as f32])
+            .collect::<Vec<Vec4>>();
         match &self.0.algorithm {
-            KMeansAlgorithm::Lloyd | KMeansAlgorithm::Hamerly => {
-                let data = data
-                    .iter()
-                    .map(|v| [v[0] as f32, v[1] as f32, v[2] as f32, v[3] as f32])
-                    .collect::<Vec<Vec4>>();
-                self.run(&data)
-            }
+            KMeansAlgorithm::Lloyd | KMeansAlgorithm::Hamerly => self.fit(&points),
             #[cfg(feature = "gpu")]
-            _ => run_lloyd_gpu(self.0.clone(), data)
-                .await
-                .map_err(|e| KMeansError(e.to_string())),
+            _ => {
+                let (_, centroids, iterations) = run_lloyd_gpu(self.0.clone(), data).await?;
+                let assignments = utils::assign(&points, &centroids);
+                Ok(KMeansFit {
+                    inertia: utils::inertia(&points, &assignments, &centroids),
+                    assignments,
+                    centroids,
+                    iterations,
+                })
+            }
         }
     }
 }
@@ -118,7 +181,7 @@ impl KMeans {
             KMeansAlgorithm::Lloyd => self.run(data),
             KMeansAlgorithm::Hamerly => self.run(data),
             #[cfg(feature = "gpu")]
-            _ => Err(KMeansError(
+            _ => Err(ColorCruncherError::InvalidConfig(
                 "GPU not supported for vec4 float data. Convert to u8 data first.".to_string(),
             )),
         }
@@ -129,7 +192,7 @@ impl KMeans {
             KMeansAlgorithm::Lloyd => self.run(data),
             KMeansAlgorithm::Hamerly => self.run(data),
             #[cfg(feature = "gpu")]
-            _ => Err(KMeansError(
+            _ => Err(ColorCruncherError::InvalidConfig(
                 "GPU not supported for 3 channel data. Convert to 4 channel data first."
                     .to_string(),
             )),
@@ -179,6 +242,7 @@ mod tests {
                 algorithm,
                 initializer: DEFAULT_INITIALIZER,
                 seed: None,
+                fixed_centroids: Vec::new(),
             };
 
             let kmeans = KMeans::from_config(config.clone());
@@ -238,12 +302,16 @@ mod tests {
             algorithm: KMeansAlgorithm::Lloyd,
             initializer: DEFAULT_INITIALIZER,
             seed: None,
+            fixed_centroids: Vec::new(),
         };
         let kmeans = KMeans::from_config(config);
         let result = kmeans.run(&data);
         assert_eq!(
-            result.err().unwrap().to_string(),
-            "Number of unique colors is less than k: 2"
+            result.err().unwrap(),
+            ColorCruncherError::TooFewColors {
+                requested: 3,
+                available: 2
+            }
         );
     }
 
@@ -252,13 +320,14 @@ mod tests {
         let seed = 42;
         let data_size = 100;
 
+        // Whole-number channels so the GPU (which takes u32 pixels) sees the exact same data
         let mut rng = StdRng::seed_from_u64(seed);
         let data = (0..data_size)
             .map(|_| {
                 [
-                    rng.gen::<f32>() * 255.0,
-                    rng.gen::<f32>() * 255.0,
-                    rng.gen::<f32>() * 255.0,
+                    rng.gen_range(0..=255) as f32,
+                    rng.gen_range(0..=255) as f32,
+                    rng.gen_range(0..=255) as f32,
                     0.0,
                 ]
             })
@@ -271,6 +340,7 @@ mod tests {
             algorithm: KMeansAlgorithm::Lloyd,
             initializer: DEFAULT_INITIALIZER,
             seed: Some(seed),
+            fixed_centroids: Vec::new(),
         };
 
         let config_hamerly = KMeansConfig {
@@ -280,6 +350,7 @@ mod tests {
             algorithm: KMeansAlgorithm::Hamerly,
             initializer: DEFAULT_INITIALIZER,
             seed: Some(seed),
+            fixed_centroids: Vec::new(),
         };
 
         #[cfg(feature = "gpu")]
@@ -290,6 +361,7 @@ mod tests {
             algorithm: KMeansAlgorithm::LloydGpu,
             initializer: DEFAULT_INITIALIZER,
             seed: Some(seed),
+            fixed_centroids: Vec::new(),
         };
 
         let kmeans_lloyd = KMeans::from_config(config_lloyd);
@@ -311,9 +383,133 @@ mod tests {
             dbg!(&centroids3);
 
             centroids1.assert_almost_eq(&centroids3, 1.0);
+            assert_eq!(clusters1, clusters3);
         }
 
         centroids1.assert_almost_eq(&centroids2, 1.0);
         assert_eq!(clusters1, clusters2);
     }
+
+    #[test]
+    fn test_fixed_centroids_stay_put() {
+        let fixed = vec![[0.0, 0.0, 0.0, 255.0], [255.0, 0.0, 0.0, 255.0]];
+        let mut rng = StdRng::seed_from_u64(7);
+        let data: Vec<Vec4> = (0..200)
+            .map(|_| {
+                [
+                    rng.gen_range(0..=255) as f32,
+                    rng.gen_range(0..=255) as f32,
+                    rng.gen_range(0..=255) as f32,
+                    255.0,
+                ]
+            })
+            .collect();
+        let u32_data: Vec<Vec4u> = data
+            .iter()
+            .map(|p| [p[0] as u32, p[1] as u32, p[2] as u32, p[3] as u32])
+            .collect();
+
+        let algorithms = vec![
+            KMeansAlgorithm::Lloyd,
+            KMeansAlgorithm::Hamerly,
+            #[cfg(feature = "gpu")]
+            KMeansAlgorithm::LloydGpu,
+        ];
+
+        for algorithm in algorithms {
+            for initializer in [Initializer::KMeansPlusPlus, Initializer::Random] {
+                let kmeans = KMeans::default()
+                    .with_k(5)
+                    .with_algorithm(algorithm.clone())
+                    .with_seed(7)
+                    .with_fixed_centroids(fixed.clone());
+                let kmeans = KMeans(KMeansConfig {
+                    initializer: initializer.clone(),
+                    ..kmeans.0
+                });
+
+                let (_, centroids) = block_on(kmeans.run_async(&u32_data)).unwrap();
+                assert_eq!(centroids.len(), 5, "{algorithm} with {initializer:?}");
+                assert_eq!(
+                    centroids[..2],
+                    fixed[..],
+                    "{algorithm} with {initializer:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_fit_reports_inertia_and_iterations() {
+        let data: Vec<Vec3> = (0..60)
+            .map(|i| {
+                let base = if i % 2 == 0 { 20.0 } else { 220.0 };
+                [base + (i % 7) as f32, base, base - (i % 5) as f32]
+            })
+            .collect();
+
+        for algorithm in [KMeansAlgorithm::Lloyd, KMeansAlgorithm::Hamerly] {
+            let kmeans = KMeans::default()
+                .with_k(2)
+                .with_seed(42)
+                .with_algorithm(algorithm.clone());
+            let fit = kmeans.fit(&data).unwrap();
+
+            let expected: f32 = data
+                .iter()
+                .zip(&fit.assignments)
+                .map(|(point, &cluster)| {
+                    let centroid = fit.centroids[cluster];
+                    (0..3)
+                        .map(|c| (point[c] - centroid[c]).powi(2))
+                        .sum::<f32>()
+                })
+                .sum();
+            assert!((fit.inertia - expected).abs() < 1e-2, "{algorithm}");
+            assert!((1..=100).contains(&fit.iterations), "{algorithm}");
+        }
+    }
+
+    #[test]
+    fn test_assignments_match_the_returned_centroids() {
+        let data: Vec<Vec3> = (0..200)
+            .map(|i| {
+                [
+                    (i * 37 % 256) as f32,
+                    (i * 91 % 256) as f32,
+                    (i * 53 % 256) as f32,
+                ]
+            })
+            .collect();
+
+        for algorithm in [KMeansAlgorithm::Lloyd, KMeansAlgorithm::Hamerly] {
+            // Stopped long before converging
+            let kmeans = KMeans::default()
+                .with_k(8)
+                .with_seed(3)
+                .with_max_iterations(1)
+                .with_algorithm(algorithm.clone());
+            let fit = kmeans.fit(&data).unwrap();
+
+            for (point, &cluster) in data.iter().zip(&fit.assignments) {
+                assert_eq!(
+                    cluster,
+                    find_closest_centroid(point, &fit.centroids),
+                    "{algorithm}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_more_fixed_centroids_than_k() {
+        let data = vec![[255.0, 0.0, 0.0], [0.0, 255.0, 0.0]];
+        let kmeans = KMeans::default()
+            .with_k(1)
+            .with_fixed_centroids(vec![[0.0; 4], [255.0; 4]]);
+        assert_eq!(
+            kmeans.run(&data).err().unwrap().to_string(),
+            "Invalid configuration: number of fixed centroids is greater than k: 2 > 1"
+        );
+    }
 }
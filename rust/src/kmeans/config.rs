@@ -1,4 +1,5 @@
 use crate::kmeans::initializer::Initializer;
+use crate::types::Vec4;
 use std::fmt;
 
 #[derive(Debug, Clone)]
@@ -23,6 +24,8 @@ pub struct KMeansConfig {
     pub algorithm: KMeansAlgorithm,
     pub initializer: Initializer,
     pub seed: Option<u64>,
+    // Centroids that stay put. They take up the first indices of the result and count towards k.
+    pub fixed_centroids: Vec<Vec4>,
 }
 
 impl Default for KMeansConfig {
@@ -34,6 +37,7 @@ impl Default for KMeansConfig {
             algorithm: KMeansAlgorithm::Lloyd,
             initializer: Initializer::KMeansPlusPlus,
             seed: None,
+            fixed_centroids: Vec::new(),
         }
     }
 }
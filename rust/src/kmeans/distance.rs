@@ -9,11 +9,10 @@ use std::ops::MulAssign;
 use std::ops::Sub;
 use std::ops::SubAssign;
 
+// Over every component, so RGBA colors that only differ in alpha are still apart
 #[inline]
 pub fn euclidean_distance_squared<T: VectorExt>(a: &T, b: &T) -> SquaredEuclideanDistance {
-    SquaredEuclideanDistance(
-        f32::powi(a[0] - b[0], 2) + f32::powi(a[1] - b[1], 2) + f32::powi(a[2] - b[2], 2),
-    )
+    SquaredEuclideanDistance((0..T::LEN).map(|i| f32::powi(a[i] - b[i], 2)).sum())
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
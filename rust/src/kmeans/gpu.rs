@@ -9,14 +9,15 @@ use crate::types::{Vec4, Vec4u};
 
 use self::lloyd_gpu1::LloydAssignmentsOnly;
 
-use super::types::KMeansError;
+use crate::error::ColorCruncherError;
 
+// Returns the assignments, the centroids and how many iterations ran
 pub async fn run_lloyd_gpu(
     config: KMeansConfig,
     data: &[Vec4u],
-) -> Result<(Vec<usize>, Vec<Vec4>), KMeansError> {
-    let lloyd_gpu = LloydAssignmentsOnly::from_config(config).await;
-    lloyd_gpu.run_async(data).await
+) -> Result<(Vec<usize>, Vec<Vec4>, usize), ColorCruncherError> {
+    let lloyd_gpu = LloydAssignmentsOnly::from_config(config).await?;
+    lloyd_gpu.fit_async(data).await
 }
 
 #[cfg(test)]
@@ -59,7 +60,7 @@ mod tests {
         };
 
         // Run the GPU algorithm
-        let (assignments, centroids) = block_on(run_lloyd_gpu(config, &data)).unwrap();
+        let (assignments, centroids, _) = block_on(run_lloyd_gpu(config, &data)).unwrap();
 
         // Basic sanity checks
         assert_eq!(assignments.len(), N);
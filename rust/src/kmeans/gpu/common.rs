@@ -1,12 +1,15 @@
+use crate::error::ColorCruncherError;
 use wgpu::{Adapter, Device, DeviceDescriptor, Instance, Queue, RequestAdapterOptions};
 
-pub async fn common_wgpu_setup() -> Result<(Instance, Adapter, Device, Queue), String> {
+pub async fn common_wgpu_setup() -> Result<(Instance, Adapter, Device, Queue), ColorCruncherError> {
     let instance = wgpu::Instance::default();
 
     let adapter = instance
         .request_adapter(&RequestAdapterOptions::default())
         .await
-        .expect("Failed to request adapter");
+        .ok_or_else(|| {
+            ColorCruncherError::GpuUnavailable("no suitable adapter found".to_string())
+        })?;
 
     let (device, queue) = adapter
         .request_device(
@@ -18,6 +21,6 @@ pub async fn common_wgpu_setup() -> Result<(Instance, Adapter, Device, Queue), S
             None,
         )
         .await
-        .expect("Failed to request device");
+        .map_err(|e| ColorCruncherError::GpuUnavailable(e.to_string()))?;
     Ok((instance, adapter, device, queue))
 }
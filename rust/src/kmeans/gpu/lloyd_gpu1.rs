@@ -1,7 +1,8 @@
 use super::buffers::MappableBuffer;
 use super::common::common_wgpu_setup;
+use crate::error::ColorCruncherError;
 use crate::kmeans::types::KMeansResult;
-use crate::kmeans::utils::has_converged;
+use crate::kmeans::utils::{fixed_centroids, has_converged, is_fixed};
 use crate::kmeans::KMeansConfig;
 use crate::types::VectorExt;
 use crate::types::{Vec4, Vec4u};
@@ -17,6 +18,8 @@ use wgpu::{
 const WORKGROUP_SIZE: u32 = 256;
 
 struct ProcessBuffers {
+    // Only held so the buffer lives as long as the bind group that references it
+    #[allow(dead_code)]
     pixel_buffer: Buffer,
     centroid_buffer: Buffer,
     assignment_buffer: MappableBuffer,
@@ -29,6 +32,7 @@ pub struct LloydAssignmentsOnly {
     queue: Queue,
     compute_pipeline: ComputePipeline,
     bind_group_layout: BindGroupLayout,
+    #[allow(dead_code)]
     pipeline_layout: PipelineLayout,
     config: KMeansConfig,
 }
@@ -37,6 +41,7 @@ impl LloydAssignmentsOnly {
     // useful because the initialization function is big
     // and we don't want to recompile the shader
     // every time we change the number of clusters
+    #[allow(dead_code)]
     pub fn set_k(&mut self, k: usize) {
         self.config.k = k;
     }
@@ -80,42 +85,42 @@ impl LloydAssignmentsOnly {
         .to_vec();
 
         device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("kmeans_bind_group_layout".into()),
+            label: Some("kmeans_bind_group_layout"),
             entries: &entries,
         })
     }
 
-    pub async fn from_config(config: KMeansConfig) -> Self {
-        let (_, _, device, queue) = common_wgpu_setup().await.unwrap();
+    pub async fn from_config(config: KMeansConfig) -> Result<Self, ColorCruncherError> {
+        let (_, _, device, queue) = common_wgpu_setup().await?;
         let bind_group_layout = Self::make_bind_group_layout(&device);
 
         let shader_module = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("kmeans_shader".into()),
+            label: Some("kmeans_shader"),
             source: ShaderSource::Wgsl(include_str!("lloyd_gpu1.wgsl").into()),
         });
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("kmeans_pipeline_layout".into()),
+            label: Some("kmeans_pipeline_layout"),
             bind_group_layouts: &[&bind_group_layout],
             push_constant_ranges: &[],
         });
 
         let compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: Some("kmeans_compute_pipeline".into()),
+            label: Some("kmeans_compute_pipeline"),
             layout: Some(&pipeline_layout),
             module: &shader_module,
             entry_point: "main",
             compilation_options: PipelineCompilationOptions::default(),
         });
 
-        Self {
+        Ok(Self {
             device,
             queue,
             compute_pipeline,
             bind_group_layout,
             pipeline_layout,
             config,
-        }
+        })
     }
 
     fn prepare_buffers(
@@ -204,11 +209,26 @@ impl LloydAssignmentsOnly {
         })
     }
 
+    #[allow(dead_code)]
     pub fn run(&self, pixels: &[Vec4u]) -> KMeansResult<Vec4> {
         block_on(self.run_async(pixels))
     }
 
     pub async fn run_async(&self, pixels: &[Vec4u]) -> KMeansResult<Vec4> {
+        let (assignments, centroids, _) = self.fit_async(pixels).await?;
+        Ok((assignments, centroids))
+    }
+
+    // Like run_async, but also returns how many iterations ran
+    pub async fn fit_async(
+        &self,
+        pixels: &[Vec4u],
+    ) -> Result<(Vec<usize>, Vec<Vec4>, usize), ColorCruncherError> {
+        // wgpu won't bind zero-sized buffers, and there's nothing to cluster anyway
+        if pixels.is_empty() {
+            return Ok((vec![], vec![], 0));
+        }
+
         let vec4_pixels: Vec<Vec4> = pixels
             .iter()
             .map(|v| [v[0] as f32, v[1] as f32, v[2] as f32, v[3] as f32])
@@ -217,22 +237,27 @@ impl LloydAssignmentsOnly {
             &vec4_pixels,
             self.config.k,
             self.config.seed,
+            &fixed_centroids(&self.config),
         );
 
         let mut assignments: Vec<u32> = vec![0; pixels.len()];
 
         let process_buffers = self
             .prepare_buffers(pixels, &centroids, &assignments)
-            .unwrap();
+            .map_err(|e| ColorCruncherError::GpuFailure(e.to_string()))?;
 
         let mut iterations = 0;
 
         while iterations < self.config.max_iterations {
-            let (new_assignments, new_centroids) =
-                self.run_iteration(&pixels, &process_buffers).await?;
+            let (new_assignments, new_centroids) = self
+                .run_iteration(pixels, &centroids, &process_buffers)
+                .await
+                .map_err(|e| ColorCruncherError::GpuFailure(e.to_string()))?;
+            iterations += 1;
 
             if has_converged(&centroids, &new_centroids, self.config.tolerance) {
                 centroids = new_centroids;
+                assignments = new_assignments;
                 break;
             }
 
@@ -243,19 +268,19 @@ impl LloydAssignmentsOnly {
             );
             centroids = new_centroids;
             assignments = new_assignments;
-
-            iterations += 1;
         }
 
         Ok((
             assignments.into_iter().map(|a| a as usize).collect(),
             centroids,
+            iterations,
         ))
     }
 
     async fn run_iteration(
         &self,
         pixels: &[Vec4u],
+        centroids: &[Vec4],
         process_buffers: &ProcessBuffers,
     ) -> Result<(Vec<u32>, Vec<Vec4>), &'static str> {
         let mut encoder = self
@@ -264,7 +289,7 @@ impl LloydAssignmentsOnly {
 
         // This should probably be a variable we can configure
         // but it requires templating the shader, which I don't want to do yet.
-        let num_workgroups = (pixels.len() as u32 + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+        let num_workgroups = (pixels.len() as u32).div_ceil(WORKGROUP_SIZE);
 
         {
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
@@ -287,12 +312,17 @@ impl LloydAssignmentsOnly {
             .assignment_buffer
             .read_back(&self.device)
             .await?;
-        let new_centroids = self.get_new_centroids(pixels, &assignments);
+        let new_centroids = self.get_new_centroids(pixels, &assignments, centroids);
 
         Ok((assignments, new_centroids))
     }
 
-    fn get_new_centroids(&self, pixels: &[Vec4u], assignments: &[u32]) -> Vec<Vec4> {
+    fn get_new_centroids(
+        &self,
+        pixels: &[Vec4u],
+        assignments: &[u32],
+        centroids: &[Vec4],
+    ) -> Vec<Vec4> {
         let mut new_centroids: Vec<Vec4> = vec![];
 
         let mut centroid_sums: Vec<Vec4> = vec![[0.0; 4]; self.config.k];
@@ -306,8 +336,14 @@ impl LloydAssignmentsOnly {
             ]);
             centroid_counts[*assignment as usize] += 1;
         }
-        for (centroid_sum, centroid_count) in centroid_sums.iter().zip(centroid_counts.iter()) {
-            new_centroids.push(centroid_sum.div_scalar(*centroid_count as f32));
+        for (j, (centroid_sum, centroid_count)) in
+            centroid_sums.iter().zip(centroid_counts.iter()).enumerate()
+        {
+            if is_fixed(&self.config, j) {
+                new_centroids.push(centroids[j]);
+            } else {
+                new_centroids.push(centroid_sum.div_scalar(*centroid_count as f32));
+            }
         }
         new_centroids
     }
@@ -330,6 +366,7 @@ mod tests {
             algorithm: KMeansAlgorithm::LloydGpu,
             initializer: Initializer::Random,
             seed: Some(42),
+            fixed_centroids: Vec::new(),
         }
     }
 
@@ -348,8 +385,8 @@ mod tests {
             [22, 22, 22, 22],
         ];
 
-        let kmeans = block_on(LloydAssignmentsOnly::from_config(config));
-        let (assignments, centroids) = block_on(kmeans.run_async(&pixels)).unwrap();
+        let kmeans = block_on(LloydAssignmentsOnly::from_config(config)).unwrap();
+        let (assignments, _) = block_on(kmeans.run_async(&pixels)).unwrap();
 
         assert_eq!(assignments.len(), pixels.len());
 
@@ -376,6 +413,7 @@ mod tests {
             algorithm: KMeansAlgorithm::LloydGpu,
             initializer: Initializer::Random,
             seed: Some(42),
+            fixed_centroids: Vec::new(),
         };
 
         let pixels: Vec<Vec4u> = vec![
@@ -387,8 +425,8 @@ mod tests {
             [12, 12, 12, 12],
         ];
 
-        let kmeans = block_on(LloydAssignmentsOnly::from_config(config));
-        let (assignments, centroids) = block_on(kmeans.run_async(&pixels)).unwrap();
+        let kmeans = block_on(LloydAssignmentsOnly::from_config(config)).unwrap();
+        let (assignments, _) = block_on(kmeans.run_async(&pixels)).unwrap();
 
         assert_eq!(assignments.len(), pixels.len());
 
@@ -405,7 +443,7 @@ mod tests {
         let config = create_test_config();
         let pixels: Vec<Vec4u> = vec![];
 
-        let kmeans = block_on(LloydAssignmentsOnly::from_config(config));
+        let kmeans = block_on(LloydAssignmentsOnly::from_config(config)).unwrap();
         let (assignments, centroids) = block_on(kmeans.run_async(&pixels)).unwrap();
 
         assert_eq!(assignments.len(), 0);
@@ -417,7 +455,7 @@ mod tests {
         let mut config = create_test_config();
         config.k = 15;
 
-        let kmeans = block_on(LloydAssignmentsOnly::from_config(config.clone()));
+        let kmeans = block_on(LloydAssignmentsOnly::from_config(config.clone())).unwrap();
         let mut rng = thread_rng();
 
         let image_size = 2000;
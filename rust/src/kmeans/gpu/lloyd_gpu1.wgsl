@@ -1,5 +1,5 @@
-@group(0) @binding(0) var<storage, read> image: array<vec3<u32>>;
-@group(0) @binding(1) var<storage, read> centers: array<vec3<f32>>;
+@group(0) @binding(0) var<storage, read> image: array<vec4<u32>>;
+@group(0) @binding(1) var<storage, read> centers: array<vec4<f32>>;
 @group(0) @binding(2) var<storage, read_write> assignments: array<u32>;
 
 const WORKGROUP_SIZE = 256;
@@ -13,7 +13,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
     let pixel = image[idx];
 
-    let fpixel = vec3<f32>(pixel);
+    let fpixel = vec4<f32>(pixel);
     var min_dist = distance(fpixel, centers[0]);
     var min_center = 0u;
 
@@ -28,7 +28,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     assignments[idx] = min_center;
 }
 
-fn distance(a: vec3<f32>, b: vec3<f32>) -> f32 {
+fn distance(a: vec4<f32>, b: vec4<f32>) -> f32 {
     let diff = a - b;
     return dot(diff, diff);
 }
\ No newline at end of file
@@ -3,17 +3,18 @@ use crate::kmeans::distance::{
     euclidean_distance_squared, EuclideanDistance, SquaredEuclideanDistance,
 };
 use crate::kmeans::types::{Assignments, CentroidCounts, CentroidSums, Centroids};
-use crate::kmeans::utils::has_converged;
+use crate::kmeans::utils::{fixed_centroids, has_converged, is_fixed};
 use crate::types::VectorExt;
 use itertools::izip;
 
 type UpperBounds = Vec<EuclideanDistance>;
 type LowerBounds = Vec<EuclideanDistance>;
 
+// Returns the assignments, the centroids and how many iterations ran
 pub fn kmeans_hamerly<T: VectorExt>(
     data: &[T],
     config: &KMeansConfig,
-) -> (Assignments, Centroids<T>) {
+) -> (Assignments, Centroids<T>, usize) {
     let (
         mut centroids,
         mut centroid_sums,
@@ -35,7 +36,9 @@ pub fn kmeans_hamerly<T: VectorExt>(
     // If the compiler is smart enough, that is.
     assert!(num_pixels >= k);
 
-    for _ in 0..config.max_iterations {
+    let mut iterations = 0;
+    while iterations < config.max_iterations {
+        iterations += 1;
         compute_neighbor_distances(&centroids, &mut centroid_neighbor_distances);
 
         for (pixel, assigned_cluster, upper_bound, lower_bound) in
@@ -68,6 +71,7 @@ pub fn kmeans_hamerly<T: VectorExt>(
 
         // Move centroids into new_centroids (we swap later)
         move_centroids(
+            config,
             &mut centroids,
             &mut new_centroids,
             &mut centroid_sums,
@@ -90,7 +94,7 @@ pub fn kmeans_hamerly<T: VectorExt>(
             &clusters,
         )
     }
-    (clusters.to_vec(), centroids.to_vec())
+    (clusters.to_vec(), centroids.to_vec(), iterations)
 }
 
 fn initialize_hamerly<T: VectorExt>(
@@ -105,9 +109,12 @@ fn initialize_hamerly<T: VectorExt>(
     Assignments,
 ) {
     // indicex of the cluster each pixel belongs to
-    let centroids = config
-        .initializer
-        .initialize_centroids(data, config.k, config.seed);
+    let centroids = config.initializer.initialize_centroids(
+        data,
+        config.k,
+        config.seed,
+        &fixed_centroids(config),
+    );
 
     let num_pixels = data.len();
     let mut clusters = vec![0; num_pixels];
@@ -221,6 +228,7 @@ fn compute_neighbor_distances<T: VectorExt>(centroids: &[T], distances: &mut [Eu
 }
 
 fn move_centroids<T: VectorExt>(
+    config: &KMeansConfig,
     centroids: &mut [T],
     new_centroids: &mut [T],
     centroid_sums: &mut [T],
@@ -230,6 +238,11 @@ fn move_centroids<T: VectorExt>(
     for (j, (current_centroid, new_centroid)) in
         centroids.iter().zip(new_centroids.iter_mut()).enumerate()
     {
+        if is_fixed(config, j) {
+            *new_centroid = *current_centroid;
+            centroid_move_distances[j] = EuclideanDistance(0.0);
+            continue;
+        }
         *new_centroid = centroid_sums[j].div_scalar(centroid_counts[j] as f32);
         // We need to square root here because the bounds check assumes true distances.
         centroid_move_distances[j] =
@@ -1,6 +1,6 @@
 use crate::kmeans::distance::euclidean_distance_squared;
 use crate::kmeans::distance::SquaredEuclideanDistance;
-use crate::types::VectorExt;
+use crate::types::{Vec4, VectorExt};
 use rand::prelude::*;
 use rand::SeedableRng;
 
@@ -8,6 +8,9 @@ use rand::SeedableRng;
 pub enum Initializer {
     KMeansPlusPlus,
     Random,
+    // Warm start from an existing palette. Extra entries are dropped and missing ones
+    // are seeded with k-means++.
+    FromPalette(Vec<Vec4>),
 }
 
 impl Initializer {
@@ -16,14 +19,39 @@ impl Initializer {
         data: &[T],
         k: usize,
         seed: Option<u64>,
+        fixed: &[T],
     ) -> Vec<T> {
         match self {
-            Initializer::KMeansPlusPlus => kmeans_plus_plus(data, k, seed),
-            Initializer::Random => initialize_random(data, k, seed),
+            Initializer::KMeansPlusPlus => kmeans_plus_plus(data, k, seed, fixed),
+            Initializer::Random => initialize_random(data, k, seed, fixed),
+            Initializer::FromPalette(palette) => from_palette(data, k, seed, fixed, palette),
         }
     }
 }
 
+fn from_palette<T: VectorExt>(
+    data: &[T],
+    k: usize,
+    seed: Option<u64>,
+    fixed: &[T],
+    palette: &[Vec4],
+) -> Vec<T> {
+    let mut centroids = fixed.to_vec();
+
+    // A palette from an earlier run will already contain the fixed colors
+    for color in palette.iter().map(T::from_vec4) {
+        let is_duplicate = fixed
+            .iter()
+            .any(|f| euclidean_distance_squared(f, &color).0 == 0.0);
+        if !is_duplicate {
+            centroids.push(color);
+        }
+    }
+    centroids.truncate(k);
+
+    kmeans_plus_plus(data, k, seed, &centroids)
+}
+
 fn get_seedable_rng(seed: Option<u64>) -> StdRng {
     if let Some(seed) = seed {
         rand::rngs::StdRng::seed_from_u64(seed)
@@ -34,16 +62,22 @@ fn get_seedable_rng(seed: Option<u64>) -> StdRng {
 
 // Ok we're using the K-Means++ initialization
 // I think this is right? Seems to work
-fn kmeans_plus_plus<T: VectorExt>(data: &[T], k: usize, seed: Option<u64>) -> Vec<T> {
+// Fixed centroids count as already chosen, so the rest get seeded away from them.
+fn kmeans_plus_plus<T: VectorExt>(data: &[T], k: usize, seed: Option<u64>, fixed: &[T]) -> Vec<T> {
     let mut centroids = Vec::with_capacity(k);
+    centroids.extend_from_slice(fixed);
 
     // Seed the RNG if provided, otherwise use the current time
     let mut rng = get_seedable_rng(seed);
 
     // Choose the first centroid randomly
-    if let Some(first_centroid) = data.choose(&mut rng) {
-        centroids.push(*first_centroid);
-    } else {
+    if centroids.is_empty() {
+        if let Some(first_centroid) = data.choose(&mut rng) {
+            centroids.push(*first_centroid);
+        } else {
+            return centroids;
+        }
+    } else if data.is_empty() {
         return centroids;
     }
 
@@ -76,7 +110,7 @@ fn kmeans_plus_plus<T: VectorExt>(data: &[T], k: usize, seed: Option<u64>) -> Ve
     centroids
 }
 
-pub fn initialize_random<T: Copy>(data: &[T], k: usize, seed: Option<u64>) -> Vec<T> {
+pub fn initialize_random<T: Copy>(data: &[T], k: usize, seed: Option<u64>, fixed: &[T]) -> Vec<T> {
     // Seed the RNG if provided, otherwise use the current time
     let mut rng = {
         if let Some(seed) = seed {
@@ -87,9 +121,59 @@ pub fn initialize_random<T: Copy>(data: &[T], k: usize, seed: Option<u64>) -> Ve
     };
 
     let mut centroids = Vec::with_capacity(k);
-    for centroid in data.choose_multiple(&mut rng, k) {
+    centroids.extend_from_slice(fixed);
+    for centroid in data.choose_multiple(&mut rng, k.saturating_sub(fixed.len())) {
         centroids.push(*centroid);
     }
 
     centroids
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Vec3;
+
+    #[test]
+    fn test_from_palette_uses_the_palette() {
+        let data: Vec<Vec3> = vec![[0.0, 0.0, 0.0], [100.0, 100.0, 100.0], [200.0, 0.0, 0.0]];
+        let palette = vec![[10.0, 20.0, 30.0, 255.0], [40.0, 50.0, 60.0, 255.0]];
+
+        let centroids =
+            Initializer::FromPalette(palette).initialize_centroids(&data, 2, Some(0), &[]);
+        assert_eq!(centroids, vec![[10.0, 20.0, 30.0], [40.0, 50.0, 60.0]]);
+    }
+
+    #[test]
+    fn test_from_palette_fills_and_truncates() {
+        let data: Vec<Vec3> = vec![[0.0, 0.0, 0.0], [100.0, 100.0, 100.0], [200.0, 0.0, 0.0]];
+        let palette = vec![[10.0, 20.0, 30.0, 255.0]];
+
+        let centroids =
+            Initializer::FromPalette(palette.clone()).initialize_centroids(&data, 3, Some(0), &[]);
+        assert_eq!(centroids.len(), 3);
+        assert_eq!(centroids[0], [10.0, 20.0, 30.0]);
+
+        let centroids = Initializer::FromPalette(palette).initialize_centroids(
+            &data,
+            1,
+            Some(0),
+            &[[0.0, 0.0, 0.0]],
+        );
+        assert_eq!(centroids, vec![[0.0, 0.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_from_palette_skips_fixed_colors() {
+        let data: Vec<Vec3> = vec![[0.0, 0.0, 0.0], [100.0, 100.0, 100.0]];
+        let palette = vec![[0.0, 0.0, 0.0, 255.0], [90.0, 90.0, 90.0, 255.0]];
+
+        let centroids = Initializer::FromPalette(palette).initialize_centroids(
+            &data,
+            2,
+            Some(0),
+            &[[0.0, 0.0, 0.0]],
+        );
+        assert_eq!(centroids, vec![[0.0, 0.0, 0.0], [90.0, 90.0, 90.0]]);
+    }
+}
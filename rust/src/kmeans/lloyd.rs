@@ -1,11 +1,18 @@
 use crate::kmeans::config::KMeansConfig;
-use crate::kmeans::utils::{find_closest_centroid, has_converged};
+use crate::kmeans::utils::{find_closest_centroid, fixed_centroids, has_converged, is_fixed};
 use crate::types::VectorExt;
 
-pub fn kmeans_lloyd<T: VectorExt>(data: &[T], config: &KMeansConfig) -> (Vec<usize>, Vec<T>) {
-    let mut centroids = config
-        .initializer
-        .initialize_centroids(data, config.k, config.seed);
+// Returns the assignments, the centroids and how many iterations ran
+pub fn kmeans_lloyd<T: VectorExt>(
+    data: &[T],
+    config: &KMeansConfig,
+) -> (Vec<usize>, Vec<T>, usize) {
+    let mut centroids = config.initializer.initialize_centroids(
+        data,
+        config.k,
+        config.seed,
+        &fixed_centroids(config),
+    );
     let mut new_centroids: Vec<T> = centroids.clone();
 
     let mut clusters = vec![Vec::new(); config.k];
@@ -32,26 +39,16 @@ pub fn kmeans_lloyd<T: VectorExt>(data: &[T], config: &KMeansConfig) -> (Vec<usi
         clusters
             .iter()
             .zip(new_centroids.iter_mut())
-            .for_each(|(cluster, new_centroid)| {
-                if cluster.is_empty() {
-                    return; // centroid can't move if there are no points
+            .enumerate()
+            .for_each(|(j, (cluster, new_centroid))| {
+                if cluster.is_empty() || is_fixed(config, j) {
+                    return; // centroid can't move if there are no points or it's fixed
                 }
 
-                let mut sum_r = 0.0;
-                let mut sum_g = 0.0;
-                let mut sum_b = 0.0;
-                let num_pixels = cluster.len() as f32;
-
-                for &idx in cluster {
-                    let pixel = &data[idx];
-                    sum_r += pixel[0];
-                    sum_g += pixel[1];
-                    sum_b += pixel[2];
-                }
-
-                new_centroid[0] = sum_r / num_pixels;
-                new_centroid[1] = sum_g / num_pixels;
-                new_centroid[2] = sum_b / num_pixels;
+                let sum = cluster
+                    .iter()
+                    .fold(T::zero(), |sum, &idx| sum.add(&data[idx]));
+                *new_centroid = sum.div_scalar(cluster.len() as f32);
             });
         converged = has_converged(&centroids, &new_centroids, config.tolerance);
         // Swap the centroids and new_centroid. We'll update the new centroids again before
@@ -60,5 +57,5 @@ pub fn kmeans_lloyd<T: VectorExt>(data: &[T], config: &KMeansConfig) -> (Vec<usi
         iterations += 1;
     }
 
-    (assignments, centroids)
+    (assignments, centroids, iterations)
 }
@@ -0,0 +1,325 @@
+use crate::error::ColorCruncherError;
+use crate::kmeans::distance::euclidean_distance_squared;
+use crate::kmeans::types::KMeansResult;
+use crate::kmeans::utils::{self, find_closest_centroid};
+use crate::kmeans::{Initializer, KMeans};
+use crate::types::{Vec4, Vec4u};
+use crate::utils::num_distinct_colors_u32;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+// Silhouette is quadratic in the number of points, so it only looks at this many
+const SILHOUETTE_SAMPLES: usize = 1000;
+// BIC stops growing k after this many steps without a new best score
+const BIC_PATIENCE: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KCriterion {
+    // The k where inertia stops dropping sharply
+    Elbow,
+    // Mean silhouette score on a sample of the data
+    Silhouette,
+    // X-means style: grow k while the Bayesian information criterion keeps improving
+    Bic,
+}
+
+impl fmt::Display for KCriterion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+// One finished k-means run inside a k search
+pub struct KRun<'a> {
+    pub k: usize,
+    pub data: &'a [Vec4],
+    pub assignments: &'a [usize],
+    pub centroids: &'a [Vec4],
+}
+
+impl KMeans {
+    // Runs k-means for each k in `ks`, warm-starting every run from the previous centroids.
+    // `visit` sees each run and returns false to stop early.
+    pub async fn search_k<F>(
+        &self,
+        data: &[Vec4u],
+        ks: RangeInclusive<usize>,
+        mut visit: F,
+    ) -> Result<(), ColorCruncherError>
+    where
+        F: FnMut(KRun) -> bool,
+    {
+        let points = to_points(data);
+        let mut kmeans = self.clone();
+        for k in ks {
+            kmeans = kmeans.with_k(k);
+            let (assignments, centroids) = kmeans.run_async(data).await?;
+            let run = KRun {
+                k,
+                data: &points,
+                assignments: &assignments,
+                centroids: &centroids,
+            };
+            if !visit(run) {
+                break;
+            }
+            kmeans.0.initializer = Initializer::FromPalette(centroids);
+        }
+        Ok(())
+    }
+
+    // Picks k between min and max (inclusive) with the criterion, and returns that run
+    pub async fn run_auto(
+        &self,
+        data: &[Vec4u],
+        min: usize,
+        max: usize,
+        criterion: KCriterion,
+    ) -> KMeansResult<Vec4> {
+        let fixed = self.0.fixed_centroids.len();
+        let min = min.max(fixed).max(1);
+        // k-means can't find more clusters than there are colors
+        let max = max
+            .min(fixed + num_distinct_colors_u32(data, max.saturating_sub(fixed)))
+            .max(min);
+
+        // Only centroids are kept per k, assignments are recomputed for the winner
+        let mut runs: Vec<(usize, f32, Vec<Vec4>)> = Vec::new();
+        let mut best_bic = f32::NEG_INFINITY;
+        let mut since_best = 0;
+        self.search_k(data, min..=max, |run| {
+            let score = match criterion {
+                KCriterion::Elbow => inertia(&run),
+                KCriterion::Silhouette => silhouette(&run),
+                KCriterion::Bic => bic(&run),
+            };
+            runs.push((run.k, score, run.centroids.to_vec()));
+
+            if criterion == KCriterion::Bic {
+                if score > best_bic {
+                    best_bic = score;
+                    since_best = 0;
+                } else {
+                    since_best += 1;
+                }
+                return since_best < BIC_PATIENCE;
+            }
+            true
+        })
+        .await?;
+
+        let chosen = match criterion {
+            KCriterion::Elbow => elbow(&runs),
+            KCriterion::Silhouette | KCriterion::Bic => runs
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.1.total_cmp(&b.1))
+                .map(|(i, _)| i)
+                .unwrap_or(0),
+        };
+        let centroids = runs.swap_remove(chosen).2;
+
+        let assignments = to_points(data)
+            .iter()
+            .map(|point| find_closest_centroid(point, &centroids))
+            .collect();
+        Ok((assignments, centroids))
+    }
+}
+
+fn to_points(data: &[Vec4u]) -> Vec<Vec4> {
+    data.iter()
+        .map(|v| [v[0] as f32, v[1] as f32, v[2] as f32, v[3] as f32])
+        .collect()
+}
+
+fn inertia(run: &KRun) -> f32 {
+    utils::inertia(run.data, run.assignments, run.centroids)
+}
+
+// The point furthest below the line joining the first and last (k, inertia) pairs,
+// with both axes scaled to 0-1
+fn elbow(runs: &[(usize, f32, Vec<Vec4>)]) -> usize {
+    let (Some(first), Some(last)) = (runs.first(), runs.last()) else {
+        return 0;
+    };
+    let k_range = (last.0 - first.0).max(1) as f32;
+    let inertia_range = (first.1 - last.1).max(f32::EPSILON);
+
+    let mut chosen = 0;
+    let mut max_gap = 0.0;
+    for (i, (k, inertia, _)) in runs.iter().enumerate() {
+        let x = (k - first.0) as f32 / k_range;
+        let y = (inertia - last.1) / inertia_range;
+        let gap = (1.0 - x) - y;
+        if gap > max_gap {
+            max_gap = gap;
+            chosen = i;
+        }
+    }
+    chosen
+}
+
+fn silhouette(run: &KRun) -> f32 {
+    let step = run.data.len().div_ceil(SILHOUETTE_SAMPLES).max(1);
+    let sample: Vec<(&Vec4, usize)> = run
+        .data
+        .iter()
+        .zip(run.assignments.iter().copied())
+        .step_by(step)
+        .collect();
+    if sample.is_empty() {
+        return 0.0;
+    }
+
+    let k = run.centroids.len();
+    let mut total = 0.0;
+    for &(point, cluster) in &sample {
+        let mut sums = vec![0.0; k];
+        let mut counts = vec![0usize; k];
+        for &(other, other_cluster) in &sample {
+            sums[other_cluster] += euclidean_distance_squared(point, other).0.sqrt();
+            counts[other_cluster] += 1;
+        }
+
+        // Singletons score 0 by convention, and so does everything when k is 1
+        if counts[cluster] <= 1 {
+            continue;
+        }
+        let a = sums[cluster] / (counts[cluster] - 1) as f32;
+        let b = (0..k)
+            .filter(|&c| c != cluster && counts[c] > 0)
+            .map(|c| sums[c] / counts[c] as f32)
+            .fold(f32::INFINITY, f32::min);
+        if b.is_finite() {
+            total += (b - a) / a.max(b).max(f32::EPSILON);
+        }
+    }
+    total / sample.len() as f32
+}
+
+// Pelleg & Moore's BIC for identical spherical Gaussians. Dimensions that never vary
+// (alpha in opaque images) are left out so they don't deflate the variance.
+fn bic(run: &KRun) -> f32 {
+    let n = run.data.len();
+    let k = run.centroids.len();
+    if n <= k {
+        return f32::NEG_INFINITY;
+    }
+    let dims = (0..4)
+        .filter(|&d| run.data.iter().any(|p| p[d] != run.data[0][d]))
+        .count()
+        .max(1) as f64;
+
+    let variance = (inertia(run) as f64 / (dims * (n - k) as f64)).max(f64::EPSILON);
+    let mut counts = vec![0usize; k];
+    for &cluster in run.assignments {
+        counts[cluster] += 1;
+    }
+
+    let n_f = n as f64;
+    let log_likelihood: f64 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let count = count as f64;
+            count * count.ln()
+                - count * n_f.ln()
+                - count * dims / 2.0 * (2.0 * std::f64::consts::PI * variance).ln()
+                - dims * (count - 1.0) / 2.0
+        })
+        .sum();
+    let parameters = (k - 1) as f64 + dims * k as f64 + 1.0;
+
+    (log_likelihood - parameters / 2.0 * n_f.ln()) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kmeans::KMeansConfig;
+    use futures::executor::block_on;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Three round blobs of 200 colors each
+    fn blobs() -> Vec<Vec4u> {
+        let centers = [
+            [40.0, 40.0, 200.0],
+            [200.0, 60.0, 40.0],
+            [90.0, 210.0, 90.0],
+        ];
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut gaussian = || {
+            // Box-Muller
+            let (u1, u2): (f32, f32) = (rng.gen_range(f32::EPSILON..1.0), rng.gen());
+            (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+        };
+
+        centers
+            .iter()
+            .flat_map(|center| {
+                (0..200)
+                    .map(|_| {
+                        let mut channel =
+                            |c: f32| (c + 6.0 * gaussian()).round().clamp(0.0, 255.0) as u32;
+                        [
+                            channel(center[0]),
+                            channel(center[1]),
+                            channel(center[2]),
+                            255,
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_criteria_find_the_blobs() {
+        let data = blobs();
+        let kmeans = KMeans::from_config(KMeansConfig {
+            seed: Some(42),
+            ..KMeansConfig::default()
+        });
+
+        for criterion in [KCriterion::Elbow, KCriterion::Silhouette, KCriterion::Bic] {
+            let (assignments, centroids) =
+                block_on(kmeans.run_auto(&data, 2, 8, criterion)).unwrap();
+            assert_eq!(centroids.len(), 3, "{criterion}");
+            assert_eq!(assignments.len(), data.len());
+        }
+    }
+
+    #[test]
+    fn test_search_k_stops_early() {
+        let kmeans = KMeans::from_config(KMeansConfig {
+            seed: Some(42),
+            ..KMeansConfig::default()
+        });
+
+        let mut seen = Vec::new();
+        block_on(kmeans.search_k(&blobs(), 2..=8, |run| {
+            assert_eq!(run.centroids.len(), run.k);
+            seen.push(run.k);
+            run.k < 4
+        }))
+        .unwrap();
+
+        assert_eq!(seen, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_max_is_capped_by_distinct_colors() {
+        let data: Vec<Vec4u> = (0..60).map(|i| [i % 3 * 100, 0, 0, 255]).collect();
+        let kmeans = KMeans::from_config(KMeansConfig {
+            seed: Some(42),
+            ..KMeansConfig::default()
+        });
+
+        for criterion in [KCriterion::Elbow, KCriterion::Silhouette, KCriterion::Bic] {
+            let (_, centroids) = block_on(kmeans.run_auto(&data, 2, 8, criterion)).unwrap();
+            assert!(centroids.len() <= 3, "{criterion}");
+        }
+    }
+}
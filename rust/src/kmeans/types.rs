@@ -1,25 +1,20 @@
+use crate::error::ColorCruncherError;
+
 // Some utility type aliases for readability
 pub type Centroids<T> = Vec<T>;
 pub type CentroidSums<T> = Vec<T>;
 pub type Assignments = Vec<usize>;
 pub type CentroidCounts = Vec<usize>;
 
-// Result
-pub type KMeansResult<T> = Result<(Assignments, Centroids<T>), KMeansError>;
-
+// A finished run with the stats scikit-learn style callers expect
 #[derive(Debug, Clone)]
-pub struct KMeansError(pub String);
-
-impl std::fmt::Display for KMeansError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
+pub struct KMeansFit<T> {
+    pub assignments: Assignments,
+    pub centroids: Centroids<T>,
+    // Sum of squared distances from each point to its centroid
+    pub inertia: f32,
+    pub iterations: usize,
 }
 
-impl From<&str> for KMeansError {
-    fn from(s: &str) -> Self {
-        KMeansError(s.to_string())
-    }
-}
-
-impl std::error::Error for KMeansError {}
+// Result
+pub type KMeansResult<T> = Result<(Assignments, Centroids<T>), ColorCruncherError>;
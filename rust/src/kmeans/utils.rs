@@ -1,5 +1,6 @@
 use crate::kmeans::distance::euclidean_distance_squared;
 use crate::kmeans::distance::SquaredEuclideanDistance;
+use crate::kmeans::KMeansConfig;
 use crate::types::VectorExt;
 
 // Return the index of closest centroid and distance to that centroid
@@ -17,6 +18,31 @@ pub fn find_closest_centroid<T: VectorExt>(pixel: &T, centroids: &[T]) -> usize
     min_index
 }
 
+// Each point's closest centroid
+pub fn assign<T: VectorExt>(data: &[T], centroids: &[T]) -> Vec<usize> {
+    data.iter()
+        .map(|point| find_closest_centroid(point, centroids))
+        .collect()
+}
+
+// Sum of squared distances from each point to its assigned centroid
+pub fn inertia<T: VectorExt>(data: &[T], assignments: &[usize], centroids: &[T]) -> f32 {
+    data.iter()
+        .zip(assignments)
+        .map(|(point, &cluster)| euclidean_distance_squared(point, &centroids[cluster]).0)
+        .sum()
+}
+
+pub fn fixed_centroids<T: VectorExt>(config: &KMeansConfig) -> Vec<T> {
+    config.fixed_centroids.iter().map(T::from_vec4).collect()
+}
+
+// Fixed centroids come first, so their index is all that's needed to tell them apart
+#[inline]
+pub fn is_fixed(config: &KMeansConfig, index: usize) -> bool {
+    index < config.fixed_centroids.len()
+}
+
 pub fn has_converged<T: VectorExt>(
     initial_centroids: &[T],
     final_centroids: &[T],
@@ -1,10 +1,20 @@
-#![feature(portable_simd)]
-
 #[cfg(feature = "python")]
 pub mod python;
 
+pub mod color;
+pub mod dither;
+pub mod error;
+pub mod gif;
+#[cfg(feature = "image-io")]
+pub mod image_io;
 pub mod kmeans;
+pub mod metrics;
+pub mod palette_io;
+pub mod palette_order;
+pub mod palettes;
+pub mod pixel_layout;
 pub mod quantize;
+pub mod sampling;
 pub mod types;
 mod utils;
 pub mod wasm;
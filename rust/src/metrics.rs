@@ -0,0 +1,407 @@
+use crate::color::{srgb_to_lab, WhitePoint};
+use crate::error::ColorCruncherError;
+use std::collections::HashMap;
+use std::fmt;
+
+// SSIM is averaged over square windows of this size, moved by half a window at a time
+const SSIM_WINDOW: usize = 8;
+const SSIM_STRIDE: usize = 4;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+// How close a quantized image is to its original
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityReport {
+    pub mse: f64,
+    // Infinite for identical images
+    pub psnr: f64,
+    pub ssim_gray: f64,
+    // One per channel, in buffer order
+    pub ssim_channels: Vec<f64>,
+    pub delta_e_mean: f64,
+    pub delta_e_max: f64,
+}
+
+impl fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MSE: {:.3}, PSNR: {:.2} dB, SSIM: {:.4}, ΔE mean: {:.3}, ΔE max: {:.3}",
+            self.mse, self.psnr, self.ssim_gray, self.delta_e_mean, self.delta_e_max
+        )
+    }
+}
+
+// A quality bar for target-quality quantization
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QualityTarget {
+    // PSNR of at least this many dB
+    MinPsnr(f64),
+    // Mean CIEDE2000 of at most this
+    MaxMeanDeltaE(f64),
+}
+
+impl QualityTarget {
+    pub fn is_met(
+        &self,
+        original: &[u8],
+        quantized: &[u8],
+        channels: usize,
+    ) -> Result<bool, ColorCruncherError> {
+        Ok(match self {
+            QualityTarget::MinPsnr(db) => psnr(original, quantized)? >= *db,
+            QualityTarget::MaxMeanDeltaE(delta_e_max) => {
+                delta_e(original, quantized, channels)?.0 <= *delta_e_max
+            }
+        })
+    }
+}
+
+impl fmt::Display for QualityTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QualityTarget::MinPsnr(db) => write!(f, "PSNR >= {} dB", db),
+            QualityTarget::MaxMeanDeltaE(delta_e) => write!(f, "mean ΔE <= {}", delta_e),
+        }
+    }
+}
+
+// Compares two buffers of the same layout, as passed to and returned from `quantize_image`
+pub fn compare(
+    original: &[u8],
+    quantized: &[u8],
+    channels: usize,
+    width: usize,
+) -> Result<QualityReport, ColorCruncherError> {
+    let mse = mse(original, quantized)?;
+    let (delta_e_mean, delta_e_max) = delta_e(original, quantized, channels)?;
+
+    Ok(QualityReport {
+        mse,
+        psnr: psnr_from_mse(mse),
+        ssim_gray: ssim_gray(original, quantized, channels, width)?,
+        ssim_channels: (0..channels)
+            .map(|channel| {
+                ssim(
+                    &plane(original, channels, |p| p[channel] as f64),
+                    &plane(quantized, channels, |p| p[channel] as f64),
+                    width,
+                )
+            })
+            .collect::<Result<_, _>>()?,
+        delta_e_mean,
+        delta_e_max,
+    })
+}
+
+fn check_lengths(original: usize, quantized: usize) -> Result<(), ColorCruncherError> {
+    if original != quantized {
+        return Err(ColorCruncherError::InvalidBuffer(format!(
+            "buffers differ in size: {} and {}",
+            original, quantized
+        )));
+    }
+    Ok(())
+}
+
+fn check_pixels(
+    original: &[u8],
+    quantized: &[u8],
+    channels: usize,
+) -> Result<(), ColorCruncherError> {
+    if channels == 0 {
+        return Err(ColorCruncherError::InvalidBuffer(
+            "channels must be at least 1".to_string(),
+        ));
+    }
+    check_lengths(original.len(), quantized.len())
+}
+
+// Over every byte, alpha included
+pub fn mse(original: &[u8], quantized: &[u8]) -> Result<f64, ColorCruncherError> {
+    check_lengths(original.len(), quantized.len())?;
+    if original.is_empty() {
+        return Ok(0.0);
+    }
+
+    let sum: f64 = original
+        .iter()
+        .zip(quantized)
+        .map(|(&a, &b)| {
+            let d = a as f64 - b as f64;
+            d * d
+        })
+        .sum();
+    Ok(sum / original.len() as f64)
+}
+
+pub fn psnr(original: &[u8], quantized: &[u8]) -> Result<f64, ColorCruncherError> {
+    mse(original, quantized).map(psnr_from_mse)
+}
+
+fn psnr_from_mse(mse: f64) -> f64 {
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+fn plane(pixels: &[u8], channels: usize, value: impl Fn(&[u8]) -> f64) -> Vec<f64> {
+    pixels.chunks_exact(channels).map(value).collect()
+}
+
+fn gray(pixel: &[u8]) -> f64 {
+    if pixel.len() < 3 {
+        return pixel[0] as f64;
+    }
+    0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64
+}
+
+// SSIM of the luma planes
+pub fn ssim_gray(
+    original: &[u8],
+    quantized: &[u8],
+    channels: usize,
+    width: usize,
+) -> Result<f64, ColorCruncherError> {
+    check_pixels(original, quantized, channels)?;
+    ssim(
+        &plane(original, channels, gray),
+        &plane(quantized, channels, gray),
+        width,
+    )
+}
+
+// Mean SSIM over windows of a single plane. Images smaller than a window use one window
+// covering the whole image.
+pub fn ssim(a: &[f64], b: &[f64], width: usize) -> Result<f64, ColorCruncherError> {
+    check_lengths(a.len(), b.len())?;
+    let width = width.max(1);
+    let height = a.len() / width;
+    if height == 0 {
+        return Ok(1.0);
+    }
+
+    let window_w = SSIM_WINDOW.min(width);
+    let window_h = SSIM_WINDOW.min(height);
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for y in (0..=height - window_h).step_by(SSIM_STRIDE) {
+        for x in (0..=width - window_w).step_by(SSIM_STRIDE) {
+            let n = (window_w * window_h) as f64;
+            let (mut sum_a, mut sum_b) = (0.0, 0.0);
+            for row in y..y + window_h {
+                for i in row * width + x..row * width + x + window_w {
+                    sum_a += a[i];
+                    sum_b += b[i];
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for row in y..y + window_h {
+                for i in row * width + x..row * width + x + window_w {
+                    let (da, db) = (a[i] - mean_a, b[i] - mean_b);
+                    var_a += da * da;
+                    var_b += db * db;
+                    covariance += da * db;
+                }
+            }
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+
+    Ok(total / windows as f64)
+}
+
+// Mean and max CIEDE2000 over all pixels, ignoring alpha. Lab conversions are cached
+// since quantized images reuse the same few colors.
+pub fn delta_e(
+    original: &[u8],
+    quantized: &[u8],
+    channels: usize,
+) -> Result<(f64, f64), ColorCruncherError> {
+    check_pixels(original, quantized, channels)?;
+    let mut cache: HashMap<[u8; 3], [f32; 3]> = HashMap::new();
+    let mut lab = |pixel: &[u8]| {
+        let rgb = if channels < 3 {
+            [pixel[0]; 3]
+        } else {
+            [pixel[0], pixel[1], pixel[2]]
+        };
+        *cache.entry(rgb).or_insert_with(|| {
+            srgb_to_lab(
+                [rgb[0] as f32, rgb[1] as f32, rgb[2] as f32],
+                WhitePoint::D65,
+            )
+        })
+    };
+
+    let mut sum = 0.0;
+    let mut max: f64 = 0.0;
+    let mut count = 0;
+    for (a, b) in original
+        .chunks_exact(channels)
+        .zip(quantized.chunks_exact(channels))
+    {
+        let difference = ciede2000(lab(a), lab(b));
+        sum += difference;
+        max = max.max(difference);
+        count += 1;
+    }
+
+    Ok(if count == 0 {
+        (0.0, 0.0)
+    } else {
+        (sum / count as f64, max)
+    })
+}
+
+// Sharma, Wu and Dalal's formulation of CIEDE2000
+pub fn ciede2000(lab1: [f32; 3], lab2: [f32; 3]) -> f64 {
+    let [l1, a1, b1] = lab1.map(|v| v as f64);
+    let [l2, a2, b2] = lab2.map(|v| v as f64);
+    let pow25_7 = 25f64.powi(7);
+
+    let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + pow25_7)).sqrt());
+    let (a1p, a2p) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1p, c2p) = ((a1p * a1p + b1 * b1).sqrt(), (a2p * a2p + b2 * b2).sqrt());
+
+    let hue = |b: f64, a: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let (h1p, h2p) = (hue(b1, a1p), hue(b2, a2p));
+    let chroma_product = c1p * c2p;
+
+    let dl = l2 - l1;
+    let dc = c2p - c1p;
+    let dh_angle = if chroma_product == 0.0 {
+        0.0
+    } else if (h2p - h1p).abs() <= 180.0 {
+        h2p - h1p
+    } else if h2p - h1p > 180.0 {
+        h2p - h1p - 360.0
+    } else {
+        h2p - h1p + 360.0
+    };
+    let dh = 2.0 * chroma_product.sqrt() * (dh_angle / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar_p = (c1p + c2p) / 2.0;
+    let h_bar = if chroma_product == 0.0 {
+        h1p + h2p
+    } else if (h1p - h2p).abs() <= 180.0 {
+        (h1p + h2p) / 2.0
+    } else if h1p + h2p < 360.0 {
+        (h1p + h2p + 360.0) / 2.0
+    } else {
+        (h1p + h2p - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar).to_radians().cos()
+        + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_bar_p.powi(7) / (c_bar_p.powi(7) + pow25_7)).sqrt();
+    let l_offset = (l_bar - 50.0) * (l_bar - 50.0);
+    let s_l = 1.0 + 0.015 * l_offset / (20.0 + l_offset).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar_p;
+    let s_h = 1.0 + 0.015 * c_bar_p * t;
+    let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+    let (l_term, c_term, h_term) = (dl / s_l, dc / s_c, dh / s_h);
+    (l_term * l_term + c_term * c_term + h_term * h_term + r_t * c_term * h_term).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_images() {
+        let image: Vec<u8> = (0..16 * 16 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let report = compare(&image, &image, 3, 16).unwrap();
+
+        assert_eq!(report.mse, 0.0);
+        assert_eq!(report.psnr, f64::INFINITY);
+        assert!((report.ssim_gray - 1.0).abs() < 1e-9);
+        assert!(report.ssim_channels.iter().all(|s| (s - 1.0).abs() < 1e-9));
+        assert_eq!((report.delta_e_mean, report.delta_e_max), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_mse_and_psnr() {
+        let a = vec![0, 0, 0, 10, 10, 10];
+        let b = vec![0, 0, 0, 0, 0, 0];
+        assert_eq!(mse(&a, &b).unwrap(), 50.0);
+        assert!((psnr(&a, &b).unwrap() - 31.1411).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_ssim_drops_with_noise() {
+        let image: Vec<u8> = (0..32 * 32).map(|i| ((i % 32) * 8) as u8).collect();
+        let flat = vec![128; image.len()];
+        let noisy: Vec<u8> = image
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| v.saturating_add((i * 13 % 20) as u8))
+            .collect();
+
+        let noisy_ssim = ssim_gray(&image, &noisy, 1, 32).unwrap();
+        let flat_ssim = ssim_gray(&image, &flat, 1, 32).unwrap();
+        assert!(noisy_ssim < 1.0 && noisy_ssim > flat_ssim);
+    }
+
+    #[test]
+    fn test_mismatched_buffers_are_rejected() {
+        let image = vec![0; 12];
+        assert!(matches!(
+            compare(&image, &image[..9], 3, 2),
+            Err(ColorCruncherError::InvalidBuffer(_))
+        ));
+        assert!(matches!(
+            compare(&image, &image, 0, 2),
+            Err(ColorCruncherError::InvalidBuffer(_))
+        ));
+        assert!(mse(&image, &image[..1]).is_err());
+        assert!(delta_e(&image, &image[..3], 3).is_err());
+        assert!(ssim(&[0.0; 4], &[0.0; 2], 2).is_err());
+    }
+
+    // Reference pairs from Sharma, Wu and Dalal's test data
+    #[test]
+    fn test_ciede2000_reference_pairs() {
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, 2.5, 0.0], [50.0, 0.0, -2.5], 4.3065),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+            (
+                [22.7233, 20.0904, -46.694],
+                [23.0331, 14.973, -42.5619],
+                2.0373,
+            ),
+        ];
+        for (lab1, lab2, expected) in pairs {
+            let actual = ciede2000(lab1, lab2);
+            assert!((actual - expected).abs() < 1e-3, "{actual} != {expected}");
+        }
+    }
+}
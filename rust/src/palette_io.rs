@@ -0,0 +1,444 @@
+mod ase;
+
+pub use self::ase::{
+    ase_colors, ase_from_palette, read_ase, write_ase, AseColor, AseColorType, AseEntry, Swatch,
+};
+use crate::error::ColorCruncherError;
+use crate::quantize::Palette;
+use std::fmt;
+
+// Adobe Color Table files always hold 256 RGB entries, optionally followed by a
+// big-endian color count and transparent index.
+const ACT_ENTRIES: usize = 256;
+const ACT_NO_TRANSPARENCY: u16 = 0xFFFF;
+
+fn invalid_palette(message: impl Into<String>) -> ColorCruncherError {
+    ColorCruncherError::InvalidPalette(message.into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+    // GIMP .gpl
+    Gpl,
+    // JASC-PAL (Paint Shop Pro) .pal
+    JascPal,
+    // Adobe Color Table .act
+    Act,
+    // Paint.NET .txt
+    PaintNet,
+    // One RRGGBB (or RRGGBBAA) per line, as used by Lospec .hex files
+    Hex,
+    // Adobe Swatch Exchange .ase
+    Ase,
+}
+
+impl PaletteFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "gpl" => Some(PaletteFormat::Gpl),
+            "pal" => Some(PaletteFormat::JascPal),
+            "act" => Some(PaletteFormat::Act),
+            "txt" => Some(PaletteFormat::PaintNet),
+            "hex" => Some(PaletteFormat::Hex),
+            "ase" => Some(PaletteFormat::Ase),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PaletteFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+// Palettes read from files have no pixel counts, so every count is zero. ASE groups
+// are flattened in file order.
+pub fn read_palette(bytes: &[u8], format: PaletteFormat) -> Result<Palette, ColorCruncherError> {
+    let colors = match format {
+        PaletteFormat::Gpl => read_gpl(as_text(bytes)?)?,
+        PaletteFormat::JascPal => read_jasc_pal(as_text(bytes)?)?,
+        PaletteFormat::Act => read_act(bytes)?,
+        PaletteFormat::PaintNet => read_paint_net(as_text(bytes)?)?,
+        PaletteFormat::Hex => read_hex(as_text(bytes)?)?,
+        PaletteFormat::Ase => ase_colors(&read_ase(bytes)?),
+    };
+    Ok(Palette::from_colors(colors))
+}
+
+// Formats without alpha drop it, except ACT which keeps the first fully transparent color
+// as its transparent index.
+pub fn write_palette(
+    palette: &Palette,
+    format: PaletteFormat,
+) -> Result<Vec<u8>, ColorCruncherError> {
+    let colors = &palette.colors;
+    match format {
+        PaletteFormat::Gpl => Ok(write_gpl(colors).into_bytes()),
+        PaletteFormat::JascPal => Ok(write_jasc_pal(colors).into_bytes()),
+        PaletteFormat::Act => write_act(colors),
+        PaletteFormat::PaintNet => Ok(write_paint_net(colors).into_bytes()),
+        PaletteFormat::Hex => Ok(write_hex(colors).into_bytes()),
+        PaletteFormat::Ase => Ok(write_ase(&ase_from_palette(palette))),
+    }
+}
+
+fn as_text(bytes: &[u8]) -> Result<&str, ColorCruncherError> {
+    std::str::from_utf8(bytes).map_err(|e| invalid_palette(format!("Palette is not text: {}", e)))
+}
+
+fn parse_channel(value: &str, line: &str) -> Result<u8, ColorCruncherError> {
+    value
+        .parse::<u8>()
+        .map_err(|_| invalid_palette(format!("Invalid color component in line: {}", line)))
+}
+
+fn parse_hex_byte(hex: &str, at: usize) -> Result<u8, ColorCruncherError> {
+    hex.get(at..at + 2)
+        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        .ok_or_else(|| invalid_palette(format!("Invalid hex color: {}", hex)))
+}
+
+fn to_hex(color: &[u8; 4]) -> String {
+    format!("{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+}
+
+pub fn read_gpl(text: &str) -> Result<Vec<[u8; 4]>, ColorCruncherError> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err(invalid_palette("Missing GIMP Palette header"));
+    }
+
+    let mut colors = Vec::new();
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.is_empty()
+            || trimmed.starts_with('#')
+            || trimmed.starts_with("Name:")
+            || trimmed.starts_with("Columns:")
+        {
+            continue;
+        }
+
+        // Anything after the three components is the color's name
+        let mut parts = trimmed.split_whitespace();
+        let mut channel = || match parts.next() {
+            Some(value) => parse_channel(value, line),
+            None => Err(invalid_palette(format!(
+                "Incomplete color in line: {}",
+                line
+            ))),
+        };
+        colors.push([channel()?, channel()?, channel()?, u8::MAX]);
+    }
+    Ok(colors)
+}
+
+pub fn write_gpl(colors: &[[u8; 4]]) -> String {
+    let mut text = String::from("GIMP Palette\nName: colorcruncher\nColumns: 0\n#\n");
+    for color in colors {
+        text.push_str(&format!(
+            "{:3} {:3} {:3}\t#{}\n",
+            color[0],
+            color[1],
+            color[2],
+            to_hex(color)
+        ));
+    }
+    text
+}
+
+pub fn read_jasc_pal(text: &str) -> Result<Vec<[u8; 4]>, ColorCruncherError> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next() != Some("JASC-PAL") {
+        return Err(invalid_palette("Missing JASC-PAL header"));
+    }
+    // Version, always 0100
+    lines.next();
+    let count = lines
+        .next()
+        .and_then(|count| count.parse::<usize>().ok())
+        .ok_or_else(|| invalid_palette("Missing JASC-PAL color count"))?;
+
+    let mut colors = Vec::with_capacity(count);
+    for line in lines.filter(|line| !line.is_empty()).take(count) {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 3 {
+            return Err(invalid_palette(format!(
+                "Incomplete color in line: {}",
+                line
+            )));
+        }
+        colors.push([
+            parse_channel(parts[0], line)?,
+            parse_channel(parts[1], line)?,
+            parse_channel(parts[2], line)?,
+            u8::MAX,
+        ]);
+    }
+
+    if colors.len() != count {
+        return Err(invalid_palette(format!(
+            "Expected {} colors, found {}",
+            count,
+            colors.len()
+        )));
+    }
+    Ok(colors)
+}
+
+pub fn write_jasc_pal(colors: &[[u8; 4]]) -> String {
+    let mut text = format!("JASC-PAL\r\n0100\r\n{}\r\n", colors.len());
+    for color in colors {
+        text.push_str(&format!("{} {} {}\r\n", color[0], color[1], color[2]));
+    }
+    text
+}
+
+pub fn read_act(bytes: &[u8]) -> Result<Vec<[u8; 4]>, ColorCruncherError> {
+    if bytes.len() < ACT_ENTRIES * 3 {
+        return Err(invalid_palette(format!(
+            "ACT files are at least {} bytes, got {}",
+            ACT_ENTRIES * 3,
+            bytes.len()
+        )));
+    }
+
+    let (count, transparent) = match bytes.get(ACT_ENTRIES * 3..ACT_ENTRIES * 3 + 4) {
+        Some(footer) => (
+            u16::from_be_bytes([footer[0], footer[1]]) as usize,
+            u16::from_be_bytes([footer[2], footer[3]]),
+        ),
+        None => (ACT_ENTRIES, ACT_NO_TRANSPARENCY),
+    };
+    // Some writers store 0 to mean "all of them"
+    let count = if count == 0 || count > ACT_ENTRIES {
+        ACT_ENTRIES
+    } else {
+        count
+    };
+
+    Ok(bytes[..count * 3]
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(i, rgb)| {
+            let alpha = if i == transparent as usize {
+                0
+            } else {
+                u8::MAX
+            };
+            [rgb[0], rgb[1], rgb[2], alpha]
+        })
+        .collect())
+}
+
+pub fn write_act(colors: &[[u8; 4]]) -> Result<Vec<u8>, ColorCruncherError> {
+    if colors.len() > ACT_ENTRIES {
+        return Err(invalid_palette(format!(
+            "ACT files hold at most {} colors, got {}",
+            ACT_ENTRIES,
+            colors.len()
+        )));
+    }
+
+    let mut bytes = vec![0; ACT_ENTRIES * 3 + 4];
+    for (entry, color) in bytes.chunks_exact_mut(3).zip(colors) {
+        entry.copy_from_slice(&color[..3]);
+    }
+
+    let transparent = colors
+        .iter()
+        .position(|color| color[3] == 0)
+        .map_or(ACT_NO_TRANSPARENCY, |i| i as u16);
+    let footer = ACT_ENTRIES * 3;
+    bytes[footer..footer + 2].copy_from_slice(&(colors.len() as u16).to_be_bytes());
+    bytes[footer + 2..footer + 4].copy_from_slice(&transparent.to_be_bytes());
+    Ok(bytes)
+}
+
+pub fn read_paint_net(text: &str) -> Result<Vec<[u8; 4]>, ColorCruncherError> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .map(|line| {
+            if line.len() != 8 {
+                return Err(invalid_palette(format!(
+                    "Expected AARRGGBB color, got: {}",
+                    line
+                )));
+            }
+            Ok([
+                parse_hex_byte(line, 2)?,
+                parse_hex_byte(line, 4)?,
+                parse_hex_byte(line, 6)?,
+                parse_hex_byte(line, 0)?,
+            ])
+        })
+        .collect()
+}
+
+pub fn write_paint_net(colors: &[[u8; 4]]) -> String {
+    let mut text = format!(";paint.net Palette File\n;Colors: {}\n", colors.len());
+    for color in colors {
+        text.push_str(&format!("{:02X}{}\n", color[3], to_hex(color)));
+    }
+    text
+}
+
+pub fn read_hex(text: &str) -> Result<Vec<[u8; 4]>, ColorCruncherError> {
+    text.lines()
+        .map(|line| line.trim().trim_start_matches('#'))
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.len() {
+            6 => Ok([
+                parse_hex_byte(line, 0)?,
+                parse_hex_byte(line, 2)?,
+                parse_hex_byte(line, 4)?,
+                u8::MAX,
+            ]),
+            8 => Ok([
+                parse_hex_byte(line, 0)?,
+                parse_hex_byte(line, 2)?,
+                parse_hex_byte(line, 4)?,
+                parse_hex_byte(line, 6)?,
+            ]),
+            _ => Err(invalid_palette(format!("Invalid hex color: {}", line))),
+        })
+        .collect()
+}
+
+// Opaque colors are written as RRGGBB, anything else as RRGGBBAA
+pub fn write_hex(colors: &[[u8; 4]]) -> String {
+    let mut text = String::new();
+    for color in colors {
+        text.push_str(&to_hex(color).to_ascii_lowercase());
+        if color[3] != u8::MAX {
+            text.push_str(&format!("{:02x}", color[3]));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::ColorCruncherBuilder;
+    use futures::executor::block_on;
+
+    fn test_palette() -> Palette {
+        Palette {
+            colors: vec![[0, 0, 0, 255], [255, 128, 7, 255], [18, 52, 86, 255]],
+            counts: vec![10, 5, 1],
+        }
+    }
+
+    #[test]
+    fn test_round_trip_all_formats() {
+        let palette = test_palette();
+        for format in [
+            PaletteFormat::Gpl,
+            PaletteFormat::JascPal,
+            PaletteFormat::Act,
+            PaletteFormat::PaintNet,
+            PaletteFormat::Hex,
+            PaletteFormat::Ase,
+        ] {
+            let bytes = write_palette(&palette, format).unwrap();
+            let read = read_palette(&bytes, format).unwrap();
+            assert_eq!(read.colors, palette.colors, "{format}");
+            assert_eq!(read.counts, vec![0; 3], "{format}");
+        }
+    }
+
+    #[test]
+    fn test_read_gpl_with_names_and_comments() {
+        let text = "GIMP Palette\nName: Test\nColumns: 4\n# a comment\n  0   0   0\tBlack\n255 255 255 White snow\n\n";
+        assert_eq!(
+            read_gpl(text).unwrap(),
+            vec![[0, 0, 0, 255], [255, 255, 255, 255]]
+        );
+    }
+
+    #[test]
+    fn test_act_transparency_footer() {
+        let colors = vec![[1, 2, 3, 255], [0, 0, 0, 0], [4, 5, 6, 255]];
+        let bytes = write_act(&colors).unwrap();
+        assert_eq!(bytes.len(), 772);
+        assert_eq!(bytes[768..], [0, 3, 0, 1]);
+        assert_eq!(read_act(&bytes).unwrap(), colors);
+    }
+
+    #[test]
+    fn test_act_without_footer_has_256_colors() {
+        let bytes = vec![7; 768];
+        let colors = read_act(&bytes).unwrap();
+        assert_eq!(colors.len(), 256);
+        assert_eq!(colors[0], [7, 7, 7, 255]);
+    }
+
+    #[test]
+    fn test_hex_and_paint_net_keep_alpha() {
+        let colors = vec![[255, 0, 0, 128], [0, 255, 0, 255]];
+        assert_eq!(write_hex(&colors), "ff000080\n00ff00\n");
+        assert_eq!(read_hex(&write_hex(&colors)).unwrap(), colors);
+        assert_eq!(read_paint_net(&write_paint_net(&colors)).unwrap(), colors);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(matches!(
+            read_gpl("not a palette"),
+            Err(ColorCruncherError::InvalidPalette(_))
+        ));
+        assert!(read_jasc_pal("JASC-PAL\n0100\n2\n0 0 0\n").is_err());
+        assert!(read_hex("12345").is_err());
+        assert!(read_act(&[0; 10]).is_err());
+        assert!(write_act(&vec![[0, 0, 0, 255]; 257]).is_err());
+    }
+
+    #[test]
+    fn test_ase_palette_as_fixed_and_locked_colors() {
+        let entries = vec![AseEntry::Group {
+            name: "Brand".to_string(),
+            swatches: vec![
+                Swatch {
+                    name: "Black".to_string(),
+                    color: AseColor::Cmyk([0.0, 0.0, 0.0, 1.0]),
+                    color_type: AseColorType::Spot,
+                },
+                Swatch {
+                    name: "Red".to_string(),
+                    color: AseColor::Rgb([1.0, 0.0, 0.0]),
+                    color_type: AseColorType::Global,
+                },
+            ],
+        }];
+        let palette = read_palette(&write_ase(&entries), PaletteFormat::Ase).unwrap();
+        assert_eq!(palette.colors, vec![[0, 0, 0, 255], [255, 0, 0, 255]]);
+
+        let data = vec![10, 0, 0, 255, 240, 10, 10, 255];
+        let fixed = block_on(
+            ColorCruncherBuilder::default()
+                .with_channels(4)
+                .with_palette(palette.colors.clone())
+                .build(),
+        )
+        .unwrap();
+        assert_eq!(
+            block_on(fixed.quantize_image(&data)).unwrap(),
+            vec![0, 0, 0, 255, 255, 0, 0, 255]
+        );
+
+        let locked = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_fixed_colors(palette.colors.clone())
+                .build(),
+        )
+        .unwrap();
+        let created = block_on(locked.create_palette(&data)).unwrap();
+        assert_eq!(created.colors[..2], palette.colors[..]);
+    }
+}
@@ -0,0 +1,375 @@
+use super::invalid_palette;
+use crate::color::{lab_to_srgb, srgb_to_lab, WhitePoint};
+use crate::error::ColorCruncherError;
+use crate::quantize::Palette;
+
+const SIGNATURE: &[u8; 4] = b"ASEF";
+const VERSION: [u16; 2] = [1, 0];
+
+const BLOCK_GROUP_START: u16 = 0xC001;
+const BLOCK_GROUP_END: u16 = 0xC002;
+const BLOCK_COLOR: u16 = 0x0001;
+
+// Adobe stores Lab relative to D50, with L scaled to 0-1
+const LAB_WHITE: WhitePoint = WhitePoint::D50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AseColor {
+    // Components are 0-1
+    Rgb([f32; 3]),
+    // L is 0-100, a/b roughly -128..127
+    Lab([f32; 3]),
+    Cmyk([f32; 4]),
+    Gray(f32),
+}
+
+impl AseColor {
+    pub fn from_rgba(color: &[u8; 4]) -> Self {
+        AseColor::Rgb([
+            color[0] as f32 / 255.0,
+            color[1] as f32 / 255.0,
+            color[2] as f32 / 255.0,
+        ])
+    }
+
+    // ASE has no alpha, so every swatch is opaque. CMYK uses the naive conversion
+    // since we don't have the document's ICC profile.
+    pub fn to_rgba(&self) -> [u8; 4] {
+        let to_u8 = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+        match self {
+            AseColor::Rgb([r, g, b]) => [to_u8(*r), to_u8(*g), to_u8(*b), u8::MAX],
+            AseColor::Lab(lab) => {
+                let [r, g, b] = lab_to_srgb(*lab, LAB_WHITE);
+                [r, g, b, u8::MAX]
+            }
+            AseColor::Cmyk([c, m, y, k]) => [
+                to_u8((1.0 - c) * (1.0 - k)),
+                to_u8((1.0 - m) * (1.0 - k)),
+                to_u8((1.0 - y) * (1.0 - k)),
+                u8::MAX,
+            ],
+            AseColor::Gray(v) => {
+                let v = to_u8(*v);
+                [v, v, v, u8::MAX]
+            }
+        }
+    }
+
+    pub fn to_lab(&self) -> Self {
+        let [r, g, b, _] = self.to_rgba();
+        AseColor::Lab(srgb_to_lab([r as f32, g as f32, b as f32], LAB_WHITE))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AseColorType {
+    Global,
+    Spot,
+    #[default]
+    Normal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Swatch {
+    pub name: String,
+    pub color: AseColor,
+    pub color_type: AseColorType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AseEntry {
+    Swatch(Swatch),
+    Group { name: String, swatches: Vec<Swatch> },
+}
+
+// Swatches in file order, with groups flattened in place
+pub fn ase_colors(entries: &[AseEntry]) -> Vec<[u8; 4]> {
+    entries
+        .iter()
+        .flat_map(|entry| match entry {
+            AseEntry::Swatch(swatch) => std::slice::from_ref(swatch),
+            AseEntry::Group { swatches, .. } => swatches.as_slice(),
+        })
+        .map(|swatch| swatch.color.to_rgba())
+        .collect()
+}
+
+// Swatches are named after their hex value, and their share of the image when counts are known
+pub fn ase_from_palette(palette: &Palette) -> Vec<AseEntry> {
+    let total: usize = palette.counts.iter().sum();
+    palette
+        .colors
+        .iter()
+        .enumerate()
+        .map(|(i, color)| {
+            let hex = format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2]);
+            let name = match palette.counts.get(i) {
+                Some(&count) if total > 0 => {
+                    format!("{} ({:.1}%)", hex, count as f32 * 100.0 / total as f32)
+                }
+                _ => hex,
+            };
+            AseEntry::Swatch(Swatch {
+                name,
+                color: AseColor::from_rgba(color),
+                color_type: AseColorType::Global,
+            })
+        })
+        .collect()
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ColorCruncherError> {
+        let slice = self
+            .bytes
+            .get(self.position..self.position + n)
+            .ok_or_else(|| invalid_palette("Unexpected end of ASE file"))?;
+        self.position += n;
+        Ok(slice)
+    }
+
+    fn u16(&mut self) -> Result<u16, ColorCruncherError> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ColorCruncherError> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, ColorCruncherError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    // Length-prefixed, null-terminated UTF-16BE
+    fn name(&mut self) -> Result<String, ColorCruncherError> {
+        let length = self.u16()? as usize;
+        let units: Vec<u16> = self
+            .take(length * 2)?
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        String::from_utf16(&units).map_err(|_| invalid_palette("Invalid ASE swatch name"))
+    }
+}
+
+fn read_swatch(block: &mut Reader) -> Result<Swatch, ColorCruncherError> {
+    let name = block.name()?;
+    let color = match block.take(4)? {
+        b"RGB " => AseColor::Rgb([block.f32()?, block.f32()?, block.f32()?]),
+        b"LAB " => {
+            let l = block.f32()? * 100.0;
+            AseColor::Lab([l, block.f32()?, block.f32()?])
+        }
+        b"CMYK" => AseColor::Cmyk([block.f32()?, block.f32()?, block.f32()?, block.f32()?]),
+        b"Gray" => AseColor::Gray(block.f32()?),
+        model => {
+            return Err(invalid_palette(format!(
+                "Unknown ASE color model: {}",
+                String::from_utf8_lossy(model)
+            )))
+        }
+    };
+    let color_type = match block.u16()? {
+        0 => AseColorType::Global,
+        1 => AseColorType::Spot,
+        _ => AseColorType::Normal,
+    };
+    Ok(Swatch {
+        name,
+        color,
+        color_type,
+    })
+}
+
+pub fn read_ase(bytes: &[u8]) -> Result<Vec<AseEntry>, ColorCruncherError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(4)? != SIGNATURE {
+        return Err(invalid_palette("Missing ASEF signature"));
+    }
+    // Version, 1.0 in every file seen in the wild
+    reader.u16()?;
+    reader.u16()?;
+    let num_blocks = reader.u32()?;
+
+    let mut entries = Vec::new();
+    let mut group: Option<(String, Vec<Swatch>)> = None;
+    for _ in 0..num_blocks {
+        let block_type = reader.u16()?;
+        let length = reader.u32()? as usize;
+        let mut block = Reader {
+            bytes: reader.take(length)?,
+            position: 0,
+        };
+
+        match block_type {
+            BLOCK_GROUP_START => group = Some((block.name()?, Vec::new())),
+            BLOCK_GROUP_END => {
+                if let Some((name, swatches)) = group.take() {
+                    entries.push(AseEntry::Group { name, swatches });
+                }
+            }
+            BLOCK_COLOR => {
+                let swatch = read_swatch(&mut block)?;
+                match group.as_mut() {
+                    Some((_, swatches)) => swatches.push(swatch),
+                    None => entries.push(AseEntry::Swatch(swatch)),
+                }
+            }
+            // Unknown blocks are skipped, their length tells us how far
+            _ => {}
+        }
+    }
+
+    // Tolerate a missing group end
+    if let Some((name, swatches)) = group {
+        entries.push(AseEntry::Group { name, swatches });
+    }
+    Ok(entries)
+}
+
+fn push_name(block: &mut Vec<u8>, name: &str) {
+    let units: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+    block.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    for unit in units {
+        block.extend_from_slice(&unit.to_be_bytes());
+    }
+}
+
+fn push_block(bytes: &mut Vec<u8>, block_type: u16, block: &[u8]) {
+    bytes.extend_from_slice(&block_type.to_be_bytes());
+    bytes.extend_from_slice(&(block.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(block);
+}
+
+fn swatch_block(swatch: &Swatch) -> Vec<u8> {
+    let mut block = Vec::new();
+    push_name(&mut block, &swatch.name);
+    let (model, values): (&[u8; 4], Vec<f32>) = match &swatch.color {
+        AseColor::Rgb(rgb) => (b"RGB ", rgb.to_vec()),
+        AseColor::Lab([l, a, b]) => (b"LAB ", vec![l / 100.0, *a, *b]),
+        AseColor::Cmyk(cmyk) => (b"CMYK", cmyk.to_vec()),
+        AseColor::Gray(v) => (b"Gray", vec![*v]),
+    };
+    block.extend_from_slice(model);
+    for value in values {
+        block.extend_from_slice(&value.to_be_bytes());
+    }
+    let color_type: u16 = match swatch.color_type {
+        AseColorType::Global => 0,
+        AseColorType::Spot => 1,
+        AseColorType::Normal => 2,
+    };
+    block.extend_from_slice(&color_type.to_be_bytes());
+    block
+}
+
+pub fn write_ase(entries: &[AseEntry]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut num_blocks: u32 = 0;
+    for entry in entries {
+        match entry {
+            AseEntry::Swatch(swatch) => {
+                push_block(&mut bytes, BLOCK_COLOR, &swatch_block(swatch));
+                num_blocks += 1;
+            }
+            AseEntry::Group { name, swatches } => {
+                let mut block = Vec::new();
+                push_name(&mut block, name);
+                push_block(&mut bytes, BLOCK_GROUP_START, &block);
+                for swatch in swatches {
+                    push_block(&mut bytes, BLOCK_COLOR, &swatch_block(swatch));
+                }
+                push_block(&mut bytes, BLOCK_GROUP_END, &[]);
+                num_blocks += swatches.len() as u32 + 2;
+            }
+        }
+    }
+
+    let mut header = Vec::with_capacity(12 + bytes.len());
+    header.extend_from_slice(SIGNATURE);
+    header.extend_from_slice(&VERSION[0].to_be_bytes());
+    header.extend_from_slice(&VERSION[1].to_be_bytes());
+    header.extend_from_slice(&num_blocks.to_be_bytes());
+    header.extend_from_slice(&bytes);
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swatch(name: &str, color: AseColor) -> Swatch {
+        Swatch {
+            name: name.to_string(),
+            color,
+            color_type: AseColorType::Normal,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_with_groups_and_models() {
+        let entries = vec![
+            AseEntry::Swatch(swatch("Rød", AseColor::Rgb([1.0, 0.0, 0.0]))),
+            AseEntry::Group {
+                name: "Brand".to_string(),
+                swatches: vec![
+                    swatch("Ink", AseColor::Cmyk([0.0, 0.0, 0.0, 1.0])),
+                    swatch("Paper", AseColor::Lab([100.0, 0.0, 0.0])),
+                    swatch("Mid", AseColor::Gray(0.5)),
+                ],
+            },
+        ];
+
+        let read = read_ase(&write_ase(&entries)).unwrap();
+        assert_eq!(read, entries);
+        assert_eq!(
+            ase_colors(&read),
+            vec![
+                [255, 0, 0, 255],
+                [0, 0, 0, 255],
+                [255, 255, 255, 255],
+                [128, 128, 128, 255]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_palette_exports_named_swatches() {
+        let palette = Palette {
+            colors: vec![[255, 128, 0, 255], [0, 0, 0, 255]],
+            counts: vec![3, 1],
+        };
+        let entries = read_ase(&write_ase(&ase_from_palette(&palette))).unwrap();
+
+        let names: Vec<_> = entries
+            .iter()
+            .map(|entry| match entry {
+                AseEntry::Swatch(swatch) => swatch.name.clone(),
+                _ => panic!("unexpected group"),
+            })
+            .collect();
+        assert_eq!(names, vec!["#FF8000 (75.0%)", "#000000 (25.0%)"]);
+        assert_eq!(ase_colors(&entries), palette.colors);
+    }
+
+    #[test]
+    fn test_lab_swatch_converts_to_rgb() {
+        let red = AseColor::Rgb([1.0, 0.0, 0.0]);
+        assert_eq!(red.to_lab().to_rgba(), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_invalid_ase() {
+        assert!(read_ase(b"GIMP").is_err());
+        // Claims one block but has none
+        assert!(read_ase(b"ASEF\x00\x01\x00\x00\x00\x00\x00\x01").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::dither::luma;
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaletteOrder {
+    // Whatever order the palette came out in
+    #[default]
+    None,
+    // Darkest to lightest
+    Luminance,
+    // Grays first by value, then colors by hue, saturation and value
+    Hsv,
+    // Start at the darkest color and keep stepping to the closest unvisited one, which
+    // turns clusters of similar colors into smooth ramps
+    NearestNeighbor,
+    // Most used colors first
+    Population,
+}
+
+impl fmt::Display for PaletteOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+fn to_vec4(color: &[u8; 4]) -> [f32; 4] {
+    [
+        color[0] as f32,
+        color[1] as f32,
+        color[2] as f32,
+        color[3] as f32,
+    ]
+}
+
+// Hue in 0-360, saturation and value in 0-1
+fn hsv(color: &[u8; 4]) -> [f32; 3] {
+    let [r, g, b] = [
+        color[0] as f32 / 255.0,
+        color[1] as f32 / 255.0,
+        color[2] as f32 / 255.0,
+    ];
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    [hue, saturation, max]
+}
+
+fn compare_hsv(a: &[u8; 4], b: &[u8; 4]) -> Ordering {
+    let (a, b) = (hsv(a), hsv(b));
+    let (a_gray, b_gray) = (a[1] == 0.0, b[1] == 0.0);
+    match (a_gray, b_gray) {
+        (true, true) => a[2].total_cmp(&b[2]),
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => a[0]
+            .total_cmp(&b[0])
+            .then(a[1].total_cmp(&b[1]))
+            .then(a[2].total_cmp(&b[2])),
+    }
+}
+
+fn distance_squared(a: &[u8; 4], b: &[u8; 4]) -> i32 {
+    (0..4)
+        .map(|channel| {
+            let d = a[channel] as i32 - b[channel] as i32;
+            d * d
+        })
+        .sum()
+}
+
+fn nearest_neighbor_path(colors: &[[u8; 4]]) -> Vec<usize> {
+    let mut remaining: Vec<usize> = (0..colors.len()).collect();
+    let mut path = Vec::with_capacity(colors.len());
+
+    let start = remaining
+        .iter()
+        .enumerate()
+        .min_by(|(_, &a), (_, &b)| {
+            luma(&to_vec4(&colors[a])).total_cmp(&luma(&to_vec4(&colors[b])))
+        })
+        .map(|(position, _)| position);
+    let Some(mut position) = start else {
+        return path;
+    };
+
+    loop {
+        let current = remaining.remove(position);
+        path.push(current);
+        match remaining
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, &candidate)| distance_squared(&colors[current], &colors[candidate]))
+        {
+            Some((next, _)) => position = next,
+            None => return path,
+        }
+    }
+}
+
+impl PaletteOrder {
+    // The new order as indices into `colors`. Counts are only used by Population, and
+    // every sort is stable so ties keep their original order.
+    pub fn permutation(&self, colors: &[[u8; 4]], counts: &[usize]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..colors.len()).collect();
+        match self {
+            PaletteOrder::None => {}
+            PaletteOrder::Luminance => order.sort_by(|&a, &b| {
+                luma(&to_vec4(&colors[a])).total_cmp(&luma(&to_vec4(&colors[b])))
+            }),
+            PaletteOrder::Hsv => order.sort_by(|&a, &b| compare_hsv(&colors[a], &colors[b])),
+            PaletteOrder::NearestNeighbor => order = nearest_neighbor_path(colors),
+            PaletteOrder::Population => {
+                order.sort_by_key(|&i| std::cmp::Reverse(counts.get(i).copied().unwrap_or(0)))
+            }
+        }
+        order
+    }
+
+    // Reorders the palette in place and rewrites the indices that point into it
+    pub fn apply(&self, colors: &mut Vec<[u8; 4]>, counts: &mut Vec<usize>, indices: &mut [usize]) {
+        if matches!(self, PaletteOrder::None) {
+            return;
+        }
+        reorder(&self.permutation(colors, counts), colors, counts, indices);
+    }
+}
+
+// Puts the palette in the order given by a permutation and rewrites the indices to match
+pub fn reorder(
+    order: &[usize],
+    colors: &mut Vec<[u8; 4]>,
+    counts: &mut Vec<usize>,
+    indices: &mut [usize],
+) {
+    let mut new_index = vec![0; order.len()];
+    for (new, &old) in order.iter().enumerate() {
+        new_index[old] = new;
+    }
+
+    *colors = order.iter().map(|&old| colors[old]).collect();
+    if counts.len() == order.len() {
+        *counts = order.iter().map(|&old| counts[old]).collect();
+    }
+    for index in indices.iter_mut() {
+        *index = new_index[*index];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [[u8; 4]; 5] = [
+        [255, 255, 255, 255],
+        [0, 0, 255, 255],
+        [0, 0, 0, 255],
+        [255, 0, 0, 255],
+        [0, 0, 200, 255],
+    ];
+
+    #[test]
+    fn test_luminance_order() {
+        let order = PaletteOrder::Luminance.permutation(&COLORS, &[]);
+        assert_eq!(order, vec![2, 4, 1, 3, 0]);
+    }
+
+    #[test]
+    fn test_hsv_puts_grays_first() {
+        let order = PaletteOrder::Hsv.permutation(&COLORS, &[]);
+        assert_eq!(order, vec![2, 0, 3, 4, 1]);
+    }
+
+    #[test]
+    fn test_nearest_neighbor_walks_ramps() {
+        // From blue, white and red are equally far so the earlier entry wins
+        let order = PaletteOrder::NearestNeighbor.permutation(&COLORS, &[]);
+        assert_eq!(order, vec![2, 4, 1, 0, 3]);
+    }
+
+    #[test]
+    fn test_population_reorders_indices() {
+        let mut colors = vec![[0, 0, 0, 255], [255, 0, 0, 255], [0, 255, 0, 255]];
+        let mut counts = vec![1, 3, 2];
+        let mut indices = vec![0, 1, 1, 1, 2, 2];
+
+        PaletteOrder::Population.apply(&mut colors, &mut counts, &mut indices);
+        assert_eq!(
+            colors,
+            vec![[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 0, 255]]
+        );
+        assert_eq!(counts, vec![3, 2, 1]);
+        assert_eq!(indices, vec![2, 0, 0, 0, 1, 1]);
+    }
+}
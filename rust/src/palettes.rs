@@ -0,0 +1,252 @@
+use std::fmt;
+
+// Colors are stored as 0xRRGGBB and expanded to opaque RGBA on request.
+
+const EGA: [u32; 16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA, 0x555555,
+    0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+];
+
+// CGA graphics modes have a selectable background, black here
+const CGA_MODE4_PALETTE0_LOW: [u32; 4] = [0x000000, 0x00AA00, 0xAA0000, 0xAA5500];
+const CGA_MODE4_PALETTE0_HIGH: [u32; 4] = [0x000000, 0x55FF55, 0xFF5555, 0xFFFF55];
+const CGA_MODE4_PALETTE1_LOW: [u32; 4] = [0x000000, 0x00AAAA, 0xAA00AA, 0xAAAAAA];
+const CGA_MODE4_PALETTE1_HIGH: [u32; 4] = [0x000000, 0x55FFFF, 0xFF55FF, 0xFFFFFF];
+const CGA_MODE5_LOW: [u32; 4] = [0x000000, 0x00AAAA, 0xAA0000, 0xAAAAAA];
+const CGA_MODE5_HIGH: [u32; 4] = [0x000000, 0x55FFFF, 0xFF5555, 0xFFFFFF];
+
+// The usual 2C02 table with its duplicate blacks removed
+const NES: [u32; 55] = [
+    0x7C7C7C, 0x0000FC, 0x0000BC, 0x4428BC, 0x940084, 0xA80020, 0xA81000, 0x881400, 0x503000,
+    0x007800, 0x006800, 0x005800, 0x004058, 0x000000, 0xBCBCBC, 0x0078F8, 0x0058F8, 0x6844FC,
+    0xD800CC, 0xE40058, 0xF83800, 0xE45C10, 0xAC7C00, 0x00B800, 0x00A800, 0x00A844, 0x008888,
+    0xF8F8F8, 0x3CBCFC, 0x6888FC, 0x9878F8, 0xF878F8, 0xF85898, 0xF87858, 0xFCA044, 0xF8B800,
+    0xB8F818, 0x58D854, 0x58F898, 0x00E8D8, 0x787878, 0xFCFCFC, 0xA4E4FC, 0xB8B8F8, 0xD8B8F8,
+    0xF8B8F8, 0xF8A4C0, 0xF0D0B0, 0xFCE0A8, 0xF8D878, 0xD8F878, 0xB8F8B8, 0xB8F8D8, 0x00FCFC,
+    0xF8D8F8,
+];
+
+const GAMEBOY: [u32; 4] = [0x0F380F, 0x306230, 0x8BAC0F, 0x9BBC0F];
+
+const PICO_8: [u32; 16] = [
+    0x000000, 0x1D2B53, 0x7E2553, 0x008751, 0xAB5236, 0x5F574F, 0xC2C3C7, 0xFFF1E8, 0xFF004D,
+    0xFFA300, 0xFFEC27, 0x00E436, 0x29ADFF, 0x83769C, 0xFF77A8, 0xFFCCAA,
+];
+
+// Pepto's PAL measurements
+const C64: [u32; 16] = [
+    0x000000, 0xFFFFFF, 0x68372B, 0x70A4B2, 0x6F3D86, 0x588D43, 0x352879, 0xB8C76F, 0x6F4F25,
+    0x433900, 0x9A6759, 0x444444, 0x6C6C6C, 0x9AD284, 0x6C5EB5, 0x959595,
+];
+
+// Normal then bright; bright black is the same as black so it only appears once
+const ZX_SPECTRUM: [u32; 15] = [
+    0x000000, 0x0000D7, 0xD70000, 0xD700D7, 0x00D700, 0x00D7D7, 0xD7D700, 0xD7D7D7, 0x0000FF,
+    0xFF0000, 0xFF00FF, 0x00FF00, 0x00FFFF, 0xFFFF00, 0xFFFFFF,
+];
+
+// TMS9918, leaving out the transparent entry
+const MSX: [u32; 15] = [
+    0x000000, 0x21C842, 0x5EDC78, 0x5455ED, 0x7D76FC, 0xD4524D, 0x42EBF5, 0xFC5554, 0xFF7978,
+    0xD4C154, 0xE6CE80, 0x21B03B, 0xC95BBA, 0xCCCCCC, 0xFFFFFF,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PalettePreset {
+    WebSafe,
+    Ega,
+    // The full 64 colors the EGA can output
+    Ega64,
+    CgaMode4Palette0Low,
+    CgaMode4Palette0High,
+    CgaMode4Palette1Low,
+    CgaMode4Palette1High,
+    CgaMode5Low,
+    CgaMode5High,
+    Nes,
+    GameBoy,
+    Pico8,
+    C64,
+    ZxSpectrum,
+    Msx,
+    // Evenly spaced ramp from black to white with this many levels (2 to 256)
+    Grayscale(usize),
+}
+
+impl PalettePreset {
+    pub const NAMED: [PalettePreset; 15] = [
+        PalettePreset::WebSafe,
+        PalettePreset::Ega,
+        PalettePreset::Ega64,
+        PalettePreset::CgaMode4Palette0Low,
+        PalettePreset::CgaMode4Palette0High,
+        PalettePreset::CgaMode4Palette1Low,
+        PalettePreset::CgaMode4Palette1High,
+        PalettePreset::CgaMode5Low,
+        PalettePreset::CgaMode5High,
+        PalettePreset::Nes,
+        PalettePreset::GameBoy,
+        PalettePreset::Pico8,
+        PalettePreset::C64,
+        PalettePreset::ZxSpectrum,
+        PalettePreset::Msx,
+    ];
+
+    pub fn name(&self) -> String {
+        match self {
+            PalettePreset::WebSafe => "web-safe".to_string(),
+            PalettePreset::Ega => "ega".to_string(),
+            PalettePreset::Ega64 => "ega-64".to_string(),
+            PalettePreset::CgaMode4Palette0Low => "cga-mode4-palette0-low".to_string(),
+            PalettePreset::CgaMode4Palette0High => "cga-mode4-palette0-high".to_string(),
+            PalettePreset::CgaMode4Palette1Low => "cga-mode4-palette1-low".to_string(),
+            PalettePreset::CgaMode4Palette1High => "cga-mode4-palette1-high".to_string(),
+            PalettePreset::CgaMode5Low => "cga-mode5-low".to_string(),
+            PalettePreset::CgaMode5High => "cga-mode5-high".to_string(),
+            PalettePreset::Nes => "nes".to_string(),
+            PalettePreset::GameBoy => "gameboy".to_string(),
+            PalettePreset::Pico8 => "pico-8".to_string(),
+            PalettePreset::C64 => "c64".to_string(),
+            PalettePreset::ZxSpectrum => "zx-spectrum".to_string(),
+            PalettePreset::Msx => "msx".to_string(),
+            PalettePreset::Grayscale(levels) => format!("grayscale-{levels}"),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(levels) = name.strip_prefix("grayscale-") {
+            return match levels.parse::<usize>() {
+                Ok(levels) if (2..=256).contains(&levels) => Some(PalettePreset::Grayscale(levels)),
+                _ => None,
+            };
+        }
+
+        PalettePreset::NAMED
+            .iter()
+            .find(|preset| preset.name() == name)
+            .copied()
+    }
+
+    pub fn colors(&self) -> Vec<[u8; 4]> {
+        match self {
+            PalettePreset::WebSafe => web_safe(),
+            PalettePreset::Ega => from_hex(&EGA),
+            PalettePreset::Ega64 => ega_64(),
+            PalettePreset::CgaMode4Palette0Low => from_hex(&CGA_MODE4_PALETTE0_LOW),
+            PalettePreset::CgaMode4Palette0High => from_hex(&CGA_MODE4_PALETTE0_HIGH),
+            PalettePreset::CgaMode4Palette1Low => from_hex(&CGA_MODE4_PALETTE1_LOW),
+            PalettePreset::CgaMode4Palette1High => from_hex(&CGA_MODE4_PALETTE1_HIGH),
+            PalettePreset::CgaMode5Low => from_hex(&CGA_MODE5_LOW),
+            PalettePreset::CgaMode5High => from_hex(&CGA_MODE5_HIGH),
+            PalettePreset::Nes => from_hex(&NES),
+            PalettePreset::GameBoy => from_hex(&GAMEBOY),
+            PalettePreset::Pico8 => from_hex(&PICO_8),
+            PalettePreset::C64 => from_hex(&C64),
+            PalettePreset::ZxSpectrum => from_hex(&ZX_SPECTRUM),
+            PalettePreset::Msx => from_hex(&MSX),
+            PalettePreset::Grayscale(levels) => grayscale(*levels),
+        }
+    }
+}
+
+impl fmt::Display for PalettePreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+fn from_hex(colors: &[u32]) -> Vec<[u8; 4]> {
+    colors
+        .iter()
+        .map(|&c| [(c >> 16) as u8, (c >> 8) as u8, c as u8, u8::MAX])
+        .collect()
+}
+
+fn web_safe() -> Vec<[u8; 4]> {
+    let steps = [0, 51, 102, 153, 204, 255];
+    let mut colors = Vec::with_capacity(216);
+    for r in steps {
+        for g in steps {
+            for b in steps {
+                colors.push([r, g, b, u8::MAX]);
+            }
+        }
+    }
+    colors
+}
+
+// Each channel has a primary and a secondary bit worth 0xAA and 0x55
+fn ega_64() -> Vec<[u8; 4]> {
+    (0..64u8)
+        .map(|i| {
+            let channel = |primary: u8, secondary: u8| {
+                ((i >> primary) & 1) * 0xAA + ((i >> secondary) & 1) * 0x55
+            };
+            [channel(2, 5), channel(1, 4), channel(0, 3), u8::MAX]
+        })
+        .collect()
+}
+
+fn grayscale(levels: usize) -> Vec<[u8; 4]> {
+    let levels = levels.clamp(2, 256);
+    (0..levels)
+        .map(|i| {
+            let v = (i * 255 + (levels - 1) / 2) / (levels - 1);
+            [v as u8, v as u8, v as u8, u8::MAX]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_preset_sizes() {
+        assert_eq!(PalettePreset::WebSafe.colors().len(), 216);
+        assert_eq!(PalettePreset::Ega.colors().len(), 16);
+        assert_eq!(PalettePreset::Ega64.colors().len(), 64);
+        assert_eq!(PalettePreset::CgaMode4Palette1High.colors().len(), 4);
+        assert_eq!(PalettePreset::GameBoy.colors().len(), 4);
+        assert_eq!(PalettePreset::Pico8.colors().len(), 16);
+        assert_eq!(PalettePreset::ZxSpectrum.colors().len(), 15);
+        assert_eq!(PalettePreset::Grayscale(16).colors().len(), 16);
+    }
+
+    #[test]
+    fn test_presets_have_no_duplicate_colors() {
+        for preset in PalettePreset::NAMED {
+            let colors = preset.colors();
+            let distinct: HashSet<_> = colors.iter().collect();
+            assert_eq!(distinct.len(), colors.len(), "{preset} has duplicates");
+        }
+    }
+
+    #[test]
+    fn test_names_round_trip() {
+        for preset in PalettePreset::NAMED {
+            assert_eq!(PalettePreset::from_name(&preset.name()), Some(preset));
+        }
+        assert_eq!(
+            PalettePreset::from_name("grayscale-4"),
+            Some(PalettePreset::Grayscale(4))
+        );
+        assert_eq!(PalettePreset::from_name("grayscale-1"), None);
+        assert_eq!(PalettePreset::from_name("amiga"), None);
+    }
+
+    #[test]
+    fn test_grayscale_ramp() {
+        let ramp = PalettePreset::Grayscale(4).colors();
+        assert_eq!(
+            ramp,
+            vec![
+                [0, 0, 0, 255],
+                [85, 85, 85, 255],
+                [170, 170, 170, 255],
+                [255, 255, 255, 255]
+            ]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use crate::dither::luma;
+use std::fmt;
+
+// How the bytes of one pixel are laid out in the input buffer. Everything is converted
+// to RGBA internally, and quantized images are written back in the same layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelLayout {
+    Gray,
+    GrayAlpha,
+    #[default]
+    Rgb,
+    Rgba,
+    Bgra,
+    Argb,
+}
+
+impl fmt::Display for PixelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl PixelLayout {
+    // The usual layout for a channel count, with RGB(A) order for color
+    pub fn from_channels(channels: usize) -> Option<Self> {
+        match channels {
+            1 => Some(PixelLayout::Gray),
+            2 => Some(PixelLayout::GrayAlpha),
+            3 => Some(PixelLayout::Rgb),
+            4 => Some(PixelLayout::Rgba),
+            _ => None,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match self {
+            PixelLayout::Gray => 1,
+            PixelLayout::GrayAlpha => 2,
+            PixelLayout::Rgb => 3,
+            PixelLayout::Rgba | PixelLayout::Bgra | PixelLayout::Argb => 4,
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        !matches!(self, PixelLayout::Gray | PixelLayout::Rgb)
+    }
+
+    // One pixel, exactly `channels()` bytes long, as RGBA. Layouts without alpha are opaque.
+    pub fn to_rgba(&self, pixel: &[u8]) -> [u8; 4] {
+        match self {
+            PixelLayout::Gray => [pixel[0], pixel[0], pixel[0], u8::MAX],
+            PixelLayout::GrayAlpha => [pixel[0], pixel[0], pixel[0], pixel[1]],
+            PixelLayout::Rgb => [pixel[0], pixel[1], pixel[2], u8::MAX],
+            PixelLayout::Rgba => [pixel[0], pixel[1], pixel[2], pixel[3]],
+            PixelLayout::Bgra => [pixel[2], pixel[1], pixel[0], pixel[3]],
+            PixelLayout::Argb => [pixel[1], pixel[2], pixel[3], pixel[0]],
+        }
+    }
+
+    // Appends an RGBA color in this layout. Gray layouts store the color's luma.
+    pub fn extend_from_rgba(&self, buffer: &mut Vec<u8>, color: [u8; 4]) {
+        let [r, g, b, a] = color;
+        match self {
+            PixelLayout::Gray => buffer.push(gray(color)),
+            PixelLayout::GrayAlpha => buffer.extend_from_slice(&[gray(color), a]),
+            PixelLayout::Rgb => buffer.extend_from_slice(&[r, g, b]),
+            PixelLayout::Rgba => buffer.extend_from_slice(&[r, g, b, a]),
+            PixelLayout::Bgra => buffer.extend_from_slice(&[b, g, r, a]),
+            PixelLayout::Argb => buffer.extend_from_slice(&[a, r, g, b]),
+        }
+    }
+}
+
+fn gray(color: [u8; 4]) -> u8 {
+    luma(&[color[0] as f32, color[1] as f32, color[2] as f32, 0.0])
+        .round()
+        .min(255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layouts_round_trip() {
+        let color = [10, 20, 30, 40];
+        for layout in [
+            PixelLayout::Rgb,
+            PixelLayout::Rgba,
+            PixelLayout::Bgra,
+            PixelLayout::Argb,
+        ] {
+            let mut buffer = Vec::new();
+            layout.extend_from_rgba(&mut buffer, color);
+            assert_eq!(buffer.len(), layout.channels());
+
+            let expected = if layout.has_alpha() {
+                color
+            } else {
+                [10, 20, 30, 255]
+            };
+            assert_eq!(layout.to_rgba(&buffer), expected, "{layout}");
+        }
+    }
+
+    #[test]
+    fn test_gray_layouts() {
+        assert_eq!(PixelLayout::Gray.to_rgba(&[7]), [7, 7, 7, 255]);
+        assert_eq!(PixelLayout::GrayAlpha.to_rgba(&[7, 9]), [7, 7, 7, 9]);
+
+        let mut buffer = Vec::new();
+        PixelLayout::GrayAlpha.extend_from_rgba(&mut buffer, [255, 0, 0, 128]);
+        assert_eq!(buffer, vec![76, 128]);
+    }
+}
@@ -1,108 +1,232 @@
-use numpy::ndarray::Axis;
-use numpy::PyReadonlyArray3;
+mod kmeans;
+
+use std::borrow::Cow;
+
+use numpy::{PyArray1, PyArrayMethods, PyReadonlyArray3, PyUntypedArrayMethods};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
 
-use crate::{kmeans::kmeans, kmeans::KMeansConfig, quantize::ColorCruncher};
-use numpy::{PyArray1, PyArray2, PyArray3};
+use crate::error;
+use crate::kmeans::{KMeans, KMeansConfig};
+use crate::quantize::{ColorCruncherBuilder, IndexBuffer};
+use futures::executor::block_on;
 
-#[pyfunction(name = "kmeans_3chan")]
-#[doc = "Perform k-means clustering on a 3-channel dataset. Expects nx3 array of floats, returns nxk array of labels and kx3 array of centroids"]
-fn py_kmeans_3chan(
-    data: Vec<[f64; 3]>,
-    k: usize,
-) -> PyResult<(Py<PyArray1<usize>>, Py<PyArray2<f32>>)> {
-    let array = match numpy::ndarray::Array2::from_shape_vec((data.len(), 3), data) {
-        Ok(array) => array,
-        Err(e) => {
-            return Err(pyo3::exceptions::PyValueError::new_err(format!(
-                "Failed to create array: {}",
-                e
-            )))
+// Every library error is a ColorCruncherError in Python, with one subclass per kind
+create_exception!(colorcrunch, ColorCruncherError, PyException);
+create_exception!(colorcrunch, InvalidConfigError, ColorCruncherError);
+create_exception!(colorcrunch, TooFewColorsError, ColorCruncherError);
+create_exception!(colorcrunch, InvalidBufferError, ColorCruncherError);
+create_exception!(colorcrunch, GpuUnavailableError, ColorCruncherError);
+create_exception!(colorcrunch, GpuFailureError, ColorCruncherError);
+create_exception!(colorcrunch, InvalidPaletteError, ColorCruncherError);
+create_exception!(colorcrunch, InvalidImageError, ColorCruncherError);
+
+impl From<error::ColorCruncherError> for PyErr {
+    fn from(err: error::ColorCruncherError) -> Self {
+        let message = err.to_string();
+        match err {
+            error::ColorCruncherError::InvalidConfig(_) => InvalidConfigError::new_err(message),
+            error::ColorCruncherError::TooFewColors { .. } => TooFewColorsError::new_err(message),
+            error::ColorCruncherError::InvalidBuffer(_) => InvalidBufferError::new_err(message),
+            error::ColorCruncherError::GpuUnavailable(_) => GpuUnavailableError::new_err(message),
+            error::ColorCruncherError::GpuFailure(_) => GpuFailureError::new_err(message),
+            error::ColorCruncherError::InvalidPalette(_) => InvalidPaletteError::new_err(message),
+            error::ColorCruncherError::InvalidImage(_) => InvalidImageError::new_err(message),
         }
+    }
+}
+
+// An HxWxC image with 1 to 4 channels: gray, gray+alpha, RGB or RGBA. C-contiguous arrays are borrowed as-is, anything else (slices,
+// transposes) is copied into row-major order.
+struct Image<'a> {
+    height: usize,
+    width: usize,
+    channels: usize,
+    pixels: Cow<'a, [u8]>,
+}
+
+impl Image<'_> {
+    fn builder(&self, num_colors: usize, sample_rate: usize) -> ColorCruncherBuilder {
+        ColorCruncherBuilder::new()
+            .with_max_colors(num_colors)
+            .with_sample_rate(sample_rate)
+            .with_channels(self.channels)
+            .with_width(self.width)
+    }
+}
+
+fn image_from_array<'a>(
+    data: &'a PyReadonlyArray3<'_, u8>,
+) -> Result<Image<'a>, error::ColorCruncherError> {
+    let shape = data.shape();
+    let (height, width, channels) = (shape[0], shape[1], shape[2]);
+    check_channels(channels)?;
+
+    let pixels = match data.as_slice() {
+        Ok(slice) => Cow::Borrowed(slice),
+        Err(_) => Cow::Owned(data.as_array().iter().copied().collect()),
     };
-    let shape = array.shape();
-    if shape[1] != 3 {
-        return Err(pyo3::exceptions::PyValueError::new_err(format!(
-            "Expected 3-channel data, got {} channels",
-            shape[1]
+    Ok(Image {
+        height,
+        width,
+        channels,
+        pixels,
+    })
+}
+
+fn check_channels(channels: usize) -> Result<(), error::ColorCruncherError> {
+    if !(1..=4).contains(&channels) {
+        return Err(error::ColorCruncherError::InvalidBuffer(format!(
+            "expected an HxWx1 to HxWx4 array, got {} channels",
+            channels
         )));
     }
+    Ok(())
+}
 
-    let array: Vec<[f32; 3]> = array
-        .into_raw_vec()
+#[pyfunction(name = "kmeans_3chan")]
+#[doc = "Perform k-means clustering on a 3-channel dataset. Expects nx3 array of floats, returns an array of n labels and a kx3 array of centroids"]
+fn py_kmeans_3chan(
+    py: Python<'_>,
+    data: Vec<[f64; 3]>,
+    k: usize,
+) -> PyResult<(Py<PyArray1<usize>>, PyObject)> {
+    let data: Vec<[f32; 3]> = data
         .into_iter()
         .map(|row| [row[0] as f32, row[1] as f32, row[2] as f32])
         .collect();
 
-    let config = KMeansConfig {
+    let kmeans = KMeans::from_config(KMeansConfig {
         k,
         ..Default::default()
-    };
-
-    let (clusters, centroids) = kmeans(&array, &config).unwrap();
-    let centroids: Vec<Vec<f32>> = centroids
-        .into_iter()
-        .map(|c| vec![c[0], c[1], c[2]])
-        .collect();
+    });
+    let (clusters, centroids) = py.allow_threads(|| kmeans.run_vec3(&data))?;
 
-    Python::with_gil(|py| {
-        let clusters = PyArray1::from_vec_bound(py, clusters);
-        let centroids = PyArray2::from_vec2_bound(py, &centroids)
-            .expect("Failed to convert centroids to PyArray2");
-        Ok((clusters.to_owned().into(), centroids.to_owned().into()))
-    })
+    let num_centroids = centroids.len();
+    let centroids = PyArray1::from_vec_bound(py, centroids.concat()).reshape([num_centroids, 3])?;
+    Ok((
+        PyArray1::from_vec_bound(py, clusters).unbind(),
+        centroids.into_py(py),
+    ))
 }
 
 #[pyfunction(name = "reduce_colorspace")]
-#[doc = "Reduce the colorspace of a 3-channel dataset. Expects nxm x 3 array of bytes, returns nxm x k array of bytes"]
+#[pyo3(signature = (data, num_colors, sample_rate = 1))]
+#[doc = "Reduce the colors of an HxWxC array of bytes (gray, gray+alpha, RGB or RGBA) to at most num_colors. Returns an array of the same shape"]
 fn py_reduce_colorspace(
-    data: PyReadonlyArray3<u8>,
-    num_colors: i32,
-    sample_rate: i32,
-) -> PyResult<Py<PyArray3<u8>>> {
-    let array = data.as_array();
-    let shape = array.shape();
-    if shape[2] != 3 {
-        return Err(pyo3::exceptions::PyValueError::new_err(format!(
-            "Expected 3-channel data, got {} channels",
-            shape[2]
-        )));
-    }
+    py: Python<'_>,
+    data: PyReadonlyArray3<'_, u8>,
+    num_colors: usize,
+    sample_rate: usize,
+) -> PyResult<PyObject> {
+    let image = image_from_array(&data)?;
 
-    let flattened: Vec<u8> = array
-        .lanes(Axis(2))
-        .into_iter()
-        .map(|lane| {
-            let slice = lane.as_slice().unwrap();
-            [slice[0], slice[1], slice[2]]
-        })
-        .flatten()
-        .collect();
+    let quantized = py.allow_threads(|| {
+        let quantizer = block_on(image.builder(num_colors, sample_rate).build())?;
+        block_on(quantizer.quantize_image(&image.pixels))
+    })?;
 
-    let quantizer = ColorCruncher::new(num_colors as usize, sample_rate as usize, 3);
-    let data = quantizer.quantize_image(&flattened);
+    let quantized = PyArray1::from_vec_bound(py, quantized).reshape([
+        image.height,
+        image.width,
+        image.channels,
+    ])?;
+    Ok(quantized.into_py(py))
+}
 
-    let reshaped = match numpy::ndarray::Array3::from_shape_vec((shape[0], shape[1], 3), data) {
-        Ok(reshaped) => reshaped,
-        Err(e) => {
-            return Err(pyo3::exceptions::PyValueError::new_err(format!(
-                "Failed to reshape array: {}",
-                e
-            )))
-        }
-    };
+#[pyfunction(name = "quantize_indexed")]
+#[pyo3(signature = (data, num_colors, sample_rate = 1))]
+#[doc = "Quantize an HxWxC array of bytes (gray, gray+alpha, RGB or RGBA) to a palette. Returns a kx4 array of RGBA colors and an HxW array of palette indices"]
+fn py_quantize_indexed(
+    py: Python<'_>,
+    data: PyReadonlyArray3<'_, u8>,
+    num_colors: usize,
+    sample_rate: usize,
+) -> PyResult<(PyObject, PyObject)> {
+    let image = image_from_array(&data)?;
 
-    Python::with_gil(|py| {
-        Ok(PyArray3::from_owned_array_bound(py, reshaped)
-            .to_owned()
-            .into())
-    })
+    let indexed = py.allow_threads(|| {
+        let quantizer = block_on(image.builder(num_colors, sample_rate).build())?;
+        block_on(quantizer.quantize_indexed(&image.pixels))
+    })?;
+    let shape = [image.height, image.width];
+
+    let num_colors = indexed.palette.len();
+    let palette =
+        PyArray1::from_vec_bound(py, indexed.palette.concat()).reshape([num_colors, 4])?;
+    let indices = match indexed.indices {
+        IndexBuffer::U8(indices) => PyArray1::from_vec_bound(py, indices)
+            .reshape(shape)?
+            .into_py(py),
+        IndexBuffer::U16(indices) => PyArray1::from_vec_bound(py, indices)
+            .reshape(shape)?
+            .into_py(py),
+    };
+    Ok((palette.into_py(py), indices))
 }
 
 #[pymodule]
-fn colorcrunch(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+fn colorcrunch(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
     m.add_function(wrap_pyfunction!(py_kmeans_3chan, m)?)?;
     m.add_function(wrap_pyfunction!(py_reduce_colorspace, m)?)?;
+    m.add_function(wrap_pyfunction!(py_quantize_indexed, m)?)?;
+    m.add_class::<kmeans::PyKMeans>()?;
+
+    m.add(
+        "ColorCruncherError",
+        py.get_type_bound::<ColorCruncherError>(),
+    )?;
+    m.add(
+        "InvalidConfigError",
+        py.get_type_bound::<InvalidConfigError>(),
+    )?;
+    m.add(
+        "TooFewColorsError",
+        py.get_type_bound::<TooFewColorsError>(),
+    )?;
+    m.add(
+        "InvalidBufferError",
+        py.get_type_bound::<InvalidBufferError>(),
+    )?;
+    m.add(
+        "GpuUnavailableError",
+        py.get_type_bound::<GpuUnavailableError>(),
+    )?;
+    m.add("GpuFailureError", py.get_type_bound::<GpuFailureError>())?;
+    m.add(
+        "InvalidPaletteError",
+        py.get_type_bound::<InvalidPaletteError>(),
+    )?;
+    m.add(
+        "InvalidImageError",
+        py.get_type_bound::<InvalidImageError>(),
+    )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gray_arrays_are_accepted() {
+        for channels in 1..=4 {
+            assert!(check_channels(channels).is_ok());
+        }
+        assert!(check_channels(5).is_err());
+
+        // A 2x3 gray gradient, as reduce_colorspace would see an HxWx1 array
+        let image = Image {
+            height: 2,
+            width: 3,
+            channels: 1,
+            pixels: Cow::Owned(vec![0, 50, 100, 150, 200, 250]),
+        };
+        let quantizer = block_on(image.builder(2, 1).build()).unwrap();
+        let quantized = block_on(quantizer.quantize_image(&image.pixels)).unwrap();
+        assert_eq!(quantized.len(), image.height * image.width * image.channels);
+    }
+}
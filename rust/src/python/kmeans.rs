@@ -0,0 +1,380 @@
+use numpy::{PyArray1, PyArrayMethods, PyReadonlyArray2, PyUntypedArrayMethods};
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+
+use crate::error::ColorCruncherError;
+use crate::kmeans::{
+    find_closest_centroid, Initializer, KMeans, KMeansAlgorithm, KMeansConfig, KMeansFit,
+};
+use crate::types::{Vec3, Vec4, Vec4u};
+use futures::executor::block_on;
+
+// scikit-learn's KMeans for n x 3 color data. `tol` is the distance every centroid has
+// to move less than to stop, not sklearn's variance-relative tolerance.
+#[pyclass(name = "KMeans", module = "colorcrunch")]
+pub struct PyKMeans {
+    #[pyo3(get)]
+    n_clusters: usize,
+    #[pyo3(get)]
+    max_iter: usize,
+    #[pyo3(get)]
+    tol: f32,
+    #[pyo3(get)]
+    algorithm: String,
+    #[pyo3(get)]
+    random_state: Option<u64>,
+    config: KMeansConfig,
+    fit: Option<KMeansFit<Vec3>>,
+}
+
+fn parse_algorithm(algorithm: &str) -> Result<KMeansAlgorithm, ColorCruncherError> {
+    match algorithm {
+        "lloyd" => Ok(KMeansAlgorithm::Lloyd),
+        "hamerly" => Ok(KMeansAlgorithm::Hamerly),
+        #[cfg(feature = "gpu")]
+        "lloyd-gpu" => Ok(KMeansAlgorithm::LloydGpu),
+        _ => Err(ColorCruncherError::InvalidConfig(format!(
+            "unknown algorithm: {}",
+            algorithm
+        ))),
+    }
+}
+
+// A name, or an n_clusters x 3 array of starting centroids
+fn parse_init(init: &Bound<'_, PyAny>, n_clusters: usize) -> PyResult<Initializer> {
+    if let Ok(name) = init.extract::<String>() {
+        return Ok(named_init(&name)?);
+    }
+    Ok(centroid_init(&points(init)?, n_clusters)?)
+}
+
+fn named_init(name: &str) -> Result<Initializer, ColorCruncherError> {
+    match name {
+        "k-means++" | "kmeans++" => Ok(Initializer::KMeansPlusPlus),
+        "random" => Ok(Initializer::Random),
+        _ => Err(ColorCruncherError::InvalidConfig(format!(
+            "unknown init: {}",
+            name
+        ))),
+    }
+}
+
+fn centroid_init(centroids: &[Vec3], n_clusters: usize) -> Result<Initializer, ColorCruncherError> {
+    if centroids.len() != n_clusters {
+        return Err(ColorCruncherError::InvalidConfig(format!(
+            "init has {} centroids but n_clusters is {}",
+            centroids.len(),
+            n_clusters
+        )));
+    }
+    Ok(Initializer::FromPalette(
+        centroids.iter().map(|c| [c[0], c[1], c[2], 0.0]).collect(),
+    ))
+}
+
+fn rows<T: numpy::Element + Copy + Into<f64>>(
+    array: PyReadonlyArray2<'_, T>,
+) -> Result<Vec<Vec3>, ColorCruncherError> {
+    let features = array.shape()[1];
+    if features != 3 {
+        return Err(ColorCruncherError::InvalidBuffer(format!(
+            "expected an n x 3 array, got {} features",
+            features
+        )));
+    }
+    Ok(array
+        .as_array()
+        .rows()
+        .into_iter()
+        .map(|row| {
+            [
+                row[0].into() as f32,
+                row[1].into() as f32,
+                row[2].into() as f32,
+            ]
+        })
+        .collect())
+}
+
+// Float or byte arrays both work, so images reshaped to (-1, 3) can go straight in
+fn points(x: &Bound<'_, PyAny>) -> PyResult<Vec<Vec3>> {
+    if let Ok(array) = x.extract::<PyReadonlyArray2<'_, f64>>() {
+        return Ok(rows(array)?);
+    }
+    if let Ok(array) = x.extract::<PyReadonlyArray2<'_, f32>>() {
+        return Ok(rows(array)?);
+    }
+    if let Ok(array) = x.extract::<PyReadonlyArray2<'_, u8>>() {
+        return Ok(rows(array)?);
+    }
+    Err(PyTypeError::new_err(
+        "expected a 2-d numpy array of float64, float32 or uint8",
+    ))
+}
+
+fn config(
+    n_clusters: usize,
+    max_iter: usize,
+    tol: f32,
+    algorithm: &str,
+    initializer: Initializer,
+    random_state: Option<u64>,
+) -> Result<KMeansConfig, ColorCruncherError> {
+    if n_clusters == 0 {
+        return Err(ColorCruncherError::InvalidConfig(
+            "n_clusters must be at least 1".to_string(),
+        ));
+    }
+    Ok(KMeansConfig {
+        k: n_clusters,
+        max_iterations: max_iter,
+        tolerance: tol,
+        algorithm: parse_algorithm(algorithm)?,
+        initializer,
+        seed: random_state,
+        fixed_centroids: Vec::new(),
+    })
+}
+
+fn run(config: KMeansConfig, data: &[Vec3]) -> Result<KMeansFit<Vec3>, ColorCruncherError> {
+    let kmeans = KMeans::from_config(config.clone());
+    match config.algorithm {
+        KMeansAlgorithm::Lloyd | KMeansAlgorithm::Hamerly => kmeans.fit(data),
+        // The GPU works on whole-number RGBA pixels
+        #[cfg(feature = "gpu")]
+        KMeansAlgorithm::LloydGpu => {
+            if data.iter().flatten().any(|v| !(0.0..=255.0).contains(v)) {
+                return Err(ColorCruncherError::InvalidBuffer(
+                    "lloyd-gpu needs values between 0 and 255".to_string(),
+                ));
+            }
+            let pixels: Vec<Vec4u> = data
+                .iter()
+                .map(|p| {
+                    [
+                        p[0].round() as u32,
+                        p[1].round() as u32,
+                        p[2].round() as u32,
+                        0,
+                    ]
+                })
+                .collect();
+            let fit = block_on(kmeans.fit_async(&pixels))?;
+            Ok(KMeansFit {
+                assignments: fit.assignments,
+                centroids: fit
+                    .centroids
+                    .iter()
+                    .map(|c: &Vec4| [c[0], c[1], c[2]])
+                    .collect(),
+                inertia: fit.inertia,
+                iterations: fit.iterations,
+            })
+        }
+    }
+}
+
+impl PyKMeans {
+    fn fitted(&self) -> PyResult<&KMeansFit<Vec3>> {
+        self.fit.as_ref().ok_or_else(|| {
+            ColorCruncherError::InvalidConfig(
+                "this KMeans instance is not fitted yet, call fit first".to_string(),
+            )
+            .into()
+        })
+    }
+
+    fn fit_points(&mut self, py: Python<'_>, x: &Bound<'_, PyAny>) -> PyResult<()> {
+        let data = points(x)?;
+        let config = self.config.clone();
+        self.fit = Some(py.allow_threads(|| run(config, &data))?);
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl PyKMeans {
+    #[new]
+    #[pyo3(signature = (n_clusters = 8, *, init = None, max_iter = 300, tol = 1e-4, algorithm = "lloyd", random_state = None))]
+    fn new(
+        n_clusters: usize,
+        init: Option<&Bound<'_, PyAny>>,
+        max_iter: usize,
+        tol: f32,
+        algorithm: &str,
+        random_state: Option<u64>,
+    ) -> PyResult<Self> {
+        let initializer = match init {
+            Some(init) => parse_init(init, n_clusters)?,
+            None => Initializer::KMeansPlusPlus,
+        };
+        let config = config(
+            n_clusters,
+            max_iter,
+            tol,
+            algorithm,
+            initializer,
+            random_state,
+        )?;
+
+        Ok(Self {
+            n_clusters,
+            max_iter,
+            tol,
+            algorithm: algorithm.to_string(),
+            random_state,
+            config,
+            fit: None,
+        })
+    }
+
+    #[pyo3(signature = (x, y = None))]
+    fn fit<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        x: &Bound<'py, PyAny>,
+        y: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        // y is only accepted for pipeline compatibility
+        let _ = y;
+        slf.fit_points(x.py(), x)?;
+        Ok(slf)
+    }
+
+    #[pyo3(signature = (x, y = None))]
+    fn fit_predict<'py>(
+        &mut self,
+        x: &Bound<'py, PyAny>,
+        y: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<Bound<'py, PyArray1<usize>>> {
+        let _ = y;
+        self.fit_points(x.py(), x)?;
+        self.labels_(x.py())
+    }
+
+    fn predict<'py>(&self, x: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyArray1<usize>>> {
+        let centroids = self.fitted()?.centroids.clone();
+        let data = points(x)?;
+        let labels = x.py().allow_threads(|| {
+            data.iter()
+                .map(|point| find_closest_centroid(point, &centroids))
+                .collect::<Vec<_>>()
+        });
+        Ok(PyArray1::from_vec_bound(x.py(), labels))
+    }
+
+    #[getter]
+    fn cluster_centers_<'py>(&self, py: Python<'py>) -> PyResult<PyObject> {
+        let centroids = &self.fitted()?.centroids;
+        let array =
+            PyArray1::from_vec_bound(py, centroids.concat()).reshape([centroids.len(), 3])?;
+        Ok(array.into_py(py))
+    }
+
+    #[getter]
+    fn labels_<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray1<usize>>> {
+        Ok(PyArray1::from_slice_bound(py, &self.fitted()?.assignments))
+    }
+
+    #[getter]
+    fn inertia_(&self) -> PyResult<f32> {
+        Ok(self.fitted()?.inertia)
+    }
+
+    #[getter]
+    fn n_iter_(&self) -> PyResult<usize> {
+        Ok(self.fitted()?.iterations)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "KMeans(n_clusters={}, max_iter={}, tol={}, algorithm='{}')",
+            self.n_clusters, self.max_iter, self.tol, self.algorithm
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three tight groups of 20 colors each
+    fn groups() -> Vec<Vec3> {
+        [
+            [20.0, 20.0, 200.0],
+            [200.0, 40.0, 20.0],
+            [40.0, 220.0, 60.0],
+        ]
+        .iter()
+        .flat_map(|center| {
+            (0..20).map(move |i| {
+                let offset = (i % 5) as f32 - 2.0;
+                [
+                    center[0] + offset,
+                    center[1] - offset,
+                    center[2] + (i / 5) as f32,
+                ]
+            })
+        })
+        .collect()
+    }
+
+    fn kmeans_config(
+        n_clusters: usize,
+        algorithm: &str,
+    ) -> Result<KMeansConfig, ColorCruncherError> {
+        config(
+            n_clusters,
+            300,
+            1e-4,
+            algorithm,
+            Initializer::KMeansPlusPlus,
+            Some(7),
+        )
+    }
+
+    #[test]
+    fn test_lloyd_and_hamerly_agree() {
+        let data = groups();
+        let lloyd = run(kmeans_config(3, "lloyd").unwrap(), &data).unwrap();
+        let hamerly = run(kmeans_config(3, "hamerly").unwrap(), &data).unwrap();
+
+        assert_eq!(lloyd.assignments.len(), data.len());
+        assert_eq!(lloyd.centroids.len(), 3);
+        assert_eq!(lloyd.assignments, hamerly.assignments);
+        for (a, b) in lloyd.centroids.iter().zip(&hamerly.centroids) {
+            assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3));
+        }
+    }
+
+    #[test]
+    fn test_init_from_centroids() {
+        let start = [[0.0, 0.0, 255.0], [255.0, 0.0, 0.0], [0.0, 255.0, 0.0]];
+        let mut config = kmeans_config(3, "lloyd").unwrap();
+        config.initializer = centroid_init(&start, 3).unwrap();
+        let fit = run(config, &groups()).unwrap();
+
+        // Each group ends up with the centroid that started nearest to it
+        assert_eq!(fit.assignments[0], 0);
+        assert_eq!(fit.assignments[20], 1);
+        assert_eq!(fit.assignments[40], 2);
+
+        assert!(matches!(
+            centroid_init(&start, 2),
+            Err(ColorCruncherError::InvalidConfig(_))
+        ));
+        assert!(named_init("random").is_ok());
+        assert!(named_init("farthest").is_err());
+    }
+
+    #[test]
+    fn test_invalid_n_clusters() {
+        assert!(kmeans_config(0, "lloyd").is_err());
+        assert!(kmeans_config(3, "elkan").is_err());
+
+        let data = [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+        assert!(matches!(
+            run(kmeans_config(3, "lloyd").unwrap(), &data),
+            Err(ColorCruncherError::TooFewColors { .. })
+        ));
+    }
+}
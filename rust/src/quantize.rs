@@ -1,29 +1,157 @@
+mod frames;
+mod streaming;
+
+pub use self::frames::FramePalette;
+pub use self::streaming::{PaletteStream, TileRemapper};
+use crate::dither::{Dithering, PatternDitherer};
+use crate::error::ColorCruncherError;
 use crate::kmeans::find_closest_centroid;
 use crate::kmeans::Initializer;
+use crate::kmeans::KCriterion;
 use crate::kmeans::KMeans;
 use crate::kmeans::KMeansAlgorithm;
 use crate::kmeans::KMeansConfig;
-use crate::types::Vec4u;
-use crate::utils::num_distinct_colors_u32;
+use crate::kmeans::{KMeansResult, KRun};
+use crate::metrics::QualityTarget;
+use crate::palette_order::{reorder, PaletteOrder};
+use crate::pixel_layout::PixelLayout;
+use crate::sampling::SamplingStrategy;
+use crate::types::{Vec4, Vec4u};
+use crate::utils::{count_distinct, num_distinct_colors_u32, pack_rgba};
+use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct ColorCruncher {
     kmeans: KMeans,
-    max_colors: usize,
+    max_colors: MaxColors,
+    dithering: Dithering,
+    palette: Option<Vec<[u8; 4]>>,
+    fixed_colors: Vec<[u8; 4]>,
+    palette_order: PaletteOrder,
+    frame_palette: FramePalette,
+    sampling: SamplingStrategy,
     pub sample_rate: usize,
-    pub channels: usize,
+    pub layout: PixelLayout,
+    pub width: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaxColors {
+    Fixed(usize),
+    // Let k-means pick the palette size between min and max (inclusive)
+    Auto {
+        min: usize,
+        max: usize,
+        criterion: KCriterion,
+    },
+    // The smallest palette, up to max colors, that meets the target
+    Target {
+        max: usize,
+        target: QualityTarget,
+    },
+}
+
+impl MaxColors {
+    // The most colors the palette can end up with
+    pub fn upper_bound(&self) -> usize {
+        match self {
+            MaxColors::Fixed(k) => *k,
+            MaxColors::Auto { max, .. } => *max,
+            MaxColors::Target { max, .. } => *max,
+        }
+    }
+
+    // Images with at most this many colors are returned losslessly. Target mode always
+    // searches, since a smaller palette may already be good enough.
+    fn lossless_limit(&self) -> Option<usize> {
+        match self {
+            MaxColors::Target { .. } => None,
+            _ => Some(self.upper_bound()),
+        }
+    }
+}
+
+// Indices are stored in at most 16 bits
+const MAX_PALETTE_SIZE: usize = u16::MAX as usize + 1;
+
+// Palette indices, stored in the narrowest type that can address the whole palette
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexBuffer {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+}
+
+impl IndexBuffer {
+    fn from_indices(indices: &[usize], palette_size: usize) -> Self {
+        debug_assert!(palette_size <= MAX_PALETTE_SIZE);
+        if palette_size <= u8::MAX as usize + 1 {
+            IndexBuffer::U8(indices.iter().map(|&i| i as u8).collect())
+        } else {
+            IndexBuffer::U16(indices.iter().map(|&i| i as u16).collect())
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IndexBuffer::U8(indices) => indices.len(),
+            IndexBuffer::U16(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<usize> {
+        match self {
+            IndexBuffer::U8(indices) => indices.get(i).map(|&index| index as usize),
+            IndexBuffer::U16(indices) => indices.get(i).map(|&index| index as usize),
+        }
+    }
+}
+
+// A quantized image as a palette of RGBA colors plus one palette index per pixel
+#[derive(Debug, Clone)]
+pub struct IndexedImage {
+    pub palette: Vec<[u8; 4]>,
+    pub indices: IndexBuffer,
+    pub width: usize,
+    pub height: usize,
+}
+
+// RGBA colors alongside how many pixels of the source image each one stands for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub colors: Vec<[u8; 4]>,
+    pub counts: Vec<usize>,
+}
+
+impl Palette {
+    // For palettes that don't come from an image, every count is zero
+    pub fn from_colors(colors: Vec<[u8; 4]>) -> Self {
+        let counts = vec![0; colors.len()];
+        Self { colors, counts }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct ColorCruncherBuilder {
-    pub max_colors: Option<usize>,
+    pub max_colors: Option<MaxColors>,
     pub channels: Option<usize>,
+    pub pixel_layout: Option<PixelLayout>,
     pub sample_rate: Option<usize>,
+    pub sampling: Option<SamplingStrategy>,
     pub tolerance: Option<f32>,
     pub max_iterations: Option<usize>,
     pub initializer: Option<Initializer>,
     pub algorithm: Option<KMeansAlgorithm>,
     pub seed: Option<u64>,
+    pub dithering: Option<Dithering>,
+    pub width: Option<usize>,
+    pub palette: Option<Vec<[u8; 4]>>,
+    pub fixed_colors: Option<Vec<[u8; 4]>>,
+    pub palette_order: Option<PaletteOrder>,
+    pub frame_palette: Option<FramePalette>,
 }
 
 impl ColorCruncherBuilder {
@@ -32,20 +160,43 @@ impl ColorCruncherBuilder {
     }
 
     pub fn with_max_colors(mut self, max_colors: usize) -> Self {
-        self.max_colors = Some(max_colors);
+        self.max_colors = Some(MaxColors::Fixed(max_colors));
+        self
+    }
+
+    // Search for the palette size instead of fixing it, see `KCriterion`
+    pub fn with_auto_max_colors(mut self, min: usize, max: usize, criterion: KCriterion) -> Self {
+        self.max_colors = Some(MaxColors::Auto {
+            min,
+            max,
+            criterion,
+        });
         self
     }
 
+    // 1 is gray, 2 gray and alpha, 3 RGB and 4 RGBA. See `with_pixel_layout` for other orders.
     pub fn with_channels(mut self, channels: usize) -> Self {
         self.channels = Some(channels);
         self
     }
 
+    pub fn with_pixel_layout(mut self, pixel_layout: PixelLayout) -> Self {
+        self.pixel_layout = Some(pixel_layout);
+        self
+    }
+
+    // Cluster about 1 in sample_rate pixels, picked by the sampling strategy
     pub fn with_sample_rate(mut self, sample_rate: usize) -> Self {
         self.sample_rate = Some(sample_rate);
         self
     }
 
+    // How pixels are picked for k-means, see `SamplingStrategy`
+    pub fn with_sampling(mut self, sampling: SamplingStrategy) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+
     pub fn with_tolerance(mut self, tolerance: f32) -> Self {
         self.tolerance = Some(tolerance);
         self
@@ -71,110 +222,586 @@ impl ColorCruncherBuilder {
         self
     }
 
-    pub async fn build(&self) -> ColorCruncher {
-        let kmeans_config = self.build_config();
+    pub fn with_dithering(mut self, dithering: Dithering) -> Self {
+        self.dithering = Some(dithering);
+        self
+    }
+
+    // Use this palette as-is instead of running k-means. Takes presets too, e.g.
+    // `with_palette(PalettePreset::Pico8.colors())`.
+    pub fn with_palette(mut self, palette: Vec<[u8; 4]>) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    // Colors that always make it into the palette unchanged. They take the first palette
+    // indices and count towards max_colors; k-means fills in the rest.
+    pub fn with_fixed_colors(mut self, fixed_colors: Vec<[u8; 4]>) -> Self {
+        self.fixed_colors = Some(fixed_colors);
+        self
+    }
+
+    // How to order the palette of indexed output and create_palette. Indices follow the new
+    // order. Fixed colors keep the first indices, and `with_palette` palettes aren't reordered.
+    pub fn with_palette_order(mut self, palette_order: PaletteOrder) -> Self {
+        self.palette_order = Some(palette_order);
+        self
+    }
+
+    // How `quantize_frames` chooses palettes across an animation
+    pub fn with_frame_palette(mut self, frame_palette: FramePalette) -> Self {
+        self.frame_palette = Some(frame_palette);
+        self
+    }
+
+    // Search for the smallest palette (up to max colors) that meets the quality target.
+    // Quality is measured on RGB of the sampled pixels.
+    pub fn with_quality_target(mut self, max: usize, target: QualityTarget) -> Self {
+        self.max_colors = Some(MaxColors::Target { max, target });
+        self
+    }
+
+    // Width of the image in pixels. Ordered dithering needs it to know where each pixel sits.
+    pub fn with_width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    pub async fn build(&self) -> Result<ColorCruncher, ColorCruncherError> {
+        self.validate()?;
+        let layout = self.layout()?;
+        let kmeans_config = self.build_config(layout);
         let kmeans = KMeans::new(kmeans_config.clone()).await;
 
-        ColorCruncher {
+        Ok(ColorCruncher {
             kmeans,
-            max_colors: kmeans_config.k,
+            max_colors: self
+                .max_colors
+                .clone()
+                .unwrap_or(MaxColors::Fixed(kmeans_config.k)),
+            dithering: self.dithering.clone().unwrap_or_default(),
+            palette: self.palette.clone(),
+            fixed_colors: self.fixed_colors.clone().unwrap_or_default(),
+            palette_order: self.palette_order.unwrap_or_default(),
+            frame_palette: self.frame_palette.unwrap_or_default(),
+            sampling: self.sampling.unwrap_or_default(),
             sample_rate: self.sample_rate.unwrap_or(1),
-            channels: self.channels.unwrap_or(3),
+            layout,
+            width: self.width,
+        })
+    }
+
+    // Catches settings that would otherwise only fail halfway through quantizing
+    fn validate(&self) -> Result<(), ColorCruncherError> {
+        let invalid = |message: String| Err(ColorCruncherError::InvalidConfig(message));
+
+        if let Some(max_colors) = &self.max_colors {
+            let upper_bound = max_colors.upper_bound();
+            if upper_bound == 0 {
+                return invalid("max colors must be at least 1".to_string());
+            }
+            if upper_bound > MAX_PALETTE_SIZE {
+                return invalid(format!(
+                    "max colors must be at most {}, got {}",
+                    MAX_PALETTE_SIZE, upper_bound
+                ));
+            }
+            if let MaxColors::Auto { min, max, .. } = max_colors {
+                if min > max {
+                    return invalid(format!("min colors is greater than max: {} > {}", min, max));
+                }
+            }
+            let fixed = self.fixed_colors.as_ref().map_or(0, Vec::len);
+            if fixed > upper_bound {
+                return invalid(format!(
+                    "number of fixed colors is greater than max colors: {} > {}",
+                    fixed, upper_bound
+                ));
+            }
+        }
+        if self.sample_rate == Some(0) {
+            return invalid("sample rate must be at least 1".to_string());
+        }
+        if self.sampling == Some(SamplingStrategy::Reservoir { size: 0 }) {
+            return invalid("reservoir size must be at least 1".to_string());
+        }
+        if self.width == Some(0) {
+            return invalid("width must be at least 1".to_string());
+        }
+        if self.palette.as_ref().is_some_and(Vec::is_empty) {
+            return invalid("palette is empty".to_string());
+        }
+        for (name, colors) in [
+            ("fixed colors", &self.fixed_colors),
+            ("palette", &self.palette),
+        ] {
+            let len = colors.as_ref().map_or(0, Vec::len);
+            if len > MAX_PALETTE_SIZE {
+                return invalid(format!(
+                    "{} can hold at most {} colors, got {}",
+                    name, MAX_PALETTE_SIZE, len
+                ));
+            }
+        }
+        if let Some(FramePalette::PerFrame {
+            max_drift: Some(max_drift),
+        }) = self.frame_palette
+        {
+            if max_drift.is_nan() || max_drift < 0.0 {
+                return invalid(format!("max drift must be at least 0, got {}", max_drift));
+            }
+        }
+        Ok(())
+    }
+
+    // An explicit layout wins, as long as it agrees with the channel count if one was given
+    fn layout(&self) -> Result<PixelLayout, ColorCruncherError> {
+        match (self.pixel_layout, self.channels) {
+            (Some(layout), Some(channels)) if layout.channels() != channels => {
+                Err(ColorCruncherError::InvalidConfig(format!(
+                    "{} layout has {} channels, not {}",
+                    layout,
+                    layout.channels(),
+                    channels
+                )))
+            }
+            (Some(layout), _) => Ok(layout),
+            (None, Some(channels)) => PixelLayout::from_channels(channels).ok_or_else(|| {
+                ColorCruncherError::InvalidConfig(format!(
+                    "unsupported number of channels: {}",
+                    channels
+                ))
+            }),
+            (None, None) => Ok(PixelLayout::default()),
         }
     }
 
-    fn build_config(&self) -> KMeansConfig {
+    fn build_config(&self, layout: PixelLayout) -> KMeansConfig {
         let default_config = KMeansConfig::default();
-        let mut config = KMeansConfig::default();
-        config.k = self.max_colors.unwrap_or_else(|| default_config.k);
-        config.max_iterations = self
-            .max_iterations
-            .unwrap_or_else(|| default_config.max_iterations);
-        config.tolerance = self.tolerance.unwrap_or_else(|| default_config.tolerance);
-        config.algorithm = self
-            .algorithm
-            .clone()
-            .unwrap_or_else(|| default_config.algorithm);
-        config.initializer = self
-            .initializer
-            .clone()
-            .unwrap_or_else(|| default_config.initializer);
-        config.seed = self.seed;
-        config
+        KMeansConfig {
+            k: self
+                .max_colors
+                .as_ref()
+                .map_or(default_config.k, MaxColors::upper_bound),
+            max_iterations: self.max_iterations.unwrap_or(default_config.max_iterations),
+            tolerance: self.tolerance.unwrap_or(default_config.tolerance),
+            algorithm: self.algorithm.clone().unwrap_or(default_config.algorithm),
+            initializer: self
+                .initializer
+                .clone()
+                .unwrap_or(default_config.initializer),
+            seed: self.seed,
+            fixed_centroids: self
+                .fixed_colors
+                .as_deref()
+                .map(|fixed_colors| palette_to_centroids(fixed_colors, layout))
+                .unwrap_or_default(),
+        }
     }
 }
 
 impl ColorCruncher {
     fn chunk_pixels_vec4u(&self, pixels: &[u8]) -> Vec<Vec4u> {
-        pixels
-            .chunks_exact(self.channels)
-            .step_by(self.sample_rate)
-            .map(|chunk| {
-                [
-                    chunk[0] as u32,
-                    chunk[1] as u32,
-                    chunk[2] as u32,
-                    chunk[3] as u32,
-                ]
-            })
+        self.sample_frames(&[pixels])
+    }
+
+    // Samples the frames as one image, each frame below the one before it
+    fn sample_frames(&self, frames: &[&[u8]]) -> Vec<Vec4u> {
+        let channels = self.layout.channels();
+        if self.sampling == SamplingStrategy::Stride {
+            return frames
+                .iter()
+                .flat_map(|frame| frame.chunks_exact(channels))
+                .step_by(self.sample_rate)
+                .map(|chunk| self.layout.to_rgba(chunk).map(u32::from))
+                .collect();
+        }
+
+        // The first pixel of each frame, counting from the first frame
+        let mut starts = Vec::with_capacity(frames.len());
+        let mut num_pixels = 0;
+        for frame in frames {
+            starts.push(num_pixels);
+            num_pixels += frame.len() / channels;
+        }
+        let color = |i: usize| {
+            let frame = starts.partition_point(|&start| start <= i) - 1;
+            let i = i - starts[frame];
+            self.layout
+                .to_rgba(&frames[frame][i * channels..(i + 1) * channels])
+        };
+        self.sampling
+            .sample_indices(
+                num_pixels,
+                self.image_width(num_pixels),
+                self.sample_rate,
+                self.kmeans.seed(),
+                color,
+            )
+            .into_iter()
+            .map(|i| color(i).map(u32::from))
             .collect()
     }
 
-    pub async fn quantize_image(&self, pixels: &[u8]) -> Vec<u8> {
+    // The buffer has to hold whole pixels, and whole rows when the width is known
+    fn check_buffer(&self, pixels: &[u8]) -> Result<(), ColorCruncherError> {
+        let channels = self.layout.channels();
+        if !pixels.len().is_multiple_of(channels) {
+            return Err(ColorCruncherError::InvalidBuffer(format!(
+                "length {} is not a multiple of {} channels",
+                pixels.len(),
+                channels
+            )));
+        }
+        let num_pixels = pixels.len() / channels;
+        if let Some(width) = self.width {
+            if !num_pixels.is_multiple_of(width) {
+                return Err(ColorCruncherError::InvalidBuffer(format!(
+                    "{} pixels don't fill rows of width {}",
+                    num_pixels, width
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn quantize_image(&self, pixels: &[u8]) -> Result<Vec<u8>, ColorCruncherError> {
+        if let Some(palette) = &self.palette {
+            return self.apply_palette(pixels, palette);
+        }
+        self.check_buffer(pixels)?;
+
+        // If the whole image already fits in the palette, return the original pixels
+        if self.fits_losslessly(pixels) {
+            return Ok(pixels.to_vec());
+        }
+
         let image_data = self.chunk_pixels_vec4u(pixels);
+        let (_, centroids) = self.run_kmeans(&image_data).await?;
+        Ok(self.remap_pixels(pixels, &centroids))
+    }
 
-        // If there's already less than or equal to the max number of colors, return the original pixels
-        if num_distinct_colors_u32(&image_data) <= self.max_colors {
-            return pixels.to_vec();
+    // Maps pixels straight onto a caller-supplied palette, skipping k-means entirely
+    pub fn apply_palette(
+        &self,
+        pixels: &[u8],
+        palette: &[[u8; 4]],
+    ) -> Result<Vec<u8>, ColorCruncherError> {
+        if palette.is_empty() {
+            return Err(ColorCruncherError::InvalidConfig(
+                "palette is empty".to_string(),
+            ));
         }
+        self.check_buffer(pixels)?;
+        Ok(self.remap_pixels(pixels, &palette_to_centroids(palette, self.layout)))
+    }
 
-        let (_, centroids) = self.kmeans.run_async(&image_data).await.unwrap();
+    pub async fn quantize_indexed(
+        &self,
+        pixels: &[u8],
+    ) -> Result<IndexedImage, ColorCruncherError> {
+        let num_pixels = pixels.len() / self.layout.channels();
+        let width = self.image_width(num_pixels);
+        let (palette, indices, _) = self.index_pixels(pixels).await?;
 
-        let mut new_image = Vec::with_capacity(pixels.len());
-        for pixel in pixels.chunks_exact(self.channels) {
-            let px_vec = [
-                pixel[0] as f32,
-                pixel[1] as f32,
-                pixel[2] as f32,
-                pixel[3] as f32,
-            ];
-            let closest_centroid = find_closest_centroid(&px_vec, &centroids);
-            let new_color = &centroids[closest_centroid];
-
-            if self.channels == 3 {
-                new_image.extend_from_slice(&[
-                    new_color[0] as u8,
-                    new_color[1] as u8,
-                    new_color[2] as u8,
-                ]);
-            } else {
-                new_image.extend_from_slice(&[
-                    new_color[0] as u8,
-                    new_color[1] as u8,
-                    new_color[2] as u8,
-                    pixel[3],
-                ]);
-            }
+        Ok(IndexedImage {
+            indices: IndexBuffer::from_indices(&indices, palette.len()),
+            palette,
+            width,
+            height: num_pixels / width,
+        })
+    }
+
+    pub async fn create_palette(&self, pixels: &[u8]) -> Result<Palette, ColorCruncherError> {
+        let (colors, _, counts) = self.index_pixels(pixels).await?;
+        Ok(Palette { colors, counts })
+    }
+
+    // Shared by the indexed and palette APIs: the RGBA palette in the configured order,
+    // each pixel's index into it and how many pixels use each entry
+    async fn index_pixels(
+        &self,
+        pixels: &[u8],
+    ) -> Result<(Vec<[u8; 4]>, Vec<usize>, Vec<usize>), ColorCruncherError> {
+        self.check_buffer(pixels)?;
+        let (mut colors, mut indices) = self.unordered_index_pixels(pixels).await?;
+
+        let mut counts = vec![0; colors.len()];
+        for &index in &indices {
+            counts[index] += 1;
         }
 
-        new_image
+        self.order_palette(&mut colors, &mut counts, &mut indices);
+        Ok((colors, indices, counts))
     }
 
-    pub async fn create_palette(&self, pixels: &[u8]) -> Vec<[u8; 3]> {
-        let image_data = self.chunk_pixels_vec4u(pixels);
+    // The palette_order permutation for a palette. Fixed colors keep the first indices,
+    // and a caller's palette keeps the order it was given in.
+    fn palette_permutation(&self, colors: &[[u8; 4]], counts: &[usize]) -> Vec<usize> {
+        let pinned = match self.palette {
+            Some(_) => colors.len(),
+            None => self.fixed_colors.len().min(colors.len()),
+        };
+        let tail = self
+            .palette_order
+            .permutation(&colors[pinned..], counts.get(pinned..).unwrap_or_default());
+        (0..pinned)
+            .chain(tail.into_iter().map(|i| i + pinned))
+            .collect()
+    }
+
+    fn order_palette(
+        &self,
+        colors: &mut Vec<[u8; 4]>,
+        counts: &mut Vec<usize>,
+        indices: &mut [usize],
+    ) {
+        let order = self.palette_permutation(colors, counts);
+        reorder(&order, colors, counts, indices);
+    }
+
+    async fn unordered_index_pixels(
+        &self,
+        pixels: &[u8],
+    ) -> Result<(Vec<[u8; 4]>, Vec<usize>), ColorCruncherError> {
+        // The builder rejects empty palettes, so this one has at least one color
+        if let Some(palette) = &self.palette {
+            let indices = self.remap_indices(pixels, &palette_to_centroids(palette, self.layout));
+            return Ok((palette.clone(), indices));
+        }
 
-        // If there's already less than or equal to the max number of colors, return the original pixels
-        if num_distinct_colors_u32(&image_data) < self.max_colors {
-            // todo
-            todo!()
+        // Few enough colors already, so the image can be indexed losslessly
+        if let Some(exact) = self.exact_palette(pixels) {
+            return Ok(exact);
         }
 
-        let (_, centroids) = self.kmeans.run_async(&image_data).await.unwrap();
+        let image_data = self.chunk_pixels_vec4u(pixels);
+        let (assignments, centroids) = self.run_kmeans(&image_data).await?;
+
+        // When every pixel went through k-means undithered, its assignments are already the
+        // indices. Samples that cover the whole image are every pixel in order.
+        let num_pixels = pixels.len() / self.layout.channels();
+        let indices = if image_data.len() == num_pixels && matches!(self.dithering, Dithering::None)
+        {
+            assignments
+        } else {
+            self.remap_indices(pixels, &centroids)
+        };
+
+        Ok((self.centroids_to_palette(&centroids), indices))
+    }
+
+    // Layouts without alpha always get opaque palettes
+    fn centroids_to_palette(&self, centroids: &[Vec4]) -> Vec<[u8; 4]> {
         centroids
             .iter()
-            .map(|color| [color[0] as u8, color[1] as u8, color[2] as u8])
+            .map(|color| {
+                let alpha = if self.layout.has_alpha() {
+                    color[3] as u8
+                } else {
+                    u8::MAX
+                };
+                [color[0] as u8, color[1] as u8, color[2] as u8, alpha]
+            })
             .collect()
     }
+
+    async fn run_kmeans(&self, data: &[Vec4u]) -> KMeansResult<Vec4> {
+        match &self.max_colors {
+            MaxColors::Fixed(k) => {
+                // A sample can hold fewer colors than the image it was drawn from
+                let fixed = self.fixed_colors.len();
+                let requested = k.saturating_sub(fixed);
+                let k = fixed + num_distinct_colors_u32(data, requested).min(requested);
+                self.kmeans.clone().with_k(k).run_async(data).await
+            }
+            MaxColors::Auto {
+                min,
+                max,
+                criterion,
+            } => self.kmeans.run_auto(data, *min, *max, *criterion).await,
+            MaxColors::Target { max, target } => self.run_until_target(data, *max, target).await,
+        }
+    }
+
+    // Grows k one at a time, each run warm-started from the last, until the target is met.
+    // Falls back to the largest palette tried if it never is.
+    async fn run_until_target(
+        &self,
+        data: &[Vec4u],
+        max: usize,
+        target: &QualityTarget,
+    ) -> KMeansResult<Vec4> {
+        let min = self.fixed_colors.len().max(1);
+        // k-means can't find more clusters than there are colors
+        let max = max.min(num_distinct_colors_u32(data, max)).max(min);
+
+        let mut result = None;
+        let mut error = None;
+        self.kmeans
+            .search_k(data, min..=max, |run| {
+                let met = match target_met(target, &run) {
+                    Ok(met) => met,
+                    Err(e) => {
+                        error = Some(e);
+                        return false;
+                    }
+                };
+                result = Some((run.assignments.to_vec(), run.centroids.to_vec()));
+                !met
+            })
+            .await?;
+        if let Some(e) = error {
+            return Err(e);
+        }
+        result.ok_or_else(|| {
+            ColorCruncherError::InvalidConfig("no palette sizes to search".to_string())
+        })
+    }
+
+    // Without a width we treat the buffer as a single row
+    fn image_width(&self, num_pixels: usize) -> usize {
+        self.width.unwrap_or(num_pixels).max(1)
+    }
+
+    // Whether the image and the fixed colors together have few enough colors for
+    // exact_palette, without building its index
+    fn fits_losslessly(&self, pixels: &[u8]) -> bool {
+        let Some(limit) = self.max_colors.lossless_limit() else {
+            return false;
+        };
+        let colors = self.fixed_colors.iter().copied().chain(
+            pixels
+                .chunks_exact(self.layout.channels())
+                .map(|pixel| self.layout.to_rgba(pixel)),
+        );
+        count_distinct(colors.map(pack_rgba), limit) <= limit
+    }
+
+    // Returns the distinct colors of the image and each pixel's index into them,
+    // or None as soon as there are more than max_colors of them.
+    fn exact_palette(&self, pixels: &[u8]) -> Option<(Vec<[u8; 4]>, Vec<usize>)> {
+        self.exact_palette_frames(&[pixels])
+    }
+
+    // Like exact_palette, over several frames with their indices one after the other
+    fn exact_palette_frames(&self, frames: &[&[u8]]) -> Option<(Vec<[u8; 4]>, Vec<usize>)> {
+        let limit = self.max_colors.lossless_limit()?;
+        let mut palette = self.fixed_colors.clone();
+        let mut lookup: HashMap<u32, usize> = palette
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| (pack_rgba(color), i))
+            .collect();
+        let channels = self.layout.channels();
+        let num_pixels = frames.iter().map(|frame| frame.len() / channels).sum();
+        let mut indices = Vec::with_capacity(num_pixels);
+
+        for pixel in frames.iter().flat_map(|frame| frame.chunks_exact(channels)) {
+            let color = self.layout.to_rgba(pixel);
+            let index = match lookup.get(&pack_rgba(color)) {
+                Some(&index) => index,
+                None => {
+                    if palette.len() >= limit {
+                        return None;
+                    }
+                    palette.push(color);
+                    lookup.insert(pack_rgba(color), palette.len() - 1);
+                    palette.len() - 1
+                }
+            };
+            indices.push(index);
+        }
+
+        Some((palette, indices))
+    }
+
+    fn remap_indices(&self, pixels: &[u8], centroids: &[Vec4]) -> Vec<usize> {
+        let channels = self.layout.channels();
+        let width = self.image_width(pixels.len() / channels);
+        let mut pattern_ditherer = PatternDitherer::new(centroids);
+
+        pixels
+            .chunks_exact(channels)
+            .enumerate()
+            .map(|(i, pixel)| {
+                self.index_pixel(
+                    pixel,
+                    centroids,
+                    &mut pattern_ditherer,
+                    i % width,
+                    i / width,
+                )
+            })
+            .collect()
+    }
+
+    // x and y only matter for dithering
+    fn index_pixel(
+        &self,
+        pixel: &[u8],
+        centroids: &[Vec4],
+        pattern_ditherer: &mut PatternDitherer,
+        x: usize,
+        y: usize,
+    ) -> usize {
+        let px_vec = self.layout.to_rgba(pixel).map(f32::from);
+        match self.dithering {
+            Dithering::None => find_closest_centroid(&px_vec, centroids),
+            Dithering::Pattern => pattern_ditherer.index_at(&px_vec, x, y),
+        }
+    }
+
+    fn remap_pixels(&self, pixels: &[u8], centroids: &[Vec4]) -> Vec<u8> {
+        let indices = self.remap_indices(pixels, centroids);
+
+        let mut new_image = Vec::with_capacity(pixels.len());
+        for &index in &indices {
+            self.extend_remapped(&mut new_image, &centroids[index]);
+        }
+
+        new_image
+    }
+
+    // Alpha is clustered with the color, so it comes from the palette as well. This keeps
+    // the output the same as expanding `quantize_indexed`.
+    fn extend_remapped(&self, new_image: &mut Vec<u8>, new_color: &Vec4) {
+        self.layout
+            .extend_from_rgba(new_image, new_color.map(|channel| channel as u8));
+    }
+}
+
+// Compares the sampled pixels against their centroids, truncated to bytes like the output
+fn target_met(target: &QualityTarget, run: &KRun) -> Result<bool, ColorCruncherError> {
+    let rgb = |color: &Vec4| [color[0] as u8, color[1] as u8, color[2] as u8];
+    let original: Vec<u8> = run.data.iter().flat_map(rgb).collect();
+    let quantized: Vec<u8> = run
+        .assignments
+        .iter()
+        .flat_map(|&cluster| rgb(&run.centroids[cluster]))
+        .collect();
+    target.is_met(&original, &quantized, 3)
+}
+
+// Pixels of layouts without alpha are opaque, so palette alpha there mustn't pull them
+// towards or away from an entry
+fn palette_to_centroids(palette: &[[u8; 4]], layout: PixelLayout) -> Vec<Vec4> {
+    palette
+        .iter()
+        .map(|color| {
+            let alpha = if layout.has_alpha() {
+                color[3]
+            } else {
+                u8::MAX
+            };
+            [
+                color[0] as f32,
+                color[1] as f32,
+                color[2] as f32,
+                alpha as f32,
+            ]
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -198,9 +825,584 @@ mod tests {
                 .with_sample_rate(sample_rate)
                 .with_channels(channels)
                 .build(),
-        );
+        )
+        .unwrap();
+
+        let result = block_on(quantizer.quantize_image(&data)).unwrap();
+        assert_eq!(result.len(), data.len());
+    }
+
+    #[test]
+    fn test_pattern_dithering_only_uses_palette_colors() {
+        // A horizontal gradient from black to white, 8 pixels wide and 4 tall
+        let width = 8;
+        let data: Vec<u8> = (0..4)
+            .flat_map(|_| {
+                (0..width).flat_map(|x| {
+                    let v = (x * 255 / (width - 1)) as u8;
+                    [v, v, v, 255]
+                })
+            })
+            .collect();
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(2)
+                .with_channels(4)
+                .with_width(width)
+                .with_dithering(Dithering::Pattern)
+                .with_seed(42)
+                .build(),
+        )
+        .unwrap();
 
-        let result = block_on(quantizer.quantize_image(&data));
+        let result = block_on(quantizer.quantize_image(&data)).unwrap();
         assert_eq!(result.len(), data.len());
+
+        let distinct: std::collections::HashSet<_> = result.chunks_exact(4).collect();
+        assert!(distinct.len() <= 2);
+    }
+
+    #[test]
+    fn test_quantize_indexed_matches_quantize_image() {
+        // Alpha varies too, so it has to come from the palette in both
+        let data: Vec<u8> = (0..64u32)
+            .flat_map(|i| {
+                [
+                    (i * 4) as u8,
+                    (255 - i * 4) as u8,
+                    (i * 2) as u8,
+                    (i * 3) as u8,
+                ]
+            })
+            .collect();
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_width(8)
+                .with_seed(42)
+                .build(),
+        )
+        .unwrap();
+
+        let indexed = block_on(quantizer.quantize_indexed(&data)).unwrap();
+        assert_eq!((indexed.width, indexed.height), (8, 8));
+        assert_eq!(indexed.palette.len(), 4);
+        assert_eq!(indexed.indices.len(), 64);
+
+        let expanded: Vec<u8> = (0..indexed.indices.len())
+            .flat_map(|i| indexed.palette[indexed.indices.get(i).unwrap()])
+            .collect();
+        let quantized = block_on(quantizer.quantize_image(&data)).unwrap();
+        assert_eq!(expanded, quantized);
+    }
+
+    #[test]
+    fn test_quantize_indexed_is_lossless_with_few_colors() {
+        let data = vec![
+            255, 0, 0, 255, 0, 0, 255, 128, 255, 0, 0, 255, 0, 0, 255, 128,
+        ];
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_width(2)
+                .build(),
+        )
+        .unwrap();
+
+        let indexed = block_on(quantizer.quantize_indexed(&data)).unwrap();
+        assert_eq!(indexed.palette, vec![[255, 0, 0, 255], [0, 0, 255, 128]]);
+        assert_eq!(indexed.indices, IndexBuffer::U8(vec![0, 1, 0, 1]));
+        assert_eq!((indexed.width, indexed.height), (2, 2));
+    }
+
+    #[test]
+    fn test_lossless_shortcut_counts_colors_exactly() {
+        // (3, 0, 0) and (0, 2, 0) used to count as one color
+        let data = vec![3, 0, 0, 0, 2, 0, 200, 200, 200, 3, 0, 0];
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(2)
+                .with_channels(3)
+                .with_seed(42)
+                .build(),
+        )
+        .unwrap();
+
+        let result = block_on(quantizer.quantize_image(&data)).unwrap();
+        let distinct: std::collections::HashSet<_> = result.chunks_exact(3).collect();
+        assert_eq!(distinct.len(), 2);
+
+        // Sampling must not hide colors from the shortcut either
+        let sampled = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(2)
+                .with_channels(3)
+                .with_sample_rate(2)
+                .with_seed(42)
+                .build(),
+        )
+        .unwrap();
+        let data = vec![
+            0, 0, 0, 100, 100, 100, 0, 0, 0, 200, 200, 200, 250, 250, 250,
+        ];
+        let result = block_on(sampled.quantize_image(&data)).unwrap();
+        let distinct: std::collections::HashSet<_> = result.chunks_exact(3).collect();
+        assert!(distinct.len() <= 2);
+    }
+
+    #[test]
+    fn test_sample_with_fewer_colors_than_max() {
+        let data: Vec<u8> = (0..100u32)
+            .flat_map(|i| [(i * 2) as u8, (255 - i * 2) as u8, i as u8, 255])
+            .collect();
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(8)
+                .with_channels(4)
+                .with_sample_rate(50)
+                .with_seed(42)
+                .build(),
+        )
+        .unwrap();
+
+        let result = block_on(quantizer.quantize_image(&data)).unwrap();
+        assert_eq!(result.len(), data.len());
+        let indexed = block_on(quantizer.quantize_indexed(&data)).unwrap();
+        assert!(indexed.palette.len() <= 2);
+    }
+
+    #[test]
+    fn test_create_palette_returns_exact_colors() {
+        let data = vec![
+            255, 0, 0, 255, 0, 0, 255, 128, 255, 0, 0, 255, 255, 0, 0, 255,
+        ];
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(8)
+                .with_channels(4)
+                .build(),
+        )
+        .unwrap();
+
+        let palette = block_on(quantizer.create_palette(&data)).unwrap();
+        assert_eq!(palette.colors, vec![[255, 0, 0, 255], [0, 0, 255, 128]]);
+        assert_eq!(palette.counts, vec![3, 1]);
+    }
+
+    #[test]
+    fn test_create_palette_counts_every_pixel() {
+        let data: Vec<u8> = (0..100u32)
+            .flat_map(|i| [(i * 2) as u8, (255 - i * 2) as u8, i as u8, 255])
+            .collect();
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(3)
+                .with_channels(4)
+                .with_sample_rate(3)
+                .with_seed(42)
+                .build(),
+        )
+        .unwrap();
+
+        let palette = block_on(quantizer.create_palette(&data)).unwrap();
+        assert_eq!(palette.colors.len(), 3);
+        assert_eq!(palette.counts.iter().sum::<usize>(), 100);
+    }
+
+    #[test]
+    fn test_palette_order_remaps_indices() {
+        let data: Vec<u8> = (0..64u32)
+            .flat_map(|i| [(i * 4) as u8, (255 - i * 4) as u8, (i * 2) as u8, 255])
+            .collect();
+        let builder = ColorCruncherBuilder::default()
+            .with_max_colors(4)
+            .with_channels(4)
+            .with_seed(42);
+
+        let unordered =
+            block_on(block_on(builder.build()).unwrap().quantize_indexed(&data)).unwrap();
+        let ordered = block_on(
+            block_on(builder.with_palette_order(PaletteOrder::Luminance).build())
+                .unwrap()
+                .quantize_indexed(&data),
+        )
+        .unwrap();
+
+        let lumas: Vec<f32> = ordered
+            .palette
+            .iter()
+            .map(|c| crate::dither::luma(&[c[0] as f32, c[1] as f32, c[2] as f32, c[3] as f32]))
+            .collect();
+        assert!(lumas.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        for i in 0..unordered.indices.len() {
+            let before = unordered.palette[unordered.indices.get(i).unwrap()];
+            let after = ordered.palette[ordered.indices.get(i).unwrap()];
+            assert_eq!(before, after);
+        }
+    }
+
+    #[test]
+    fn test_palette_order_keeps_fixed_colors_first() {
+        let data: Vec<u8> = (0..64u32)
+            .flat_map(|i| [(i * 4) as u8, (255 - i * 4) as u8, (i * 2) as u8, 255])
+            .collect();
+        let white = [255, 255, 255, 255];
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_seed(42)
+                .with_fixed_colors(vec![white])
+                .with_palette_order(PaletteOrder::Luminance)
+                .build(),
+        )
+        .unwrap();
+
+        let indexed = block_on(quantizer.quantize_indexed(&data)).unwrap();
+        assert_eq!(indexed.palette[0], white);
+        let lumas: Vec<f32> = indexed.palette[1..]
+            .iter()
+            .map(|c| crate::dither::luma(&c.map(f32::from)))
+            .collect();
+        assert!(lumas.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn test_palette_order_leaves_a_given_palette_alone() {
+        let palette = vec![[255, 255, 255, 255], [0, 0, 0, 255], [255, 0, 0, 255]];
+        let data: Vec<u8> = palette.concat().repeat(3);
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_channels(4)
+                .with_palette(palette.clone())
+                .with_palette_order(PaletteOrder::Luminance)
+                .build(),
+        )
+        .unwrap();
+
+        let indexed = block_on(quantizer.quantize_indexed(&data)).unwrap();
+        assert_eq!(indexed.palette, palette);
+        assert_eq!(indexed.indices, IndexBuffer::U8([0, 1, 2].repeat(3)));
+    }
+
+    #[test]
+    fn test_auto_max_colors() {
+        let centers = [[40u8, 40, 200], [200, 60, 40], [90, 210, 90]];
+        let data: Vec<u8> = centers
+            .iter()
+            .flat_map(|c| (0..25u8).map(move |i| [c[0] + i % 5, c[1] + i / 5, c[2], 255]))
+            .flatten()
+            .collect();
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_auto_max_colors(2, 6, KCriterion::Silhouette)
+                .with_channels(4)
+                .with_seed(42)
+                .build(),
+        )
+        .unwrap();
+
+        let palette = block_on(quantizer.create_palette(&data)).unwrap();
+        assert_eq!(palette.colors.len(), 3);
+        assert_eq!(palette.counts, vec![25, 25, 25]);
+    }
+
+    #[test]
+    fn test_quality_target_picks_smallest_palette() {
+        let data: Vec<u8> = (0..32 * 32)
+            .flat_map(|i| [(i % 32 * 8) as u8, (i / 32 * 8) as u8, 128, 255])
+            .collect();
+        let build = |target| {
+            block_on(
+                ColorCruncherBuilder::default()
+                    .with_quality_target(64, target)
+                    .with_channels(4)
+                    .with_seed(42)
+                    .build(),
+            )
+            .unwrap()
+        };
+
+        let loose = build(QualityTarget::MinPsnr(24.0));
+        let loose_palette = block_on(loose.create_palette(&data)).unwrap();
+        let result = block_on(loose.quantize_image(&data)).unwrap();
+        assert!(crate::metrics::psnr(&data, &result).unwrap() >= 24.0);
+        assert!(loose_palette.colors.len() < 64);
+
+        let strict = build(QualityTarget::MinPsnr(28.0));
+        let strict_palette = block_on(strict.create_palette(&data)).unwrap();
+        assert!(strict_palette.colors.len() > loose_palette.colors.len());
+
+        let delta_e = build(QualityTarget::MaxMeanDeltaE(4.0));
+        let result = block_on(delta_e.quantize_image(&data)).unwrap();
+        assert!(crate::metrics::delta_e(&data, &result, 4).unwrap().0 <= 4.0);
+    }
+
+    #[test]
+    fn test_build_rejects_invalid_config() {
+        let build = |builder: ColorCruncherBuilder| block_on(builder.build()).err();
+
+        assert!(matches!(
+            build(ColorCruncherBuilder::default().with_max_colors(0)),
+            Some(ColorCruncherError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            build(ColorCruncherBuilder::default().with_channels(5)),
+            Some(ColorCruncherError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            build(
+                ColorCruncherBuilder::default()
+                    .with_sampling(SamplingStrategy::Reservoir { size: 0 })
+            ),
+            Some(ColorCruncherError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            build(
+                ColorCruncherBuilder::default()
+                    .with_max_colors(1)
+                    .with_fixed_colors(vec![[0, 0, 0, 255], [255; 4]])
+            ),
+            Some(ColorCruncherError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            build(ColorCruncherBuilder::default().with_palette(vec![])),
+            Some(ColorCruncherError::InvalidConfig(_))
+        ));
+
+        // Indices can't address more than 65536 colors
+        assert!(matches!(
+            build(ColorCruncherBuilder::default().with_max_colors(65537)),
+            Some(ColorCruncherError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            build(ColorCruncherBuilder::default().with_palette(vec![[0; 4]; 65537])),
+            Some(ColorCruncherError::InvalidConfig(_))
+        ));
+        assert!(block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(65536)
+                .build()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_bad_buffers_are_errors() {
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(2)
+                .with_channels(4)
+                .with_width(3)
+                .build(),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            block_on(quantizer.quantize_image(&[0, 0, 0, 255, 0])),
+            Err(ColorCruncherError::InvalidBuffer(_))
+        ));
+        assert!(matches!(
+            block_on(quantizer.quantize_indexed(&[0; 16])),
+            Err(ColorCruncherError::InvalidBuffer(_))
+        ));
+        assert!(matches!(
+            quantizer.apply_palette(&[0; 12], &[]),
+            Err(ColorCruncherError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_every_pixel_layout() {
+        let colors = [[200, 40, 40, 255], [190, 50, 40, 255], [30, 30, 220, 128]];
+
+        for layout in [
+            PixelLayout::Gray,
+            PixelLayout::GrayAlpha,
+            PixelLayout::Rgb,
+            PixelLayout::Rgba,
+            PixelLayout::Bgra,
+            PixelLayout::Argb,
+        ] {
+            let mut data = Vec::new();
+            for &color in &colors {
+                layout.extend_from_rgba(&mut data, color);
+            }
+            let decode = |buffer: &[u8]| -> Vec<[u8; 4]> {
+                buffer
+                    .chunks_exact(layout.channels())
+                    .map(|pixel| layout.to_rgba(pixel))
+                    .collect()
+            };
+            let original = decode(&data);
+
+            let quantizer = block_on(
+                ColorCruncherBuilder::default()
+                    .with_max_colors(2)
+                    .with_pixel_layout(layout)
+                    .with_seed(42)
+                    .build(),
+            )
+            .unwrap();
+
+            let result = block_on(quantizer.quantize_image(&data)).unwrap();
+            assert_eq!(result.len(), data.len(), "{layout}");
+            let quantized = decode(&result);
+            assert_eq!(quantized[0], quantized[1], "{layout}");
+            assert_eq!(quantized[2], original[2], "{layout}");
+            for (before, after) in original.iter().zip(&quantized) {
+                assert_eq!(before[3], after[3], "{layout}");
+            }
+
+            let indexed = block_on(quantizer.quantize_indexed(&data)).unwrap();
+            assert_eq!(indexed.palette.len(), 2, "{layout}");
+            assert_eq!(indexed.indices.get(0), indexed.indices.get(1), "{layout}");
+            assert_eq!(
+                indexed.palette[indexed.indices.get(2).unwrap()],
+                original[2],
+                "{layout}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pixel_layout_must_match_channels() {
+        let build = |builder: ColorCruncherBuilder| block_on(builder.build()).err();
+
+        assert!(matches!(
+            build(
+                ColorCruncherBuilder::default()
+                    .with_channels(3)
+                    .with_pixel_layout(PixelLayout::Bgra)
+            ),
+            Some(ColorCruncherError::InvalidConfig(_))
+        ));
+        assert!(build(ColorCruncherBuilder::default().with_channels(1)).is_none());
+
+        let rgb = block_on(ColorCruncherBuilder::default().with_channels(3).build()).unwrap();
+        assert!(matches!(
+            block_on(rgb.quantize_image(&[0, 0, 0, 255])),
+            Err(ColorCruncherError::InvalidBuffer(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply_palette() {
+        let data = vec![
+            10, 10, 10, 255, 240, 240, 240, 255, 100, 100, 100, 128, 200, 200, 200, 255,
+        ];
+        let palette = [[0, 0, 0, 255], [255, 255, 255, 255]];
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(2)
+                .with_channels(4)
+                .build(),
+        )
+        .unwrap();
+
+        let result = quantizer.apply_palette(&data, &palette).unwrap();
+        assert_eq!(
+            result,
+            vec![0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_apply_palette_with_dithering() {
+        let data: Vec<u8> = (0..16).flat_map(|_| [128, 128, 128, 255]).collect();
+        let palette = [[0, 0, 0, 255], [255, 255, 255, 255]];
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_channels(4)
+                .with_width(4)
+                .with_dithering(Dithering::Pattern)
+                .build(),
+        )
+        .unwrap();
+
+        let result = quantizer.apply_palette(&data, &palette).unwrap();
+        let whites = result.chunks_exact(4).filter(|px| px[0] == 255).count();
+        assert_eq!(whites, 8);
+    }
+
+    #[test]
+    fn test_fixed_palette_preset() {
+        use crate::palettes::PalettePreset;
+
+        let data = vec![
+            10, 50, 10, 255, 150, 180, 20, 255, 100, 120, 40, 255, 0, 0, 0, 255,
+        ];
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_channels(4)
+                .with_width(2)
+                .with_palette(PalettePreset::GameBoy.colors())
+                .build(),
+        )
+        .unwrap();
+
+        let gameboy = PalettePreset::GameBoy.colors();
+        let result = block_on(quantizer.quantize_image(&data)).unwrap();
+        for pixel in result.chunks_exact(4) {
+            assert!(gameboy.contains(&[pixel[0], pixel[1], pixel[2], pixel[3]]));
+        }
+
+        let indexed = block_on(quantizer.quantize_indexed(&data)).unwrap();
+        assert_eq!(indexed.palette, gameboy);
+        assert_eq!(indexed.indices, IndexBuffer::U8(vec![0, 3, 1, 0]));
+    }
+
+    #[test]
+    fn test_fixed_colors_are_kept() {
+        let data: Vec<u8> = (0..64u32)
+            .flat_map(|i| [(i * 4) as u8, (255 - i * 4) as u8, (i * 2) as u8, 255])
+            .collect();
+        let fixed = vec![[0, 0, 0, 255], [255, 0, 0, 255]];
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_fixed_colors(fixed.clone())
+                .with_seed(42)
+                .build(),
+        )
+        .unwrap();
+
+        let palette = block_on(quantizer.create_palette(&data)).unwrap();
+        assert_eq!(palette.colors.len(), 4);
+        assert_eq!(palette.colors[..2], fixed[..]);
+    }
+
+    #[test]
+    fn test_fixed_colors_lead_the_exact_palette() {
+        let data = vec![0, 0, 255, 255, 0, 0, 255, 255];
+        let fixed = vec![[255, 255, 255, 0]];
+
+        let quantizer = block_on(
+            ColorCruncherBuilder::default()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_fixed_colors(fixed)
+                .build(),
+        )
+        .unwrap();
+
+        let indexed = block_on(quantizer.quantize_indexed(&data)).unwrap();
+        assert_eq!(indexed.palette, vec![[255, 255, 255, 0], [0, 0, 255, 255]]);
+        assert_eq!(indexed.indices, IndexBuffer::U8(vec![1, 1]));
     }
 }
@@ -0,0 +1,412 @@
+use super::{palette_to_centroids, ColorCruncher, IndexBuffer, IndexedImage};
+use crate::error::ColorCruncherError;
+use crate::kmeans::Initializer;
+use crate::palette_order::reorder;
+use crate::types::Vec4;
+use crate::utils::num_distinct_colors_u32;
+
+// How the frames of an animation get their palettes
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FramePalette {
+    // One palette for every frame, clustered from samples of all of them
+    #[default]
+    Shared,
+    // A palette per frame, warm-started from the previous frame's so colors keep their
+    // place. With max_drift, no color moves further than that (in RGBA distance) from
+    // the color at the same index in the previous frame.
+    PerFrame {
+        max_drift: Option<f32>,
+    },
+}
+
+impl ColorCruncher {
+    // Quantizes a sequence of frames, each laid out like a `quantize_image` buffer, so
+    // that palettes don't flicker between frames
+    pub async fn quantize_frames(
+        &self,
+        frames: &[&[u8]],
+    ) -> Result<Vec<IndexedImage>, ColorCruncherError> {
+        for frame in frames {
+            self.check_buffer(frame)?;
+        }
+
+        match self.frame_palette {
+            FramePalette::Shared => self.quantize_frames_shared(frames).await,
+            FramePalette::PerFrame { max_drift } => {
+                self.quantize_frames_per_frame(frames, max_drift).await
+            }
+        }
+    }
+
+    async fn quantize_frames_shared(
+        &self,
+        frames: &[&[u8]],
+    ) -> Result<Vec<IndexedImage>, ColorCruncherError> {
+        // Indices of every frame, one after the other
+        let (mut colors, mut indices) = match &self.palette {
+            Some(palette) => (
+                palette.clone(),
+                self.remap_frames(frames, &palette_to_centroids(palette, self.layout)),
+            ),
+            None => match self.exact_palette_frames(frames) {
+                Some(exact) => exact,
+                None => {
+                    let (_, centroids) = self.run_kmeans(&self.sample_frames(frames)).await?;
+                    let colors = self.centroids_to_palette(&centroids);
+                    (colors, self.remap_frames(frames, &centroids))
+                }
+            },
+        };
+
+        // Ordered once over all frames, so every frame ends up with the same palette
+        let mut counts = vec![0; colors.len()];
+        for &index in &indices {
+            counts[index] += 1;
+        }
+        self.order_palette(&mut colors, &mut counts, &mut indices);
+
+        let channels = self.layout.channels();
+        let mut rest = indices.as_slice();
+        Ok(frames
+            .iter()
+            .map(|frame| {
+                let (frame_indices, tail) = rest.split_at(frame.len() / channels);
+                rest = tail;
+                self.indexed_frame(colors.clone(), frame_indices)
+            })
+            .collect())
+    }
+
+    // Each frame is remapped on its own so dither patterns line up between frames
+    fn remap_frames(&self, frames: &[&[u8]], centroids: &[Vec4]) -> Vec<usize> {
+        frames
+            .iter()
+            .flat_map(|frame| self.remap_indices(frame, centroids))
+            .collect()
+    }
+
+    async fn quantize_frames_per_frame(
+        &self,
+        frames: &[&[u8]],
+        max_drift: Option<f32>,
+    ) -> Result<Vec<IndexedImage>, ColorCruncherError> {
+        let mut previous: Option<Vec<Vec4>> = None;
+        // Warm-started palettes keep the order picked for the first clustered frame, so
+        // each slot holds the same color from frame to frame
+        let mut order: Option<Vec<usize>> = None;
+        let mut images = Vec::with_capacity(frames.len());
+
+        for frame in frames {
+            let (mut colors, mut indices, clustered) = if self.palette.is_some() {
+                let (colors, indices) = self.unordered_index_pixels(frame).await?;
+                (colors, indices, false)
+            } else if let Some((colors, indices)) = self.exact_palette(frame) {
+                // Lossless frames can't flicker, and don't move the warm start along
+                (colors, indices, false)
+            } else {
+                let centroids = match &previous {
+                    Some(previous) => self.warm_start(frame, previous, max_drift).await?,
+                    None => self.run_kmeans(&self.chunk_pixels_vec4u(frame)).await?.1,
+                };
+                let indices = self.remap_indices(frame, &centroids);
+                let colors = self.centroids_to_palette(&centroids);
+                previous = Some(centroids);
+                (colors, indices, true)
+            };
+
+            let mut counts = vec![0; colors.len()];
+            for &index in &indices {
+                counts[index] += 1;
+            }
+            if clustered {
+                let order = order.get_or_insert_with(|| self.palette_permutation(&colors, &counts));
+                reorder(order, &mut colors, &mut counts, &mut indices);
+            } else {
+                self.order_palette(&mut colors, &mut counts, &mut indices);
+            }
+            images.push(self.indexed_frame(colors, &indices));
+        }
+        Ok(images)
+    }
+
+    // Clusters a frame starting from the previous frame's palette. When the frame has too
+    // few colors for every slot, the slots past them keep the previous frame's colors.
+    async fn warm_start(
+        &self,
+        frame: &[u8],
+        previous: &[Vec4],
+        max_drift: Option<f32>,
+    ) -> Result<Vec<Vec4>, ColorCruncherError> {
+        let data = self.chunk_pixels_vec4u(frame);
+        let fixed = self.fixed_colors.len();
+        let requested = previous.len().saturating_sub(fixed);
+        let k = fixed + num_distinct_colors_u32(&data, requested).min(requested);
+
+        let kmeans = self
+            .kmeans
+            .clone()
+            .with_k(k)
+            .with_initializer(Initializer::FromPalette(previous.to_vec()));
+        let (_, mut centroids) = kmeans.run_async(&data).await?;
+        centroids.extend_from_slice(&previous[k..]);
+
+        if let Some(max_drift) = max_drift {
+            for (centroid, previous) in centroids.iter_mut().zip(previous) {
+                limit_drift(centroid, previous, max_drift);
+            }
+        }
+        Ok(centroids)
+    }
+
+    fn indexed_frame(&self, palette: Vec<[u8; 4]>, indices: &[usize]) -> IndexedImage {
+        let width = self.image_width(indices.len());
+        IndexedImage {
+            indices: IndexBuffer::from_indices(indices, palette.len()),
+            palette,
+            width,
+            height: indices.len() / width,
+        }
+    }
+}
+
+// Pulls a centroid back towards where it was so it moves at most max_drift
+fn limit_drift(centroid: &mut Vec4, previous: &Vec4, max_drift: f32) {
+    let distance = centroid
+        .iter()
+        .zip(previous)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+        .sqrt();
+    if distance > max_drift {
+        let scale = max_drift / distance;
+        for (value, previous) in centroid.iter_mut().zip(previous) {
+            *value = previous + (*value - previous) * scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette_order::PaletteOrder;
+    use crate::quantize::ColorCruncherBuilder;
+    use crate::sampling::SamplingStrategy;
+    use futures::executor::block_on;
+
+    const WIDTH: usize = 16;
+
+    // A gradient that shifts a little further every frame
+    fn frames(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|f| {
+                (0..WIDTH * 8)
+                    .flat_map(|i| {
+                        let x = i % WIDTH;
+                        [
+                            (x * 15 + f * 6) as u8,
+                            (255 - x * 15) as u8,
+                            (f * 20) as u8,
+                            255,
+                        ]
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn quantize(frame_palette: FramePalette, frames: &[Vec<u8>]) -> Vec<IndexedImage> {
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_width(WIDTH)
+                .with_seed(1)
+                .with_frame_palette(frame_palette)
+                .build(),
+        )
+        .unwrap();
+        let frames: Vec<&[u8]> = frames.iter().map(Vec::as_slice).collect();
+        block_on(cruncher.quantize_frames(&frames)).unwrap()
+    }
+
+    #[test]
+    fn test_shared_palette_is_the_same_for_every_frame() {
+        let images = quantize(FramePalette::Shared, &frames(4));
+        assert_eq!(images.len(), 4);
+        for image in &images {
+            assert_eq!(image.palette, images[0].palette);
+            assert_eq!((image.width, image.height), (WIDTH, 8));
+        }
+        assert!(images[0].palette.len() <= 4);
+    }
+
+    #[test]
+    fn test_shared_palette_is_exact_when_all_frames_fit() {
+        let first = [[1, 2, 3, 255], [4, 5, 6, 255]].concat();
+        let second = [[4, 5, 6, 255], [7, 8, 9, 255]].concat();
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_max_colors(4)
+                .with_channels(4)
+                .build(),
+        )
+        .unwrap();
+        let images = block_on(cruncher.quantize_frames(&[&first, &second])).unwrap();
+
+        assert_eq!(
+            images[0].palette,
+            vec![[1, 2, 3, 255], [4, 5, 6, 255], [7, 8, 9, 255]]
+        );
+        assert_eq!(images[1].indices, IndexBuffer::U8(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_per_frame_palettes_drift_at_most_max_drift() {
+        let max_drift = 4.0;
+        let images = quantize(
+            FramePalette::PerFrame {
+                max_drift: Some(max_drift),
+            },
+            &frames(4),
+        );
+
+        for pair in images.windows(2) {
+            assert_eq!(pair[0].palette.len(), pair[1].palette.len());
+            for (a, b) in pair[0].palette.iter().zip(&pair[1].palette) {
+                let distance = a
+                    .iter()
+                    .zip(b)
+                    .map(|(&a, &b)| (a as f32 - b as f32).powi(2))
+                    .sum::<f32>()
+                    .sqrt();
+                // Palette entries are truncated to bytes, which can add up to a unit per channel
+                assert!(distance <= max_drift + 2.0, "{:?} -> {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ordered_per_frame_palettes_keep_their_slots() {
+        // Red takes over more of the frame each time, so sorting each frame by population
+        // on its own would swap red and blue halfway through
+        let frames: Vec<Vec<u8>> = (0..4)
+            .map(|f| {
+                let split = 4 + f * 3;
+                (0..WIDTH * 8)
+                    .flat_map(|i| {
+                        let x = (i % WIDTH) as u8;
+                        if (i % WIDTH) < split {
+                            [200 + x, 30, 30, 255]
+                        } else {
+                            [30, 30, 200 - x, 255]
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let frames: Vec<&[u8]> = frames.iter().map(Vec::as_slice).collect();
+
+        let max_drift = 8.0;
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_max_colors(2)
+                .with_channels(4)
+                .with_width(WIDTH)
+                .with_seed(1)
+                .with_palette_order(PaletteOrder::Population)
+                .with_frame_palette(FramePalette::PerFrame {
+                    max_drift: Some(max_drift),
+                })
+                .build(),
+        )
+        .unwrap();
+        let images = block_on(cruncher.quantize_frames(&frames)).unwrap();
+
+        for pair in images.windows(2) {
+            for (a, b) in pair[0].palette.iter().zip(&pair[1].palette) {
+                let distance = a
+                    .iter()
+                    .zip(b)
+                    .map(|(&a, &b)| (a as f32 - b as f32).powi(2))
+                    .sum::<f32>()
+                    .sqrt();
+                assert!(distance <= max_drift + 2.0, "{:?} -> {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_per_frame_palettes_keep_slots_a_frame_has_no_colors_for() {
+        // Every pixel differs, but the second frame's sampled pixels are all black
+        let frame = |f: usize| -> Vec<u8> {
+            (0..WIDTH * 8)
+                .flat_map(|i| match (f, i % 32) {
+                    (1, 0) => [0, 0, 0, 255],
+                    _ => [(i * 2) as u8, (255 - i) as u8, (f * 40) as u8, 255],
+                })
+                .collect()
+        };
+        let frames: Vec<Vec<u8>> = (0..3).map(frame).collect();
+        let frames: Vec<&[u8]> = frames.iter().map(Vec::as_slice).collect();
+
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_sample_rate(32)
+                .with_seed(1)
+                .with_frame_palette(FramePalette::PerFrame { max_drift: None })
+                .build(),
+        )
+        .unwrap();
+        let images = block_on(cruncher.quantize_frames(&frames)).unwrap();
+
+        for image in &images {
+            assert_eq!(image.palette.len(), 4);
+        }
+        let carried = images[0]
+            .palette
+            .iter()
+            .filter(|color| images[1].palette.contains(color))
+            .count();
+        assert_eq!(carried, 3);
+    }
+
+    #[test]
+    fn test_shared_palette_samples_all_frames_at_once() {
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_max_colors(8)
+                .with_channels(4)
+                .with_width(WIDTH)
+                .with_seed(1)
+                .with_sampling(SamplingStrategy::Reservoir { size: 5 })
+                .build(),
+        )
+        .unwrap();
+        let frames = frames(3);
+        let frames: Vec<&[u8]> = frames.iter().map(Vec::as_slice).collect();
+
+        assert_eq!(cruncher.sample_frames(&frames).len(), 5);
+        let stride = block_on(
+            ColorCruncherBuilder::new()
+                .with_channels(4)
+                .with_sample_rate(100)
+                .build(),
+        )
+        .unwrap();
+        assert_eq!(stride.sample_frames(&frames).len(), 4);
+    }
+
+    #[test]
+    fn test_negative_drift_is_invalid() {
+        let result = block_on(
+            ColorCruncherBuilder::new()
+                .with_frame_palette(FramePalette::PerFrame {
+                    max_drift: Some(-1.0),
+                })
+                .build(),
+        );
+        assert!(matches!(result, Err(ColorCruncherError::InvalidConfig(_))));
+    }
+}
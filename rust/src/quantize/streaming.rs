@@ -0,0 +1,402 @@
+use super::{palette_to_centroids, ColorCruncher, IndexBuffer, Palette};
+use crate::dither::PatternDitherer;
+use crate::error::ColorCruncherError;
+use crate::kmeans::find_closest_centroid;
+use crate::sampling::{seeded_rng, Reservoir};
+use crate::types::{Vec4, Vec4u};
+use crate::utils::pack_rgba;
+use rand::rngs::StdRng;
+use std::collections::HashMap;
+
+// Builds a palette from an image fed in as rows or tiles, for images too big to hold in
+// memory. A reservoir keeps a uniform sample of at most max_samples pixels for k-means,
+// and a histogram counts exact colors for as long as there are few enough of them to
+// index the image losslessly. The pieces can come in any order, so the sampling strategy
+// doesn't apply; only sample_rate thins out what reaches the reservoir.
+pub struct PaletteStream<'a> {
+    cruncher: &'a ColorCruncher,
+    reservoir: Reservoir<Vec4u>,
+    rng: StdRng,
+    pixels: u64,
+    // Dropped once the image has more colors than a lossless palette can hold
+    histogram: Option<Histogram>,
+}
+
+// Remaps an image onto a palette one tile at a time
+pub struct TileRemapper<'a> {
+    cruncher: &'a ColorCruncher,
+    centroids: Vec<Vec4>,
+    pattern_ditherer: PatternDitherer,
+}
+
+struct Histogram {
+    colors: Vec<[u8; 4]>,
+    counts: Vec<usize>,
+    lookup: HashMap<u32, usize>,
+    limit: usize,
+}
+
+impl ColorCruncher {
+    // Starts a palette for an image that's passed in piece by piece, see `PaletteStream`
+    pub fn palette_stream(
+        &self,
+        max_samples: usize,
+    ) -> Result<PaletteStream<'_>, ColorCruncherError> {
+        if max_samples == 0 {
+            return Err(ColorCruncherError::InvalidConfig(
+                "max samples must be at least 1".to_string(),
+            ));
+        }
+        // A given palette is used as-is, so there's no need to look for an exact one
+        let histogram = match (&self.palette, self.max_colors.lossless_limit()) {
+            (None, Some(limit)) => Some(Histogram::new(&self.fixed_colors, limit)),
+            _ => None,
+        };
+
+        Ok(PaletteStream {
+            cruncher: self,
+            reservoir: Reservoir::new(max_samples),
+            rng: seeded_rng(self.kmeans.seed()),
+            pixels: 0,
+            histogram,
+        })
+    }
+
+    // Remaps an image piece by piece, usually onto the palette from a `PaletteStream`
+    pub fn tile_remapper(
+        &self,
+        palette: &[[u8; 4]],
+    ) -> Result<TileRemapper<'_>, ColorCruncherError> {
+        if palette.is_empty() {
+            return Err(ColorCruncherError::InvalidConfig(
+                "palette is empty".to_string(),
+            ));
+        }
+        let centroids = palette_to_centroids(palette, self.layout);
+        Ok(TileRemapper {
+            cruncher: self,
+            pattern_ditherer: PatternDitherer::new(&centroids),
+            centroids,
+        })
+    }
+}
+
+impl PaletteStream<'_> {
+    // Pixels laid out like a `quantize_image` buffer: a few rows, a tile, any run of
+    // whole pixels. The order the pieces come in doesn't matter.
+    pub fn add_pixels(&mut self, pixels: &[u8]) -> Result<(), ColorCruncherError> {
+        let layout = self.cruncher.layout;
+        let channels = layout.channels();
+        if !pixels.len().is_multiple_of(channels) {
+            return Err(ColorCruncherError::InvalidBuffer(format!(
+                "length {} is not a multiple of {} channels",
+                pixels.len(),
+                channels
+            )));
+        }
+
+        let sample_rate = self.cruncher.sample_rate as u64;
+        for pixel in pixels.chunks_exact(channels) {
+            let color = layout.to_rgba(pixel);
+            if let Some(histogram) = &mut self.histogram {
+                if !histogram.add(color) {
+                    self.histogram = None;
+                }
+            }
+            if self.pixels.is_multiple_of(sample_rate) {
+                self.reservoir.offer(color.map(u32::from), &mut self.rng);
+            }
+            self.pixels += 1;
+        }
+        Ok(())
+    }
+
+    // The palette in the configured order. Counts are exact when the image was indexed
+    // losslessly, and estimated from the sample otherwise.
+    pub async fn finish(mut self) -> Result<Palette, ColorCruncherError> {
+        if self.pixels == 0 {
+            return Err(ColorCruncherError::InvalidBuffer(
+                "no pixels were added".to_string(),
+            ));
+        }
+        let cruncher = self.cruncher;
+        let (mut colors, mut counts) = match (&cruncher.palette, self.histogram.take()) {
+            (Some(palette), _) => {
+                let counts = self.estimate_counts(&palette_to_centroids(palette, cruncher.layout));
+                (palette.clone(), counts)
+            }
+            (None, Some(histogram)) => (histogram.colors, histogram.counts),
+            (None, None) => {
+                let (_, centroids) = cruncher.run_kmeans(self.reservoir.items()).await?;
+                let colors = cruncher.centroids_to_palette(&centroids);
+                (colors, self.estimate_counts(&centroids))
+            }
+        };
+
+        cruncher.order_palette(&mut colors, &mut counts, &mut []);
+        Ok(Palette { colors, counts })
+    }
+
+    // Each sample stands for pixels / samples pixels of the image
+    fn estimate_counts(&self, centroids: &[Vec4]) -> Vec<usize> {
+        let mut counts = vec![0; centroids.len()];
+        let samples = self.reservoir.items();
+        for sample in samples {
+            counts[find_closest_centroid(&sample.map(|channel| channel as f32), centroids)] += 1;
+        }
+        let scale = self.pixels as f64 / samples.len().max(1) as f64;
+        counts
+            .into_iter()
+            .map(|count| (count as f64 * scale).round() as usize)
+            .collect()
+    }
+}
+
+impl TileRemapper<'_> {
+    // Palette indices for a tile `width` pixels wide whose top left corner is at x, y in
+    // the whole image. Rows are tiles as wide as the image. Pattern dithering follows the
+    // whole image's grid, so tiles come out the same as if the image were remapped at once.
+    pub fn index_tile(
+        &mut self,
+        pixels: &[u8],
+        x: usize,
+        y: usize,
+        width: usize,
+    ) -> Result<IndexBuffer, ColorCruncherError> {
+        let indices = self.tile_indices(pixels, x, y, width)?;
+        Ok(IndexBuffer::from_indices(&indices, self.centroids.len()))
+    }
+
+    // Like `index_tile`, but returns pixels in the cruncher's layout like `quantize_image`
+    pub fn quantize_tile(
+        &mut self,
+        pixels: &[u8],
+        x: usize,
+        y: usize,
+        width: usize,
+    ) -> Result<Vec<u8>, ColorCruncherError> {
+        let indices = self.tile_indices(pixels, x, y, width)?;
+        let mut new_tile = Vec::with_capacity(pixels.len());
+        for &index in &indices {
+            self.cruncher
+                .extend_remapped(&mut new_tile, &self.centroids[index]);
+        }
+        Ok(new_tile)
+    }
+
+    fn tile_indices(
+        &mut self,
+        pixels: &[u8],
+        x: usize,
+        y: usize,
+        width: usize,
+    ) -> Result<Vec<usize>, ColorCruncherError> {
+        let channels = self.cruncher.layout.channels();
+        if width == 0 || !pixels.len().is_multiple_of(channels * width) {
+            return Err(ColorCruncherError::InvalidBuffer(format!(
+                "length {} doesn't hold rows of {} pixels with {} channels",
+                pixels.len(),
+                width,
+                channels
+            )));
+        }
+
+        Ok(pixels
+            .chunks_exact(channels)
+            .enumerate()
+            .map(|(i, pixel)| {
+                self.cruncher.index_pixel(
+                    pixel,
+                    &self.centroids,
+                    &mut self.pattern_ditherer,
+                    x + i % width,
+                    y + i / width,
+                )
+            })
+            .collect())
+    }
+}
+
+impl Histogram {
+    // Fixed colors take the first entries, like `exact_palette`
+    fn new(fixed_colors: &[[u8; 4]], limit: usize) -> Self {
+        Self {
+            colors: fixed_colors.to_vec(),
+            counts: vec![0; fixed_colors.len()],
+            lookup: fixed_colors
+                .iter()
+                .enumerate()
+                .map(|(i, &color)| (pack_rgba(color), i))
+                .collect(),
+            limit,
+        }
+    }
+
+    // Returns false once there are more colors than the limit
+    fn add(&mut self, color: [u8; 4]) -> bool {
+        let key = pack_rgba(color);
+        let index = match self.lookup.get(&key) {
+            Some(&index) => index,
+            None => {
+                if self.colors.len() >= self.limit {
+                    return false;
+                }
+                self.colors.push(color);
+                self.counts.push(0);
+                self.lookup.insert(key, self.colors.len() - 1);
+                self.colors.len() - 1
+            }
+        };
+        self.counts[index] += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dither::Dithering;
+    use crate::error::ColorCruncherError;
+    use crate::palette_order::PaletteOrder;
+    use crate::quantize::ColorCruncherBuilder;
+    use futures::executor::block_on;
+
+    const WIDTH: usize = 10;
+    const HEIGHT: usize = 7;
+
+    fn gradient() -> Vec<u8> {
+        (0..WIDTH * HEIGHT)
+            .flat_map(|i| {
+                let (x, y) = (i % WIDTH, i / WIDTH);
+                [(x * 25) as u8, (y * 36) as u8, 128, 255]
+            })
+            .collect()
+    }
+
+    // The pixels of the tile at x, y, cut short at the image's edges
+    fn tile(image: &[u8], x: usize, y: usize, size: usize) -> (Vec<u8>, usize) {
+        let width = size.min(WIDTH - x);
+        let pixels = (y..(y + size).min(HEIGHT))
+            .flat_map(|row| &image[(row * WIDTH + x) * 4..(row * WIDTH + x + width) * 4])
+            .copied()
+            .collect();
+        (pixels, width)
+    }
+
+    #[test]
+    fn test_dithered_tiles_match_the_whole_image() {
+        let palette = vec![[0, 0, 128, 255], [255, 255, 128, 255], [255, 0, 128, 255]];
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_channels(4)
+                .with_width(WIDTH)
+                .with_palette(palette.clone())
+                .with_dithering(Dithering::Pattern)
+                .build(),
+        )
+        .unwrap();
+        let image = gradient();
+        let whole = block_on(cruncher.quantize_indexed(&image)).unwrap();
+
+        // 3x3 tiles don't line up with the 4x4 dither matrix
+        let mut remapper = cruncher.tile_remapper(&palette).unwrap();
+        let mut tiled = vec![0; WIDTH * HEIGHT];
+        for y in (0..HEIGHT).step_by(3) {
+            for x in (0..WIDTH).step_by(3) {
+                let (pixels, width) = tile(&image, x, y, 3);
+                let indices = remapper.index_tile(&pixels, x, y, width).unwrap();
+                for i in 0..indices.len() {
+                    tiled[(y + i / width) * WIDTH + x + i % width] = indices.get(i).unwrap();
+                }
+            }
+        }
+
+        let whole: Vec<usize> = (0..whole.indices.len())
+            .map(|i| whole.indices.get(i).unwrap())
+            .collect();
+        assert_eq!(tiled, whole);
+    }
+
+    #[test]
+    fn test_stream_matches_create_palette_when_every_pixel_is_sampled() {
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_max_colors(4)
+                .with_channels(4)
+                .with_seed(3)
+                .build(),
+        )
+        .unwrap();
+        let image = gradient();
+
+        let mut stream = cruncher.palette_stream(WIDTH * HEIGHT).unwrap();
+        for row in image.chunks(WIDTH * 4) {
+            stream.add_pixels(row).unwrap();
+        }
+        let streamed = block_on(stream.finish()).unwrap();
+        let whole = block_on(cruncher.create_palette(&image)).unwrap();
+
+        assert_eq!(streamed.colors, whole.colors);
+        assert_eq!(streamed.counts.iter().sum::<usize>(), WIDTH * HEIGHT);
+    }
+
+    #[test]
+    fn test_few_colors_are_counted_exactly() {
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_max_colors(4)
+                .with_channels(3)
+                .with_palette_order(PaletteOrder::Population)
+                .build(),
+        )
+        .unwrap();
+
+        let mut stream = cruncher.palette_stream(1).unwrap();
+        stream.add_pixels(&[1, 1, 1, 2, 2, 2]).unwrap();
+        stream.add_pixels(&[2, 2, 2, 2, 2, 2, 3, 3, 3]).unwrap();
+        let palette = block_on(stream.finish()).unwrap();
+
+        assert_eq!(
+            palette.colors,
+            vec![[2, 2, 2, 255], [1, 1, 1, 255], [3, 3, 3, 255]]
+        );
+        assert_eq!(palette.counts, vec![3, 1, 1]);
+
+        let empty = cruncher.palette_stream(1).unwrap();
+        assert!(matches!(
+            block_on(empty.finish()),
+            Err(ColorCruncherError::InvalidBuffer(_))
+        ));
+    }
+
+    #[test]
+    fn test_reservoir_is_bounded() {
+        let cruncher = block_on(
+            ColorCruncherBuilder::new()
+                .with_max_colors(8)
+                .with_channels(4)
+                .with_seed(1)
+                .build(),
+        )
+        .unwrap();
+        let image: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| [i as u8, (i >> 8) as u8, (i * 7) as u8, 255])
+            .collect();
+
+        let mut stream = cruncher.palette_stream(100).unwrap();
+        for chunk in image.chunks(4 * 128) {
+            stream.add_pixels(chunk).unwrap();
+        }
+        assert_eq!(stream.reservoir.items().len(), 100);
+        assert!(stream.histogram.is_none());
+
+        let palette = block_on(stream.finish()).unwrap();
+        assert_eq!(palette.colors.len(), 8);
+        assert!(cruncher.palette_stream(0).is_err());
+
+        // A reservoir smaller than the palette still makes one
+        let mut stream = cruncher.palette_stream(3).unwrap();
+        stream.add_pixels(&image).unwrap();
+        let palette = block_on(stream.finish()).unwrap();
+        assert_eq!(palette.colors.len(), 3);
+    }
+}
@@ -0,0 +1,250 @@
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+
+// Bits of each RGB channel and of alpha that decide a pixel's Histogram bin
+const BIN_BITS: u32 = 4;
+const BIN_ALPHA_BITS: u32 = 2;
+const NUM_BINS: usize = 1 << (3 * BIN_BITS + BIN_ALPHA_BITS);
+
+// How the pixels k-means clusters are picked out of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplingStrategy {
+    // Every sample_rate-th pixel in buffer order. Cheap, but lines up with stripes and
+    // columns whenever the stride and the width share a factor.
+    #[default]
+    Stride,
+    // A uniform random 1 / sample_rate of the pixels
+    Random,
+    // One random pixel from each cell of a grid of sample_rate pixel cells, so samples
+    // spread evenly over the image without a regular pattern
+    Jittered,
+    // A uniform random sample of a fixed number of pixels, whatever the sample rate
+    Reservoir {
+        size: usize,
+    },
+    // Pixels binned by coarse color and sampled in proportion to each bin, but with at
+    // least one from every bin, so small features in rare colors still get a say. Can
+    // take a few more than 1 / sample_rate of the pixels for that.
+    Histogram,
+}
+
+impl fmt::Display for SamplingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl SamplingStrategy {
+    // Indices of the pixels to sample, in increasing order. A sample that would cover the
+    // whole image is every pixel. `color` looks up a pixel's RGBA color, which only
+    // Histogram needs.
+    pub fn sample_indices(
+        &self,
+        num_pixels: usize,
+        width: usize,
+        sample_rate: usize,
+        seed: Option<u64>,
+        color: impl Fn(usize) -> [u8; 4],
+    ) -> Vec<usize> {
+        let sample_rate = sample_rate.max(1);
+        let target = match self {
+            SamplingStrategy::Reservoir { size } => *size,
+            _ => num_pixels.div_ceil(sample_rate),
+        };
+        if target >= num_pixels {
+            return (0..num_pixels).collect();
+        }
+
+        let mut rng = seeded_rng(seed);
+        let mut indices = match self {
+            SamplingStrategy::Stride => return (0..num_pixels).step_by(sample_rate).collect(),
+            SamplingStrategy::Random => index::sample(&mut rng, num_pixels, target).into_vec(),
+            SamplingStrategy::Jittered => jittered(num_pixels, width, sample_rate, &mut rng),
+            SamplingStrategy::Reservoir { .. } => {
+                let mut reservoir = Reservoir::new(target);
+                for i in 0..num_pixels {
+                    reservoir.offer(i, &mut rng);
+                }
+                reservoir.into_items()
+            }
+            SamplingStrategy::Histogram => histogram(num_pixels, target, color, &mut rng),
+        };
+        indices.sort_unstable();
+        indices
+    }
+}
+
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+// Algorithm R: keeps a uniform random sample of at most `capacity` of the items offered,
+// replacing a random one with probability capacity / n for the n-th item
+#[derive(Debug, Clone)]
+pub struct Reservoir<T> {
+    items: Vec<T>,
+    capacity: usize,
+    seen: u64,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: Vec::new(),
+            capacity,
+            seen: 0,
+        }
+    }
+
+    pub fn offer(&mut self, item: T, rng: &mut impl Rng) {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let slot = rng.gen_range(0..=self.seen);
+            if slot < self.capacity as u64 {
+                self.items[slot as usize] = item;
+            }
+        }
+        self.seen += 1;
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+}
+
+// Cells are about sqrt(sample_rate) on a side, or runs of sample_rate pixels when the
+// image is a single row
+fn jittered(num_pixels: usize, width: usize, sample_rate: usize, rng: &mut StdRng) -> Vec<usize> {
+    let width = width.clamp(1, num_pixels);
+    let height = num_pixels.div_ceil(width);
+    let cell_height = if height == 1 {
+        1
+    } else {
+        (sample_rate as f64).sqrt() as usize
+    };
+    let cell_width = sample_rate / cell_height;
+
+    let mut indices = Vec::with_capacity(num_pixels.div_ceil(sample_rate));
+    for top in (0..height).step_by(cell_height) {
+        for left in (0..width).step_by(cell_width) {
+            let x = rng.gen_range(left..(left + cell_width).min(width));
+            let y = rng.gen_range(top..(top + cell_height).min(height));
+            let i = y * width + x;
+            if i < num_pixels {
+                indices.push(i);
+            }
+        }
+    }
+    indices
+}
+
+fn histogram(
+    num_pixels: usize,
+    target: usize,
+    color: impl Fn(usize) -> [u8; 4],
+    rng: &mut StdRng,
+) -> Vec<usize> {
+    let mut counts = vec![0; NUM_BINS];
+    for i in 0..num_pixels {
+        counts[bin(color(i))] += 1;
+    }
+
+    let scale = target as f64 / num_pixels as f64;
+    let mut reservoirs: Vec<Reservoir<usize>> = counts
+        .iter()
+        .map(|&count| match count {
+            0 => Reservoir::new(0),
+            count => Reservoir::new(((count as f64 * scale).round() as usize).max(1)),
+        })
+        .collect();
+    for i in 0..num_pixels {
+        reservoirs[bin(color(i))].offer(i, rng);
+    }
+    reservoirs
+        .into_iter()
+        .flat_map(Reservoir::into_items)
+        .collect()
+}
+
+fn bin(color: [u8; 4]) -> usize {
+    let [r, g, b, a] = color.map(usize::from);
+    let shift = 8 - BIN_BITS;
+    (((r >> shift) << (2 * BIN_BITS) | (g >> shift) << BIN_BITS | (b >> shift)) << BIN_ALPHA_BITS)
+        | (a >> (8 - BIN_ALPHA_BITS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(i: usize) -> [u8; 4] {
+        [i as u8, i as u8, i as u8, 255]
+    }
+
+    #[test]
+    fn test_every_strategy_samples_about_one_in_sample_rate() {
+        for strategy in [
+            SamplingStrategy::Stride,
+            SamplingStrategy::Random,
+            SamplingStrategy::Jittered,
+            SamplingStrategy::Reservoir { size: 2500 },
+        ] {
+            let indices = strategy.sample_indices(10_000, 100, 4, Some(1), gray);
+            assert_eq!(indices.len(), 2500, "{}", strategy);
+            assert!(indices.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(indices.iter().all(|&i| i < 10_000));
+        }
+    }
+
+    #[test]
+    fn test_jittered_does_not_alias_with_the_width() {
+        // A stride of 4 on a 100 pixel wide image only ever sees every 4th column
+        let columns = |strategy: SamplingStrategy| {
+            let indices = strategy.sample_indices(10_000, 100, 4, Some(1), gray);
+            let mut columns: Vec<usize> = indices.iter().map(|i| i % 100).collect();
+            columns.sort_unstable();
+            columns.dedup();
+            columns.len()
+        };
+        assert_eq!(columns(SamplingStrategy::Stride), 25);
+        assert_eq!(columns(SamplingStrategy::Jittered), 100);
+    }
+
+    #[test]
+    fn test_histogram_keeps_rare_colors() {
+        // Three red pixels in a sea of black
+        let color = |i: usize| {
+            if i % 3001 == 3000 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 0, 255]
+            }
+        };
+        let indices = SamplingStrategy::Histogram.sample_indices(10_000, 100, 100, Some(1), color);
+        assert!(indices.iter().any(|&i| color(i) == [255, 0, 0, 255]));
+        assert!(indices.len() <= 101);
+    }
+
+    #[test]
+    fn test_whole_sample_is_every_pixel() {
+        for strategy in [
+            SamplingStrategy::Random,
+            SamplingStrategy::Jittered,
+            SamplingStrategy::Reservoir { size: 50 },
+            SamplingStrategy::Histogram,
+        ] {
+            let indices = strategy.sample_indices(20, 5, 1, None, gray);
+            assert_eq!(indices, (0..20).collect::<Vec<_>>());
+        }
+    }
+}
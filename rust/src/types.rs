@@ -6,17 +6,28 @@ pub trait GPUVector {}
 pub trait VectorExt:
     Clone + Copy + std::ops::Index<usize, Output = f32> + std::ops::IndexMut<usize> + std::fmt::Debug
 {
+    // Number of components
+    const LEN: usize;
+
     fn add(&self, other: &Self) -> Self;
     fn sub(&self, other: &Self) -> Self;
     fn div_scalar(&self, scalar: f32) -> Self;
     fn zero() -> Self;
+    // Takes as many leading components as the vector has
+    fn from_vec4(values: &Vec4) -> Self;
 }
 
 impl VectorExt for Vec3 {
+    const LEN: usize = 3;
+
     fn zero() -> Self {
         [0.0; 3]
     }
 
+    fn from_vec4(values: &Vec4) -> Self {
+        [values[0], values[1], values[2]]
+    }
+
     fn add(&self, other: &Vec3) -> Self {
         let mut sum = [0.0; 3];
         for i in 0..3 {
@@ -39,6 +50,8 @@ impl VectorExt for Vec3 {
 }
 
 impl VectorExt for Vec4 {
+    const LEN: usize = 4;
+
     fn add(&self, other: &Vec4) -> Self {
         let mut sum = [0.0; 4];
         for i in 0..4 {
@@ -67,4 +80,8 @@ impl VectorExt for Vec4 {
     fn zero() -> Self {
         [0.0; 4]
     }
+
+    fn from_vec4(values: &Vec4) -> Self {
+        *values
+    }
 }
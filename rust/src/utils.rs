@@ -1,21 +1,63 @@
 use crate::types::{Vec4u, VectorExt};
 
 use std::collections::HashSet;
+use std::hash::Hash;
 
-pub fn num_distinct_colors<T: VectorExt>(data: &[T]) -> usize {
-    let mut color_hashset = HashSet::new();
-    for pixel in data {
-        // hacky but its fine, it only occurs once at the beginning
-        let hash_key = pixel[0] as usize * 2 + pixel[1] as usize * 3 + pixel[2] as usize * 5;
-        color_hashset.insert(hash_key);
+// Packs an RGBA color into a single u32 so it can be hashed and compared exactly
+pub fn pack_rgba(color: [u8; 4]) -> u32 {
+    u32::from_be_bytes(color)
+}
+
+// Counts distinct keys, giving up once there are more than `limit` of them.
+// The result is exact up to `limit` and `limit + 1` means "more than limit".
+pub fn count_distinct<K: Hash + Eq>(keys: impl Iterator<Item = K>, limit: usize) -> usize {
+    let mut seen = HashSet::new();
+    for key in keys {
+        if seen.insert(key) && seen.len() > limit {
+            break;
+        }
     }
-    color_hashset.len()
+    seen.len()
+}
+
+// Float vectors are compared by their bit patterns, component by component
+pub fn num_distinct_colors<T: VectorExt>(data: &[T], limit: usize) -> usize {
+    let keys = data.iter().map(|v| {
+        let mut key = [0u32; 4];
+        for (i, component) in key.iter_mut().enumerate().take(T::LEN) {
+            *component = v[i].to_bits();
+        }
+        key
+    });
+    count_distinct(keys, limit)
+}
+
+// For pixel data, where every channel is a byte
+pub fn num_distinct_colors_u32(data: &[Vec4u], limit: usize) -> usize {
+    let keys = data
+        .iter()
+        .map(|v| pack_rgba([v[0] as u8, v[1] as u8, v[2] as u8, v[3] as u8]));
+    count_distinct(keys, limit)
 }
 
-pub fn num_distinct_colors_u32(data: &[Vec4u]) -> usize {
-    let mut color_hashset = HashSet::new();
-    for pixel in data {
-        color_hashset.insert(pixel[0] * 2 + pixel[1] * 3 + pixel[2] * 5);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors_that_used_to_collide() {
+        // Used to hash to the same key
+        let data = [[3, 0, 0, 255], [0, 2, 0, 255], [0, 0, 0, 255], [0, 0, 0, 0]];
+        assert_eq!(num_distinct_colors_u32(&data, usize::MAX), 4);
+
+        let data: Vec<[f32; 3]> = vec![[3.0, 0.0, 0.0], [0.0, 2.0, 0.0]];
+        assert_eq!(num_distinct_colors(&data, usize::MAX), 2);
+    }
+
+    #[test]
+    fn test_counting_stops_past_the_limit() {
+        let data: Vec<Vec4u> = (0..100).map(|i| [i, 0, 0, 255]).collect();
+        assert_eq!(num_distinct_colors_u32(&data, 10), 11);
+        assert_eq!(num_distinct_colors_u32(&data[..5], 10), 5);
     }
-    color_hashset.len()
 }
@@ -3,9 +3,11 @@
 #![cfg(feature = "gpu")]
 
 const RGBA_CHANNELS: usize = 4;
-use js_sys::Uint8Array;
+use js_sys::{Uint16Array, Uint32Array, Uint8Array};
 
-use crate::quantize::{ColorCruncher, ColorCruncherBuilder};
+use crate::error::ColorCruncherError;
+use crate::gif::{Disposal as GifDisposal, FrameOptions, GifEncoder, Repeat};
+use crate::quantize::{ColorCruncher, ColorCruncherBuilder, IndexBuffer, IndexedImage, Palette};
 use console_error_panic_hook;
 use console_log;
 use log::Level;
@@ -20,20 +22,75 @@ fn start() {
     console_log::init_with_level(Level::Warn).expect("Failed to initialize console log");
 }
 
+// Errors are thrown as a JS Error whose name is the kind of error, e.g. "InvalidConfig"
+impl From<ColorCruncherError> for JsValue {
+    fn from(error: ColorCruncherError) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name(error.kind());
+        js_error.into()
+    }
+}
+
+fn invalid_option(kind: &str, value: &str) -> JsValue {
+    ColorCruncherError::InvalidConfig(format!("unknown {}: {}", kind, value)).into()
+}
+
+// Flat RGBA bytes, four per color
+fn rgba_colors(bytes: &[u8]) -> Result<Vec<[u8; 4]>, JsValue> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(ColorCruncherError::InvalidBuffer(format!(
+            "length {} is not a multiple of 4 RGBA bytes",
+            bytes.len()
+        ))
+        .into());
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|c| [c[0], c[1], c[2], c[3]])
+        .collect())
+}
+
 #[wasm_bindgen(js_name = ColorCruncher)]
 pub struct WasmColorCruncher(ColorCruncher);
 
 #[wasm_bindgen(js_name = ColorCruncherBuilder)]
 pub struct WasmColorCruncherBuilder(ColorCruncherBuilder);
 
+#[wasm_bindgen(js_name = IndexedImage)]
+pub struct WasmIndexedImage(IndexedImage);
+
+#[wasm_bindgen(js_name = Palette)]
+pub struct WasmPalette(Palette);
+
+#[wasm_bindgen(js_name = GifEncoder)]
+pub struct WasmGifEncoder(GifEncoder);
+
 #[wasm_bindgen(typescript_custom_section)]
 const TS_APPEND_CONTENT: &'static str = r#"
 export type Algorithm = "lloyd" | "hamerly" | "lloyd-gpu"
 export type Initializer = "kmeans++" | "random";
+export type Dithering = "none" | "pattern";
+export type KCriterion = "elbow" | "silhouette" | "bic";
+export type QualityMetric = "psnr" | "delta-e";
+export type PaletteOrder = "none" | "luminance" | "hsv" | "nearest-neighbor" | "population";
+export type PalettePreset = "web-safe" | "ega" | "ega-64" | "cga-mode4-palette0-low" | "cga-mode4-palette0-high"
+    | "cga-mode4-palette1-low" | "cga-mode4-palette1-high" | "cga-mode5-low" | "cga-mode5-high" | "nes"
+    | "gameboy" | "pico-8" | "c64" | "zx-spectrum" | "msx" | `grayscale-${number}`;
+export type Disposal = "unspecified" | "keep" | "background" | "previous";
+export type FramePaletteMode = "shared" | "per-frame";
+export type SamplingStrategy = "stride" | "random" | "jittered" | "reservoir" | "histogram";
 "#;
 
 type Algorithm = String;
 type Initializer = String;
+type Dithering = String;
+type KCriterion = String;
+type QualityMetric = String;
+type PaletteOrder = String;
+type PalettePreset = String;
+type Disposal = String;
+type FramePaletteMode = String;
+type SamplingStrategy = String;
 
 #[wasm_bindgen(js_class = ColorCruncherBuilder)]
 impl WasmColorCruncherBuilder {
@@ -52,6 +109,35 @@ impl WasmColorCruncherBuilder {
         Self(self.0.with_sample_rate(sample_rate as usize))
     }
 
+    // How sampleRate picks its pixels. "reservoir" takes a fixed number of pixels, given
+    // as size, whatever the sample rate.
+    #[wasm_bindgen(js_name = withSampling)]
+    pub fn with_sampling(
+        self,
+        strategy: SamplingStrategy,
+        size: Option<u32>,
+    ) -> Result<WasmColorCruncherBuilder, JsValue> {
+        let sampling = match strategy.as_str() {
+            "stride" => crate::sampling::SamplingStrategy::Stride,
+            "random" => crate::sampling::SamplingStrategy::Random,
+            "jittered" => crate::sampling::SamplingStrategy::Jittered,
+            "histogram" => crate::sampling::SamplingStrategy::Histogram,
+            "reservoir" => match size {
+                Some(size) => crate::sampling::SamplingStrategy::Reservoir {
+                    size: size as usize,
+                },
+                None => {
+                    return Err(ColorCruncherError::InvalidConfig(
+                        "reservoir sampling needs a size".to_string(),
+                    )
+                    .into())
+                }
+            },
+            _ => return Err(invalid_option("sampling", &strategy)),
+        };
+        Ok(Self(self.0.with_sampling(sampling)))
+    }
+
     #[wasm_bindgen(js_name = withTolerance)]
     pub fn with_tolerance(self, tolerance: f32) -> Self {
         Self(self.0.with_tolerance(tolerance))
@@ -63,24 +149,27 @@ impl WasmColorCruncherBuilder {
     }
 
     #[wasm_bindgen(js_name = withInitializer)]
-    pub fn with_initializer(self, initializer: Initializer) -> Self {
+    pub fn with_initializer(
+        self,
+        initializer: Initializer,
+    ) -> Result<WasmColorCruncherBuilder, JsValue> {
         let init = match initializer.as_str() {
             "kmeans++" => crate::kmeans::Initializer::KMeansPlusPlus,
             "random" => crate::kmeans::Initializer::Random,
-            _ => panic!("Invalid initializer: {}", initializer),
+            _ => return Err(invalid_option("initializer", &initializer)),
         };
-        Self(self.0.with_initializer(init))
+        Ok(Self(self.0.with_initializer(init)))
     }
 
     #[wasm_bindgen(js_name = withAlgorithm)]
-    pub fn with_algorithm(self, algorithm: Algorithm) -> Self {
+    pub fn with_algorithm(self, algorithm: Algorithm) -> Result<WasmColorCruncherBuilder, JsValue> {
         let algo = match algorithm.as_str() {
             "lloyd" => crate::kmeans::KMeansAlgorithm::Lloyd,
             "hamerly" => crate::kmeans::KMeansAlgorithm::Hamerly,
             "lloyd-gpu" => crate::kmeans::KMeansAlgorithm::LloydGpu,
-            _ => panic!("Invalid algorithm: {}", algorithm),
+            _ => return Err(invalid_option("algorithm", &algorithm)),
         };
-        Self(self.0.with_algorithm(algo))
+        Ok(Self(self.0.with_algorithm(algo)))
     }
 
     #[wasm_bindgen(js_name = withSeed)]
@@ -88,19 +177,269 @@ impl WasmColorCruncherBuilder {
         Self(self.0.with_seed(seed))
     }
 
+    // Let the palette size be picked between min and max colors
+    #[wasm_bindgen(js_name = withAutoMaxColors)]
+    pub fn with_auto_max_colors(
+        self,
+        min: u32,
+        max: u32,
+        criterion: KCriterion,
+    ) -> Result<WasmColorCruncherBuilder, JsValue> {
+        let criterion = match criterion.as_str() {
+            "elbow" => crate::kmeans::KCriterion::Elbow,
+            "silhouette" => crate::kmeans::KCriterion::Silhouette,
+            "bic" => crate::kmeans::KCriterion::Bic,
+            _ => return Err(invalid_option("criterion", &criterion)),
+        };
+        Ok(Self(self.0.with_auto_max_colors(
+            min as usize,
+            max as usize,
+            criterion,
+        )))
+    }
+
+    // Use the smallest palette, up to max colors, with PSNR of at least `value` dB or a
+    // mean ΔE of at most `value`
+    #[wasm_bindgen(js_name = withQualityTarget)]
+    pub fn with_quality_target(
+        self,
+        max: u32,
+        metric: QualityMetric,
+        value: f64,
+    ) -> Result<WasmColorCruncherBuilder, JsValue> {
+        let target = match metric.as_str() {
+            "psnr" => crate::metrics::QualityTarget::MinPsnr(value),
+            "delta-e" => crate::metrics::QualityTarget::MaxMeanDeltaE(value),
+            _ => return Err(invalid_option("quality metric", &metric)),
+        };
+        Ok(Self(self.0.with_quality_target(max as usize, target)))
+    }
+
+    #[wasm_bindgen(js_name = withDithering)]
+    pub fn with_dithering(self, dithering: Dithering) -> Result<WasmColorCruncherBuilder, JsValue> {
+        let dither = match dithering.as_str() {
+            "none" => crate::dither::Dithering::None,
+            "pattern" => crate::dither::Dithering::Pattern,
+            _ => return Err(invalid_option("dithering", &dithering)),
+        };
+        Ok(Self(self.0.with_dithering(dither)))
+    }
+
+    #[wasm_bindgen(js_name = withPaletteOrder)]
+    pub fn with_palette_order(
+        self,
+        order: PaletteOrder,
+    ) -> Result<WasmColorCruncherBuilder, JsValue> {
+        let order = match order.as_str() {
+            "none" => crate::palette_order::PaletteOrder::None,
+            "luminance" => crate::palette_order::PaletteOrder::Luminance,
+            "hsv" => crate::palette_order::PaletteOrder::Hsv,
+            "nearest-neighbor" => crate::palette_order::PaletteOrder::NearestNeighbor,
+            "population" => crate::palette_order::PaletteOrder::Population,
+            _ => return Err(invalid_option("palette order", &order)),
+        };
+        Ok(Self(self.0.with_palette_order(order)))
+    }
+
+    // "shared" clusters one palette from all frames. "per-frame" warm-starts each frame
+    // from the previous one, and maxDrift caps how far any color can move between frames.
+    #[wasm_bindgen(js_name = withFramePalette)]
+    pub fn with_frame_palette(
+        self,
+        mode: FramePaletteMode,
+        max_drift: Option<f32>,
+    ) -> Result<WasmColorCruncherBuilder, JsValue> {
+        let frame_palette = match mode.as_str() {
+            "shared" => crate::quantize::FramePalette::Shared,
+            "per-frame" => crate::quantize::FramePalette::PerFrame { max_drift },
+            _ => return Err(invalid_option("frame palette", &mode)),
+        };
+        Ok(Self(self.0.with_frame_palette(frame_palette)))
+    }
+
+    #[wasm_bindgen(js_name = withPalette)]
+    pub fn with_palette(self, preset: PalettePreset) -> Result<WasmColorCruncherBuilder, JsValue> {
+        let palette = match crate::palettes::PalettePreset::from_name(&preset) {
+            Some(palette) => palette,
+            None => return Err(invalid_option("palette", &preset)),
+        };
+        Ok(Self(self.0.with_palette(palette.colors())))
+    }
+
+    // Start k-means from an existing palette, given as flat RGBA bytes
+    #[wasm_bindgen(js_name = withInitialPalette)]
+    pub fn with_initial_palette(self, palette: &[u8]) -> Result<WasmColorCruncherBuilder, JsValue> {
+        let palette = rgba_colors(palette)?
+            .iter()
+            .map(|c| c.map(f32::from))
+            .collect();
+        Ok(Self(self.0.with_initializer(
+            crate::kmeans::Initializer::FromPalette(palette),
+        )))
+    }
+
+    // Flat RGBA bytes, four per color
+    #[wasm_bindgen(js_name = withFixedColors)]
+    pub fn with_fixed_colors(self, colors: &[u8]) -> Result<WasmColorCruncherBuilder, JsValue> {
+        Ok(Self(self.0.with_fixed_colors(rgba_colors(colors)?)))
+    }
+
+    #[wasm_bindgen(js_name = withWidth)]
+    pub fn with_width(self, width: u32) -> Self {
+        Self(self.0.with_width(width as usize))
+    }
+
     #[wasm_bindgen(js_name = build)]
-    pub async fn build(&self) -> WasmColorCruncher {
-        WasmColorCruncher(self.0.build().await)
+    pub async fn build(&self) -> Result<WasmColorCruncher, JsValue> {
+        Ok(WasmColorCruncher(self.0.build().await?))
     }
 }
 
 #[wasm_bindgen(js_class = ColorCruncher)]
 impl WasmColorCruncher {
     #[wasm_bindgen(js_name = quantizeImage)]
-    pub async fn quantize_image(&self, data: &[u8]) -> Result<Uint8Array, String> {
-        let result = self.0.quantize_image(data).await;
+    pub async fn quantize_image(&self, data: &[u8]) -> Result<Uint8Array, JsValue> {
+        let result = self.0.quantize_image(data).await?;
         Ok(Uint8Array::from(result.as_slice()))
     }
+
+    // The palette is flat RGBA bytes, four per color
+    #[wasm_bindgen(js_name = applyPalette)]
+    pub fn apply_palette(&self, data: &[u8], palette: &[u8]) -> Result<Uint8Array, JsValue> {
+        if palette.is_empty() || !palette.len().is_multiple_of(4) {
+            return Err(ColorCruncherError::InvalidConfig(
+                "palette must be a non-empty list of RGBA bytes".to_string(),
+            )
+            .into());
+        }
+        let palette: Vec<[u8; 4]> = palette
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], c[3]])
+            .collect();
+        let result = self.0.apply_palette(data, &palette)?;
+        Ok(Uint8Array::from(result.as_slice()))
+    }
+
+    #[wasm_bindgen(js_name = quantizeIndexed)]
+    pub async fn quantize_indexed(&self, data: &[u8]) -> Result<WasmIndexedImage, JsValue> {
+        Ok(WasmIndexedImage(self.0.quantize_indexed(data).await?))
+    }
+
+    #[wasm_bindgen(js_name = quantizeFrames)]
+    pub async fn quantize_frames(&self, frames: Vec<Uint8Array>) -> Result<js_sys::Array, JsValue> {
+        let frames: Vec<Vec<u8>> = frames.iter().map(Uint8Array::to_vec).collect();
+        let frames: Vec<&[u8]> = frames.iter().map(Vec::as_slice).collect();
+        let images = self.0.quantize_frames(&frames).await?;
+        // An array of IndexedImage, one per frame
+        Ok(images
+            .into_iter()
+            .map(|image| JsValue::from(WasmIndexedImage(image)))
+            .collect())
+    }
+
+    #[wasm_bindgen(js_name = createPalette)]
+    pub async fn create_palette(&self, data: &[u8]) -> Result<WasmPalette, JsValue> {
+        Ok(WasmPalette(self.0.create_palette(data).await?))
+    }
+}
+
+#[wasm_bindgen(js_class = Palette)]
+impl WasmPalette {
+    // Flat RGBA bytes, four per color
+    #[wasm_bindgen(getter)]
+    pub fn colors(&self) -> Uint8Array {
+        Uint8Array::from(self.0.colors.concat().as_slice())
+    }
+
+    // Number of pixels each color stands for
+    #[wasm_bindgen(getter)]
+    pub fn counts(&self) -> Uint32Array {
+        let counts: Vec<u32> = self.0.counts.iter().map(|&c| c as u32).collect();
+        Uint32Array::from(counts.as_slice())
+    }
+}
+
+#[wasm_bindgen(js_class = IndexedImage)]
+impl WasmIndexedImage {
+    // Flat RGBA bytes, four per palette entry
+    #[wasm_bindgen(getter)]
+    pub fn palette(&self) -> Uint8Array {
+        Uint8Array::from(self.0.palette.concat().as_slice())
+    }
+
+    // A Uint8Array for palettes of up to 256 colors, a Uint16Array otherwise
+    #[wasm_bindgen(getter)]
+    pub fn indices(&self) -> JsValue {
+        match &self.0.indices {
+            IndexBuffer::U8(indices) => Uint8Array::from(indices.as_slice()).into(),
+            IndexBuffer::U16(indices) => Uint16Array::from(indices.as_slice()).into(),
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.0.width as u32
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.0.height as u32
+    }
+}
+
+#[wasm_bindgen(js_class = GifEncoder)]
+impl WasmGifEncoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32) -> Self {
+        Self(GifEncoder::new(width as usize, height as usize))
+    }
+
+    // Flat RGBA bytes, four per color
+    #[wasm_bindgen(js_name = withGlobalPalette)]
+    pub fn with_global_palette(self, palette: &[u8]) -> Result<WasmGifEncoder, JsValue> {
+        Ok(Self(self.0.with_global_palette(rgba_colors(palette)?)))
+    }
+
+    // Loops forever when times is left out
+    #[wasm_bindgen(js_name = withRepeat)]
+    pub fn with_repeat(self, times: Option<u16>) -> Self {
+        let repeat = match times {
+            Some(times) => Repeat::Times(times),
+            None => Repeat::Infinite,
+        };
+        Self(self.0.with_repeat(repeat))
+    }
+
+    // Delay is in hundredths of a second
+    #[wasm_bindgen(js_name = addFrame)]
+    pub fn add_frame(
+        &mut self,
+        image: &WasmIndexedImage,
+        delay: u16,
+        disposal: Disposal,
+        left: Option<u32>,
+        top: Option<u32>,
+    ) -> Result<(), JsValue> {
+        let disposal = match disposal.as_str() {
+            "unspecified" => GifDisposal::Unspecified,
+            "keep" => GifDisposal::Keep,
+            "background" => GifDisposal::Background,
+            "previous" => GifDisposal::Previous,
+            _ => return Err(invalid_option("disposal", &disposal)),
+        };
+        let options = FrameOptions {
+            delay,
+            disposal,
+            left: left.unwrap_or(0) as usize,
+            top: top.unwrap_or(0) as usize,
+        };
+        Ok(self.0.add_frame(&image.0, &options)?)
+    }
+
+    #[wasm_bindgen(js_name = finish)]
+    pub fn finish(self) -> Result<Uint8Array, JsValue> {
+        Ok(Uint8Array::from(self.0.finish()?.as_slice()))
+    }
 }
 
 #[cfg(test)]
@@ -120,10 +459,12 @@ mod tests {
             .with_tolerance(0.01)
             .with_max_iterations(100)
             .with_initializer("kmeans++".to_string())
+            .unwrap()
             .with_algorithm("lloyd".to_string())
+            .unwrap()
             .with_seed(42);
 
-        let cruncher = builder.build().await;
+        let cruncher = builder.build().await.unwrap();
         assert!(true); // If we got here, the test passed
     }
 
@@ -132,7 +473,7 @@ mod tests {
         let builder = WasmColorCruncherBuilder::new()
             .with_max_colors(2)
             .with_sample_rate(1);
-        let cruncher = builder.build().await;
+        let cruncher = builder.build().await.unwrap();
 
         let input_data = vec![
             255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255,
@@ -56,9 +56,14 @@ document.addEventListener("DOMContentLoaded", () => {
     reader.readAsDataURL(file);
   }
 
+  // Clustering a few thousand pixels per color is plenty, so big images only
+  // sample that many. Histogram sampling keeps small areas of rare colors.
+  const SAMPLES_PER_COLOR = 4096;
+  const MIN_SAMPLES = 65536;
+
   function getBestSampleRate(totalPixels, maxColors) {
-    // TODO: Implement a better sample rate calculation
-    return 1;
+    const samples = Math.max(MIN_SAMPLES, maxColors * SAMPLES_PER_COLOR);
+    return Math.max(1, Math.floor(totalPixels / samples));
   }
 
   processBtn.addEventListener("click", async () => {
@@ -92,6 +97,7 @@ document.addEventListener("DOMContentLoaded", () => {
           let cruncherBuilder = new ColorCruncherBuilder()
             .withMaxColors(numColors)
             .withSampleRate(sampleRate)
+            .withSampling("histogram")
             .withAlgorithm(selectedAlgorithm)
             .withMaxIterations(maxIter)
             .withTolerance(tol);